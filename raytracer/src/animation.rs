@@ -0,0 +1,267 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::matrix::{Matrix4, Quaternion};
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene_file::{
+    array, DescribeResult, KeyframeDesc, ObjectDesc, RotationDesc, SceneWriter, TransformOp,
+};
+use crate::transform::{corners, transformed_bbox, Frame};
+use crate::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+// Where an animated object is at one time: scaled, then rotated, then translated.
+#[derive(Copy, Clone)]
+pub struct Keyframe {
+    pub time: f64,
+    pub translation: Vec3,
+    pub rotation: Quaternion,
+    pub scale: Vec3,
+}
+
+// Keyframes interpolated at the time of each ray, so that objects blur along their path
+// while the shutter is open. Translation and scale change linearly between keys and the
+// rotation turns along the shorter arc, so a full turn takes at least three keys. Before
+// the first key and after the last the object holds still.
+pub struct Animation {
+    keys: Vec<Keyframe>,
+}
+
+// Steps a rotation between keys is split into when bounding the swept box
+const SWEEP_STEPS: usize = 32;
+
+impl Animation {
+    pub fn new(mut keys: Vec<Keyframe>) -> Result<Self, String> {
+        if keys.is_empty() {
+            return Err(String::from("an animation needs at least one key"));
+        }
+        if keys.iter().any(|key| !key.time.is_finite()) {
+            return Err(String::from("key times must be finite"));
+        }
+        if keys
+            .iter()
+            .any(|key| key.scale.x == 0.0 || key.scale.y == 0.0 || key.scale.z == 0.0)
+        {
+            return Err(String::from("a key scales the object to nothing"));
+        }
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(Self { keys })
+    }
+
+    pub fn matrix(&self, time: f64) -> Matrix4 {
+        let next = self.keys.partition_point(|key| key.time <= time);
+        let key = if next == 0 || next == self.keys.len() {
+            self.keys[next.saturating_sub(1)]
+        } else {
+            let (a, b) = (&self.keys[next - 1], &self.keys[next]);
+            let f = (time - a.time) / (b.time - a.time);
+            Keyframe {
+                time,
+                translation: a.translation * (1.0 - f) + b.translation * f,
+                rotation: a.rotation.slerp(&b.rotation, f),
+                scale: a.scale * (1.0 - f) + b.scale * f,
+            }
+        };
+        Matrix4::translation(&key.translation)
+            * key.rotation.matrix()
+            * Matrix4::scaling(&key.scale)
+    }
+
+    // The frame at `time` of an object first transformed by `setup`. A scale can pass
+    // through zero between keys, where the object is flat and cannot be hit.
+    fn frame(&self, time: f64, setup: &Matrix4) -> Option<Frame> {
+        Frame::new(&(self.matrix(time) * *setup)).ok()
+    }
+
+    // The box around everywhere `bbox` goes, transformed by `setup` and then animated
+    fn swept_bbox(&self, bbox: &Aabb, setup: &Matrix4) -> Aabb {
+        let mut swept = transformed_bbox(bbox, &(self.matrix(self.keys[0].time) * *setup));
+        for pair in self.keys.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            let step_angle = a.rotation.angle_to(&b.rotation) / SWEEP_STEPS as f64;
+            let mut radius: f64 = 0.0;
+            for step in 1..=SWEEP_STEPS {
+                let time = a.time + (b.time - a.time) * step as f64 / SWEEP_STEPS as f64;
+                let animated = self.matrix(time);
+                let matrix = animated * *setup;
+                swept = Aabb::two_aabb(&swept, &transformed_bbox(bbox, &matrix));
+                // Rotations turn about the animation's origin.
+                let center = animated.point(&Point3::new(0.0, 0.0, 0.0));
+                for corner in corners(bbox) {
+                    radius = radius.max((matrix.point(&corner) - center).length());
+                }
+            }
+            // Between two steps a corner travels along an arc, which bulges out of the
+            // straight line between them by up to r (1 - cos(angle / 2)).
+            let bulge = radius * (1.0 - (step_angle.to_radians() / 2.0).cos());
+            swept = Aabb::new(
+                swept.x.expand(2.0 * bulge),
+                swept.y.expand(2.0 * bulge),
+                swept.z.expand(2.0 * bulge),
+            );
+        }
+        swept
+    }
+
+    pub fn describe(&self) -> Vec<KeyframeDesc> {
+        self.keys
+            .iter()
+            .map(|key| {
+                let (axis, angle) = key.rotation.axis_angle();
+                KeyframeDesc {
+                    time: key.time,
+                    translate: array(&key.translation),
+                    rotate: (angle != 0.0).then(|| RotationDesc {
+                        axis: array(&axis),
+                        angle,
+                    }),
+                    scale: array(&key.scale),
+                }
+            })
+            .collect()
+    }
+}
+
+// An object moving through keyframes
+pub struct AnimatedTransform {
+    object: Arc<dyn Hittable>,
+    animation: Animation,
+    bbox: Aabb,
+}
+
+impl AnimatedTransform {
+    pub fn new(object: Arc<dyn Hittable>, animation: Animation) -> Self {
+        let bbox = animation.swept_bbox(&object.bounding_box(), &Matrix4::IDENTITY);
+        Self {
+            object,
+            animation,
+            bbox,
+        }
+    }
+}
+
+impl Hittable for AnimatedTransform {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        self.animation
+            .frame(r.time(), &Matrix4::IDENTITY)?
+            .hit(self.object.as_ref(), r, ray_t)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox.clone()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        self.animation
+            .frame(time, &Matrix4::IDENTITY)
+            .map_or(0.0, |frame| {
+                frame.pdf_value(self.object.as_ref(), origin, direction, time)
+            })
+    }
+
+    fn random(&self, origin: &Point3, time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        match self.animation.frame(time, &Matrix4::IDENTITY) {
+            Some(frame) => frame.random(self.object.as_ref(), origin, time, sampler),
+            None => Vec3::new(1.0, 0.0, 0.0),
+        }
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<ObjectDesc> {
+        Ok(ObjectDesc::Animated {
+            keys: self.animation.describe(),
+            object: Box::new(writer.object(self.object.as_ref())?),
+        })
+    }
+}
+
+// A copy of geometry shared with other instances, placed by its own transform and
+// optionally drawn in another material. Only the transform is stored per copy, so a model
+// can be repeated thousands of times. An animated instance is set up by the transform and
+// then moved by the keys.
+pub struct Instance {
+    geometry: Arc<dyn Hittable>,
+    frame: Frame,
+    animation: Option<Animation>,
+    material: Option<Arc<dyn Material>>,
+    bbox: Aabb,
+}
+
+impl Instance {
+    pub fn new(
+        geometry: Arc<dyn Hittable>,
+        matrix: &Matrix4,
+        animation: Option<Animation>,
+        material: Option<Arc<dyn Material>>,
+    ) -> Result<Self, String> {
+        let frame = Frame::new(matrix)?;
+        let bbox = match &animation {
+            Some(animation) => animation.swept_bbox(&geometry.bounding_box(), matrix),
+            None => transformed_bbox(&geometry.bounding_box(), matrix),
+        };
+        Ok(Self {
+            geometry,
+            frame,
+            animation,
+            material,
+            bbox,
+        })
+    }
+
+    fn frame(&self, time: f64) -> Option<Frame> {
+        match &self.animation {
+            Some(animation) => animation.frame(time, &self.frame.matrix),
+            None => Some(self.frame),
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let mut rec = self
+            .frame(r.time())?
+            .hit(self.geometry.as_ref(), r, ray_t)?;
+        if let Some(material) = &self.material {
+            rec.mat = material.clone();
+        }
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox.clone()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        self.frame(time).map_or(0.0, |frame| {
+            frame.pdf_value(self.geometry.as_ref(), origin, direction, time)
+        })
+    }
+
+    fn random(&self, origin: &Point3, time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        match self.frame(time) {
+            Some(frame) => frame.random(self.geometry.as_ref(), origin, time, sampler),
+            None => Vec3::new(1.0, 0.0, 0.0),
+        }
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<ObjectDesc> {
+        let matrix = self.frame.matrix;
+        Ok(ObjectDesc::Instance {
+            geometry: writer.geometry(&self.geometry)?,
+            transform: if matrix == Matrix4::IDENTITY {
+                vec![]
+            } else {
+                vec![TransformOp::Matrix { rows: matrix.m }]
+            },
+            keys: self
+                .animation
+                .as_ref()
+                .map_or_else(Vec::new, Animation::describe),
+            material: match &self.material {
+                Some(material) => Some(writer.material(material)?),
+                None => None,
+            },
+        })
+    }
+}
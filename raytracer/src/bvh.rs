@@ -3,6 +3,7 @@ use crate::hittable::{HitRecord, Hittable};
 use crate::hittable_list::HittableList;
 use crate::interval::Interval;
 use crate::ray::Ray;
+use crate::scene_file::{cannot_describe, DescribeResult, ObjectDesc, SceneWriter};
 use std::cmp::Ordering;
 use std::cmp::Ordering::{Greater, Less};
 use std::sync::Arc;
@@ -12,6 +13,8 @@ pub struct BvhNode {
     left: Arc<dyn Hittable>,
     right: Arc<dyn Hittable>,
     bbox: Aabb,
+    // The primitives the tree was built from, kept on the root for scene export.
+    objects: Vec<Arc<dyn Hittable>>,
 }
 
 impl BvhNode {
@@ -30,11 +33,17 @@ impl BvhNode {
                 left: objects[start].clone(),
                 right: objects[start].clone(),
                 bbox,
+                objects: vec![],
             }
         } else if object_span == 2 {
             let left = objects[start].clone();
             let right = objects[start + 1].clone();
-            Self { left, right, bbox }
+            Self {
+                left,
+                right,
+                bbox,
+                objects: vec![],
+            }
         } else {
             if axis == 0 {
                 objects[start..end - 1].sort_unstable_by(|a, b| box_x_compare(a, b))
@@ -50,12 +59,16 @@ impl BvhNode {
                 left: Arc::new(left),
                 right: Arc::new(right),
                 bbox,
+                objects: vec![],
             }
         }
     }
     pub fn from_list(list: &mut HittableList) -> Self {
+        let objects = list.objects.clone();
         let len = list.objects.len();
-        Self::new(&mut list.objects, 0, len)
+        let mut root = Self::new(&mut list.objects, 0, len);
+        root.objects = objects;
+        root
     }
 }
 
@@ -101,4 +114,16 @@ impl Hittable for BvhNode {
     fn bounding_box(&self) -> Aabb {
         self.bbox.clone()
     }
+
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<ObjectDesc> {
+        if self.objects.is_empty() {
+            return cannot_describe("BvhNode without its primitive list");
+        }
+        let objects = self
+            .objects
+            .iter()
+            .map(|object| writer.object(object.as_ref()))
+            .collect::<DescribeResult<Vec<_>>>()?;
+        Ok(ObjectDesc::Bvh { objects })
+    }
 }
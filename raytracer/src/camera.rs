@@ -4,10 +4,12 @@ use crate::hittable_list::HittableList;
 use crate::interval::Interval;
 use crate::pdf::{HittablePDF, MixturePDF, Pdf};
 use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerKind};
+use crate::spectrum::Wavelengths;
+use crate::tonemap::ToneMapping;
 use crate::vec3::{cross, random_in_unit_disk, unit_vector, Point3, Vec3};
-use image::RgbImage; // ImageBuffer
 use indicatif::ProgressBar;
-use rand::{thread_rng, Rng};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
@@ -27,8 +29,92 @@ pub struct CameraSettings {
     pub vup: Vec3,
     pub defocus_angle: f64,
     pub focus_dist: f64,
+    pub shutter: Shutter,
 }
 
+// When the shutter is open, on the time scale where moving spheres go from their start at
+// 0 to their end at 1 and keyframes are placed. `curve` is how far it is open at evenly spaced times from `open` to
+// `close`, linear in between, so rays are spread over time in proportion to it; without a
+// curve the shutter opens fully at once.
+#[derive(Clone)]
+pub struct Shutter {
+    pub open: f64,
+    pub close: f64,
+    pub curve: Vec<f64>,
+}
+
+impl Default for Shutter {
+    fn default() -> Self {
+        Self {
+            open: 0.0,
+            close: 1.0,
+            curve: vec![],
+        }
+    }
+}
+
+impl Shutter {
+    pub fn new(open: f64, close: f64, curve: Vec<f64>) -> Result<Self, String> {
+        if !(open.is_finite() && close.is_finite()) {
+            return Err(String::from("the shutter times must be finite"));
+        }
+        if close < open {
+            return Err(String::from("the shutter must open before it closes"));
+        }
+        if curve.iter().any(|&c| c < 0.0 || !c.is_finite())
+            || (!curve.is_empty() && curve.iter().all(|&c| c == 0.0))
+        {
+            return Err(String::from(
+                "the shutter curve must be positive somewhere and never negative",
+            ));
+        }
+        Ok(Self { open, close, curve })
+    }
+
+    // The time of a ray, from a uniform random number `s`
+    fn sample(&self, s: f64) -> f64 {
+        self.open + (self.close - self.open) * self.fraction(s)
+    }
+
+    // Invert the integral of the piecewise linear curve.
+    fn fraction(&self, s: f64) -> f64 {
+        if self.curve.len() < 2 {
+            return s;
+        }
+        let segments = self.curve.len() - 1;
+        let areas: Vec<f64> = self.curve.windows(2).map(|c| (c[0] + c[1]) / 2.0).collect();
+        let mut remaining = s * areas.iter().sum::<f64>();
+        for (i, &area) in areas.iter().enumerate() {
+            if remaining > area && i + 1 < segments {
+                remaining -= area;
+                continue;
+            }
+            // Solve c0 x + (c1 - c0) x² / 2 = remaining for x within the segment.
+            let (c0, slope) = (self.curve[i], self.curve[i + 1] - self.curve[i]);
+            let x = if slope.abs() >= 1e-12 {
+                ((c0 * c0 + 2.0 * slope * remaining).max(0.0).sqrt() - c0) / slope
+            } else if c0 > 0.0 {
+                remaining / c0
+            } else {
+                0.0
+            };
+            return (i as f64 + x.clamp(0.0, 1.0)) / segments as f64;
+        }
+        s
+    }
+}
+
+// Stop sampling a pixel once the relative standard error of its luminance drops below
+// `threshold`, but never before it has `min_samples` samples.
+#[derive(Copy, Clone)]
+pub struct Adaptive {
+    pub threshold: f64,
+    pub min_samples: u32,
+}
+
+// Luminance below which the error is measured in absolute rather than relative terms
+const MIN_ERROR_LUMINANCE: f64 = 0.01;
+
 #[derive(Clone)]
 pub struct Camera {
     // image
@@ -37,12 +123,26 @@ pub struct Camera {
     pub image_height: u32,
     pub quality: u8,
     pub samples_per_pixel: u32,
-    pub pixel_samples_scale: f64,
-    pub sqrt_spp: u32,
-    pub recip_sqrt_spp: f64,
     pub max_depth: i32,
     pub background: Color,
-    pub img: RgbImage,
+    // Linear radiance, row by row from the top left
+    pub framebuffer: Vec<Color>,
+    // Running sums of the radiance samples, of their squared luminance, and how many were
+    // taken, per pixel
+    pub accumulator: Vec<Color>,
+    pub luminance_squares: Vec<f64>,
+    pub sample_counts: Vec<u32>,
+    // Samples added to every pixel by each progressive pass, and the passes done so far
+    pub pass_samples: u32,
+    pub passes: u32,
+    // Render
+    pub threads: usize,
+    pub seed: u64,
+    pub tone_mapping: ToneMapping,
+    pub sampler: SamplerKind,
+    pub adaptive: Option<Adaptive>,
+    // Trace wavelengths instead of RGB
+    pub spectral: bool,
     // Camera
     pub camera_center: Point3,
     pub vfov: f64,
@@ -53,6 +153,7 @@ pub struct Camera {
     pub h: f64,
     pub defocus_angle: f64,
     pub focus_dist: f64,
+    pub shutter: Shutter,
     // Viewport
     pub viewport_height: f64,
     pub viewport_width: f64,
@@ -84,6 +185,7 @@ impl Camera {
             vup,
             defocus_angle,
             focus_dist,
+            shutter,
         } = camera_settings;
         let mut image_height: u32 = (image_width as f64 / aspect_ratio) as u32;
         if image_height == 0 {
@@ -92,9 +194,6 @@ impl Camera {
         let camera_center: Point3 = look_from;
         let theta: f64 = vfov * std::f64::consts::PI / 180.0;
         let h: f64 = f64::tan(theta / 2.0);
-        let sqrt_spp = (samples_per_pixel as f64).sqrt() as u32;
-        let pixel_samples_scale: f64 = 1.0 / (sqrt_spp * sqrt_spp) as f64;
-        let recip_sqrt_spp = 1.0 / sqrt_spp as f64;
         let viewport_height: f64 = 2.0 * h * focus_dist;
         let viewport_width: f64 = viewport_height * (image_width as f64 / image_height as f64);
         // edge vector
@@ -121,12 +220,20 @@ impl Camera {
             image_height,
             quality,
             samples_per_pixel,
-            pixel_samples_scale,
-            sqrt_spp,
-            recip_sqrt_spp,
             max_depth,
             background,
-            img: RgbImage::new(image_width, image_height),
+            framebuffer: vec![Color::black(); (image_width * image_height) as usize],
+            accumulator: vec![Color::black(); (image_width * image_height) as usize],
+            luminance_squares: vec![0.0; (image_width * image_height) as usize],
+            sample_counts: vec![0; (image_width * image_height) as usize],
+            pass_samples: samples_per_pixel,
+            passes: 0,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            seed: 0,
+            tone_mapping: ToneMapping::default(),
+            sampler: SamplerKind::Sobol,
+            adaptive: None,
+            spectral: false,
             camera_center,
             look_from,
             look_at,
@@ -136,6 +243,7 @@ impl Camera {
             h,
             defocus_angle,
             focus_dist,
+            shutter,
             viewport_height,
             viewport_width,
             viewport_u,
@@ -149,72 +257,232 @@ impl Camera {
         }
     }
 
-    pub fn render(&mut self, world: HittableList, lights: Arc<dyn Hittable>) {
+    // Render in passes until every pixel has `samples_per_pixel` samples, or with adaptive
+    // sampling until it has converged, continuing from whatever is already accumulated.
+    // `after_pass` runs once each pass has been merged.
+    pub fn render(
+        &mut self,
+        world: HittableList,
+        lights: HittableList,
+        mut after_pass: impl FnMut(&Camera),
+    ) {
+        let remaining: u64 = self
+            .sample_counts
+            .iter()
+            .map(|&count| self.samples_per_pixel.saturating_sub(count) as u64)
+            .sum();
         let progress = if option_env!("CI").unwrap_or_default() == "true" {
             ProgressBar::hidden()
         } else {
-            ProgressBar::new((self.image_height * self.image_width) as u64)
+            ProgressBar::new(remaining)
         };
-        let img = Arc::new(Mutex::new(self.img.clone()));
-        let progress = Arc::new(Mutex::new(progress));
+        // Scenes without lights fall back to sampling the material alone.
+        let sample_lights = !lights.objects.is_empty();
+        let lights: Arc<dyn Hittable> = Arc::new(lights);
 
-        let mut rend_lines = vec![];
+        loop {
+            let budget = self.pass_budget();
+            if budget.iter().all(|&samples| samples == 0) {
+                break;
+            }
+            self.render_pass(&world, &lights, sample_lights, &budget, &progress);
+            self.passes += 1;
+            after_pass(self);
+        }
+        progress.finish();
+    }
 
-        let image_height = self.image_height;
+    // How many samples each pixel gets in the next pass.
+    fn pass_budget(&self) -> Vec<u32> {
+        let pass_samples = self.pass_samples.max(1);
+        (0..self.sample_counts.len())
+            .map(|k| {
+                let count = self.sample_counts[k];
+                let converged = match &self.adaptive {
+                    Some(adaptive) => {
+                        count >= adaptive.min_samples && self.relative_error(k) < adaptive.threshold
+                    }
+                    None => false,
+                };
+                if converged {
+                    0
+                } else {
+                    pass_samples.min(self.samples_per_pixel.saturating_sub(count))
+                }
+            })
+            .collect()
+    }
+
+    // Standard error of the mean luminance of pixel `k`, relative to that mean.
+    pub fn relative_error(&self, k: usize) -> f64 {
+        let n = self.sample_counts[k] as f64;
+        if n < 2.0 {
+            return f64::INFINITY;
+        }
+        let mean = self.accumulator[k].luminance() / n;
+        let variance = (self.luminance_squares[k] / n - mean * mean).max(0.0) * n / (n - 1.0);
+        // Keep near-black pixels from needing an absurdly small absolute error.
+        (variance / n).sqrt() / mean.max(MIN_ERROR_LUMINANCE)
+    }
+
+    fn render_pass(
+        &mut self,
+        world: &HittableList,
+        lights: &Arc<dyn Hittable>,
+        sample_lights: bool,
+        budget: &[u32],
+        progress: &ProgressBar,
+    ) {
+        let tiles = Tile::split(self.image_width, self.image_height);
+        let next_tile = AtomicUsize::new(0);
+        let sums = Mutex::new((
+            std::mem::take(&mut self.accumulator),
+            std::mem::take(&mut self.luminance_squares),
+        ));
+        let sensor = Sensor::new(self, sample_lights);
         let image_width = self.image_width;
+        let sample_counts = &self.sample_counts;
+        let render_seed = self.seed;
+        let sampler_kind = self.sampler;
+        let samples_per_pixel = self.samples_per_pixel;
 
-        for n in 0..20 {
-            let progress = Arc::clone(&progress);
-            let img = Arc::clone(&img);
-            let world = world.clone();
-            let lights = lights.clone();
-            let copy = Sensor::new(self);
-            let rend_line = thread::spawn(move || {
-                for j in (0..image_height).rev() {
-                    for i in 0..image_width {
-                        if (i + j) % 20 != n {
-                            continue;
-                        }
+        // Threads pull the next unrendered tile until none are left, so a thread stuck in an
+        // expensive region simply ends up taking fewer tiles.
+        thread::scope(|scope| {
+            for _ in 0..self.threads.max(1) {
+                scope.spawn(|| {
+                    let mut buffer = Vec::with_capacity((TILE_SIZE * TILE_SIZE) as usize);
+                    let mut sampler = sampler_kind.build(render_seed, samples_per_pixel);
+                    loop {
+                        let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                        let Some(tile) = tiles.get(index) else {
+                            break;
+                        };
 
-                        let mut pixel_color: Color = Color::new(0.0, 0.0, 0.0);
+                        buffer.clear();
+                        let mut tile_samples = 0;
+                        for j in tile.y0..tile.y1 {
+                            for i in tile.x0..tile.x1 {
+                                let k = (j * image_width + i) as usize;
+                                let mut pixel_color = Color::black();
+                                let mut squares = 0.0;
+                                for s in 0..budget[k] {
+                                    // Samples are numbered across passes, so resuming never
+                                    // repeats random numbers.
+                                    let index = sample_counts[k] + s;
+                                    sampler.start_pixel_sample(i, j, index);
+                                    let r = sensor.get_ray(i, j, sampler.as_mut());
+                                    let wavelengths = sensor
+                                        .spectral
+                                        .then(|| Wavelengths::sample(sampler.get_1d()));
+                                    let radiance = sensor.ray_color(
+                                        &r,
+                                        sensor.max_depth,
+                                        world,
+                                        lights.clone(),
+                                        sampler.as_mut(),
+                                        wavelengths,
+                                    );
+                                    let sample = match wavelengths {
+                                        Some(wavelengths) => wavelengths.to_rgb(&radiance),
+                                        None => radiance,
+                                    }
+                                    .finite();
+                                    pixel_color += sample;
+                                    squares += sample.luminance().powi(2);
+                                }
+                                buffer.push((pixel_color, squares));
+                                tile_samples += budget[k] as u64;
+                            }
+                        }
 
-                        for s_j in 0..copy.sqrt_spp {
-                            for s_i in 0..copy.sqrt_spp {
-                                let r = copy.get_ray(i, j, s_i, s_j);
-                                pixel_color +=
-                                    copy.ray_color(&r, copy.max_depth, &world, lights.clone());
+                        let mut sums = sums.lock().unwrap();
+                        let (accumulator, luminance_squares) = &mut *sums;
+                        let mut results = buffer.iter();
+                        for j in tile.y0..tile.y1 {
+                            for i in tile.x0..tile.x1 {
+                                let k = (j * image_width + i) as usize;
+                                let (color, squares) = results.next().unwrap();
+                                accumulator[k] += *color;
+                                luminance_squares[k] += squares;
                             }
                         }
-                        pixel_color *= copy.pixel_samples_scale;
+                        drop(sums);
+                        progress.inc(tile_samples);
+                    }
+                });
+            }
+        });
 
-                        let mut img = img.lock().unwrap();
-                        let pixel = img.get_pixel_mut(i, j);
-                        *pixel = pixel_color.write_color();
-                        drop(img);
+        (self.accumulator, self.luminance_squares) = sums.into_inner().unwrap();
+        for (k, count) in self.sample_counts.iter_mut().enumerate() {
+            *count += budget[k];
+            if *count > 0 {
+                self.framebuffer[k] = self.accumulator[k] / *count as f64;
+            }
+        }
+    }
 
-                        let progress = progress.lock().unwrap();
-                        progress.inc(1);
-                        drop(progress);
-                    }
-                }
-            });
-            rend_lines.push(rend_line);
+    // Continue from previously accumulated samples, e.g. a checkpoint.
+    pub fn restore(
+        &mut self,
+        accumulator: Vec<Color>,
+        luminance_squares: Vec<f64>,
+        sample_counts: Vec<u32>,
+        passes: u32,
+    ) -> Result<(), String> {
+        let pixels = (self.image_width * self.image_height) as usize;
+        if accumulator.len() != pixels
+            || luminance_squares.len() != pixels
+            || sample_counts.len() != pixels
+        {
+            return Err(format!(
+                "expected {} pixels for a {}x{} image",
+                pixels, self.image_width, self.image_height
+            ));
+        }
+        for (k, &count) in sample_counts.iter().enumerate() {
+            if count > 0 {
+                self.framebuffer[k] = accumulator[k] / count as f64;
+            }
         }
+        self.accumulator = accumulator;
+        self.luminance_squares = luminance_squares;
+        self.sample_counts = sample_counts;
+        self.passes = passes;
+        Ok(())
+    }
+}
 
-        for rend_line in rend_lines {
-            rend_line.join().unwrap();
+// Square blocks of pixels handed out to the render threads
+const TILE_SIZE: u32 = 16;
+
+struct Tile {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+}
+
+impl Tile {
+    fn split(image_width: u32, image_height: u32) -> Vec<Tile> {
+        let mut tiles = vec![];
+        for y0 in (0..image_height).step_by(TILE_SIZE as usize) {
+            for x0 in (0..image_width).step_by(TILE_SIZE as usize) {
+                tiles.push(Tile {
+                    x0,
+                    y0,
+                    x1: (x0 + TILE_SIZE).min(image_width),
+                    y1: (y0 + TILE_SIZE).min(image_height),
+                });
+            }
         }
-        progress.lock().unwrap().finish();
-        let img = Some(Arc::try_unwrap(img).unwrap().into_inner().unwrap());
-        self.img = img.as_ref().unwrap().clone();
+        tiles
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 struct Sensor {
-    pub pixel_samples_scale: f64,
-    pub sqrt_spp: u32,
-    pub recip_sqrt_spp: f64,
     pub max_depth: i32,
     pub background: Color,
     pub pixel100_loc: Point3,
@@ -224,14 +492,14 @@ struct Sensor {
     pub defocus_angle: f64,
     pub defocus_disk_u: Vec3,
     pub defocus_disk_v: Vec3,
+    pub shutter: Shutter,
+    pub sample_lights: bool,
+    pub spectral: bool,
 }
 
 impl Sensor {
-    pub fn new(camera: &Camera) -> Self {
+    pub fn new(camera: &Camera, sample_lights: bool) -> Self {
         Self {
-            pixel_samples_scale: camera.pixel_samples_scale,
-            sqrt_spp: camera.sqrt_spp,
-            recip_sqrt_spp: camera.recip_sqrt_spp,
             max_depth: camera.max_depth,
             background: camera.background,
             pixel100_loc: camera.pixel100_loc,
@@ -241,66 +509,109 @@ impl Sensor {
             defocus_angle: camera.defocus_angle,
             defocus_disk_u: camera.defocus_disk_u,
             defocus_disk_v: camera.defocus_disk_v,
+            shutter: camera.shutter.clone(),
+            sample_lights,
+            spectral: camera.spectral,
         }
     }
-    fn get_ray(&self, i: u32, j: u32, s_i: u32, s_j: u32) -> Ray {
-        let offset = self.sample_square_stratified(s_i, s_j);
+    fn get_ray(&self, i: u32, j: u32, sampler: &mut dyn Sampler) -> Ray {
+        let offset = sample_square(sampler);
         let pixel_sample = self.pixel100_loc
             + (self.pixel_delta_u * (i as f64 + offset.x))
             + (self.pixel_delta_v * (j as f64 + offset.y));
         let ray_origin = if self.defocus_angle <= 0.0 {
             self.camera_center
         } else {
-            self.defocus_disk_sample()
+            self.defocus_disk_sample(sampler)
         };
         let ray_direction = pixel_sample - ray_origin;
-        let ray_time = thread_rng().gen_range(0.0..1.0);
+        let ray_time = self.shutter.sample(sampler.get_1d());
 
         Ray::new(&ray_origin, &ray_direction, ray_time)
     }
-    fn defocus_disk_sample(&self) -> Point3 {
-        let p = random_in_unit_disk();
+    fn defocus_disk_sample(&self, sampler: &mut dyn Sampler) -> Point3 {
+        let p = random_in_unit_disk(sampler);
         self.camera_center + (self.defocus_disk_u * p.x) + (self.defocus_disk_v * p.y)
     }
-    fn sample_square_stratified(&self, s_i: u32, s_j: u32) -> Vec3 {
-        let px = (s_i as f64 + thread_rng().gen_range(0.0..1.0)) * self.recip_sqrt_spp - 0.5;
-        let py = (s_j as f64 + thread_rng().gen_range(0.0..1.0)) * self.recip_sqrt_spp - 0.5;
-
-        Vec3::new(px, py, 0.0)
-    }
+    // Radiance arriving along `r`: RGB, or the values at `wavelengths` when spectral.
     fn ray_color(
         &self,
         r: &Ray,
         depth: i32,
         world: &dyn Hittable,
         lights: Arc<dyn Hittable>,
+        sampler: &mut dyn Sampler,
+        mut wavelengths: Option<Wavelengths>,
     ) -> Color {
+        // Materials work in RGB, and their response at each bounce is uplifted to a spectrum.
+        let spectrum = |rgb: Color, wavelengths: &Option<Wavelengths>| match wavelengths {
+            Some(wavelengths) => wavelengths.uplift(&rgb),
+            None => rgb,
+        };
         if depth <= 0 {
             return Color::black();
         }
+        sampler.start_bounce((self.max_depth - depth) as u32);
+        let r = &r.with_seed(sampler.get_1d().to_bits());
 
         if let Some(hit_record) = world.hit(r, Interval::new(0.001, f64::INFINITY)) {
-            let color_from_emission =
+            let color_from_emission = spectrum(
                 hit_record
                     .mat
-                    .emitted(r, &hit_record, hit_record.u, hit_record.v, &hit_record.p);
-            return if let Some(srec) = hit_record.mat.scatter(r, &hit_record) {
+                    .emitted(r, &hit_record, hit_record.u, hit_record.v, &hit_record.p),
+                &wavelengths,
+            );
+            // Only the hero wavelength can follow a direction that depends on wavelength.
+            let mut termination = Color::white();
+            if let Some(wavelengths) = &mut wavelengths {
+                if hit_record.mat.dispersive() && !wavelengths.is_single() {
+                    termination = wavelengths.terminate_secondary();
+                }
+            }
+            let spectral_ray;
+            let r = match wavelengths {
+                Some(wavelengths) => {
+                    spectral_ray = r.with_wavelength(wavelengths.hero());
+                    &spectral_ray
+                }
+                None => r,
+            };
+            return if let Some(srec) = hit_record.mat.scatter(r, &hit_record, sampler) {
                 if srec.skip_pdf {
                     if let Some(scattered) = srec.skip_pdf_ray {
-                        return srec.attenuation
-                            * self.ray_color(&scattered, depth - 1, world, lights);
+                        return termination
+                            * spectrum(srec.attenuation, &wavelengths)
+                            * self.ray_color(
+                                &scattered,
+                                depth - 1,
+                                world,
+                                lights,
+                                sampler,
+                                wavelengths,
+                            );
                     }
                 }
                 if let Some(pdf_ptr) = srec.pdf_ptr {
-                    let light_ptr = Arc::new(HittablePDF::new(lights.clone(), &hit_record.p));
-                    let p = MixturePDF::new(light_ptr, pdf_ptr);
+                    let p: Arc<dyn Pdf> = if self.sample_lights {
+                        let light_ptr =
+                            Arc::new(HittablePDF::new(lights.clone(), &hit_record.p, r.time()));
+                        Arc::new(MixturePDF::new(light_ptr, pdf_ptr))
+                    } else {
+                        pdf_ptr
+                    };
 
-                    let scattered = Ray::new(&hit_record.p, &p.generate(), r.time());
+                    let scattered = Ray::new(&hit_record.p, &p.generate(sampler), r.time());
                     let pdf_val = p.value(&scattered.direction());
-                    let scattering_pdf = hit_record.mat.scattering_pdf(r, &hit_record, &scattered);
+                    if pdf_val <= 0.0 {
+                        return color_from_emission;
+                    }
+                    let bsdf = hit_record.mat.eval(r, &hit_record, &scattered);
 
-                    let sample_color = self.ray_color(&scattered, depth - 1, world, lights);
-                    srec.attenuation * scattering_pdf * sample_color / pdf_val + color_from_emission
+                    let sample_color =
+                        self.ray_color(&scattered, depth - 1, world, lights, sampler, wavelengths);
+                    termination * spectrum(srec.attenuation * bsdf, &wavelengths) * sample_color
+                        / pdf_val
+                        + color_from_emission
                 } else {
                     color_from_emission
                 }
@@ -308,11 +619,11 @@ impl Sensor {
                 color_from_emission
             };
         }
-        self.background
+        spectrum(self.background, &wavelengths)
     }
 }
 
-fn _sample_square() -> Vec3 {
-    let mut rng = thread_rng();
-    Vec3::new(rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5), 0.0)
+fn sample_square(sampler: &mut dyn Sampler) -> Vec3 {
+    let (u, v) = sampler.get_2d();
+    Vec3::new(u - 0.5, v - 0.5, 0.0)
 }
@@ -0,0 +1,234 @@
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::sampler::{hash, SamplerKind};
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"RTCKPT03";
+
+// Bytes stored per pixel: the radiance sum, the squared luminance sum and the count
+const PIXEL_SIZE: usize = 4 * 8 + 4;
+
+// The samplers in the order of their codes in a checkpoint
+const SAMPLERS: [SamplerKind; 5] = [
+    SamplerKind::Independent,
+    SamplerKind::Stratified,
+    SamplerKind::Halton,
+    SamplerKind::Sobol,
+    SamplerKind::BlueNoise,
+];
+
+// Everything needed to pick a progressive render back up: the accumulated radiance sums,
+// squared luminance sums and sample counts of every pixel, plus enough about the render to refuse a mismatched resume.
+pub struct Checkpoint {
+    pub scene: String,
+    // Hash of the scene file's contents, so an edited scene is not resumed
+    pub scene_hash: u64,
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    pub spectral: bool,
+    pub max_depth: i32,
+    pub image_width: u32,
+    pub image_height: u32,
+    pub passes: u32,
+    pub accumulator: Vec<Color>,
+    pub luminance_squares: Vec<f64>,
+    pub sample_counts: Vec<u32>,
+}
+
+impl Checkpoint {
+    pub fn samples(&self) -> u32 {
+        self.sample_counts.iter().copied().max().unwrap_or(0)
+    }
+
+    // Whether the checkpoint was rendered from this scene as it is now.
+    pub fn check_scene(&self, scene: &str) -> Result<(), String> {
+        if self.scene != scene {
+            return Err(format!(
+                "checkpoint was rendered from scene \"{}\"",
+                self.scene
+            ));
+        }
+        if self.scene_hash != scene_hash(scene) {
+            return Err(format!(
+                "scene \"{}\" has changed since the checkpoint was saved",
+                scene
+            ));
+        }
+        Ok(())
+    }
+
+    // Hand the accumulated samples over to a camera set up for the same scene.
+    pub fn restore(self, camera: &mut Camera) -> Result<(), String> {
+        if camera.image_width != self.image_width || camera.image_height != self.image_height {
+            return Err(format!(
+                "checkpoint is {}x{} but the image is {}x{}",
+                self.image_width, self.image_height, camera.image_width, camera.image_height
+            ));
+        }
+        if camera.sampler != self.sampler {
+            return Err(format!(
+                "checkpoint used the {} sampler but the render uses {}",
+                sampler_name(self.sampler),
+                sampler_name(camera.sampler)
+            ));
+        }
+        if camera.spectral != self.spectral {
+            let mode = |spectral| if spectral { "spectral" } else { "RGB" };
+            return Err(format!(
+                "checkpoint was rendered in {} mode but the render is in {} mode",
+                mode(self.spectral),
+                mode(camera.spectral)
+            ));
+        }
+        if camera.max_depth != self.max_depth {
+            return Err(format!(
+                "checkpoint has a max depth of {} but the render has {}",
+                self.max_depth, camera.max_depth
+            ));
+        }
+        camera.seed = self.seed;
+        camera.restore(
+            self.accumulator,
+            self.luminance_squares,
+            self.sample_counts,
+            self.passes,
+        )
+    }
+}
+
+// Write the camera's accumulation buffer. The file is replaced atomically, so a crash while
+// saving leaves the previous checkpoint intact.
+pub fn save(path: &Path, scene: &str, camera: &Camera) -> Result<(), String> {
+    let partial = path.with_extension("partial");
+    let write = || -> std::io::Result<()> {
+        let mut out = BufWriter::new(File::create(&partial)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&(scene.len() as u32).to_le_bytes())?;
+        out.write_all(scene.as_bytes())?;
+        out.write_all(&scene_hash(scene).to_le_bytes())?;
+        out.write_all(&camera.seed.to_le_bytes())?;
+        let sampler = SAMPLERS.iter().position(|&s| s == camera.sampler).unwrap();
+        out.write_all(&[sampler as u8, camera.spectral as u8])?;
+        out.write_all(&camera.max_depth.to_le_bytes())?;
+        out.write_all(&camera.image_width.to_le_bytes())?;
+        out.write_all(&camera.image_height.to_le_bytes())?;
+        out.write_all(&camera.passes.to_le_bytes())?;
+        for k in 0..camera.sample_counts.len() {
+            let sum = camera.accumulator[k];
+            for v in [sum.x, sum.y, sum.z, camera.luminance_squares[k]] {
+                out.write_all(&v.to_le_bytes())?;
+            }
+            out.write_all(&camera.sample_counts[k].to_le_bytes())?;
+        }
+        out.flush()
+    };
+    write()
+        .and_then(|_| fs::rename(&partial, path))
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+pub fn load(path: &Path) -> Result<Checkpoint, String> {
+    let error = |message: String| format!("{}: {}", path.display(), message);
+    let file = File::open(path).map_err(|err| error(err.to_string()))?;
+    let mut input = BufReader::new(file);
+
+    let read = |input: &mut BufReader<File>| -> std::io::Result<Checkpoint> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a render checkpoint"));
+        }
+        let mut scene = vec![0; read_u32(input)? as usize];
+        input.read_exact(&mut scene)?;
+        let scene = String::from_utf8_lossy(&scene).into_owned();
+        let scene_hash = read_u64(input)?;
+        let seed = read_u64(input)?;
+        let mut flags = [0; 2];
+        input.read_exact(&mut flags)?;
+        let sampler = *SAMPLERS
+            .get(flags[0] as usize)
+            .ok_or_else(|| invalid("unknown sampler"))?;
+        let spectral = flags[1] != 0;
+        let max_depth = read_u32(input)? as i32;
+        let image_width = read_u32(input)?;
+        let image_height = read_u32(input)?;
+        let passes = read_u32(input)?;
+
+        // The pixels are read whole, which the file's length bounds, and only then checked
+        // against the size the header claims.
+        let mut data = vec![];
+        input.read_to_end(&mut data)?;
+        let pixels = image_width as usize * image_height as usize;
+        if pixels.checked_mul(PIXEL_SIZE) != Some(data.len()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "pixel data does not match the image size",
+            ));
+        }
+        let mut accumulator = Vec::with_capacity(pixels);
+        let mut luminance_squares = Vec::with_capacity(pixels);
+        let mut sample_counts = Vec::with_capacity(pixels);
+        for pixel in data.chunks(PIXEL_SIZE) {
+            let f = |i: usize| f64::from_le_bytes(pixel[8 * i..8 * i + 8].try_into().unwrap());
+            accumulator.push(Color::new(f(0), f(1), f(2)));
+            luminance_squares.push(f(3));
+            sample_counts.push(u32::from_le_bytes(pixel[32..36].try_into().unwrap()));
+        }
+        Ok(Checkpoint {
+            scene,
+            scene_hash,
+            seed,
+            sampler,
+            spectral,
+            max_depth,
+            image_width,
+            image_height,
+            passes,
+            accumulator,
+            luminance_squares,
+            sample_counts,
+        })
+    };
+    read(&mut input).map_err(|err| match err.kind() {
+        std::io::ErrorKind::UnexpectedEof => error(String::from("checkpoint is truncated")),
+        _ => error(err.to_string()),
+    })
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+fn sampler_name(sampler: SamplerKind) -> String {
+    format!("{:?}", sampler).to_lowercase()
+}
+
+// Hash of the scene file's contents, or of nothing for the built-in scenes
+fn scene_hash(scene: &str) -> u64 {
+    let bytes = fs::read(scene).unwrap_or_default();
+    let words: Vec<u64> = bytes
+        .chunks(8)
+        .map(|chunk| {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u64::from_le_bytes(word)
+        })
+        .chain([bytes.len() as u64])
+        .collect();
+    hash(&words)
+}
+
+fn read_u32(input: &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(input: &mut impl Read) -> std::io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
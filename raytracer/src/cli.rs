@@ -0,0 +1,330 @@
+use crate::camera::ImageSettings;
+use crate::gltf::is_gltf_file;
+use crate::output::{ImageFormat, EXTENSIONS};
+use crate::sampler::SamplerKind;
+use crate::scene_file::is_scene_file;
+use crate::scenes::BUILTIN;
+use crate::tonemap::ToneMapping;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const DEFAULT_SCENE: &str = "cornell_box";
+// Samples per progressive pass when checkpointing without an explicit `--pass-spp`
+pub const DEFAULT_PASS_SAMPLES: u32 = 16;
+// Samples every pixel takes before adaptive sampling may consider it converged
+pub const DEFAULT_MIN_SAMPLES: u32 = 16;
+
+pub struct Args {
+    pub scene: String,
+    pub output: Option<String>,
+    pub export: Option<String>,
+    pub image_width: Option<u32>,
+    pub aspect_ratio: Option<f64>,
+    pub samples_per_pixel: Option<u32>,
+    pub max_depth: Option<i32>,
+    pub threads: Option<usize>,
+    pub seed: Option<u64>,
+    pub pass_samples: Option<u32>,
+    pub checkpoint: Option<String>,
+    pub resume: Option<String>,
+    pub tone_mapping: ToneMapping,
+    pub sampler: Option<SamplerKind>,
+    pub adaptive: Option<f64>,
+    pub min_samples: Option<u32>,
+    pub heat_map: Option<String>,
+    pub spectral: bool,
+    // First and last frame of a sequence to render
+    pub frames: Option<(u32, u32)>,
+}
+
+pub enum Command {
+    Render(Box<Args>),
+    List,
+    Help,
+}
+
+impl Args {
+    fn new() -> Self {
+        Self {
+            scene: String::from(DEFAULT_SCENE),
+            output: None,
+            export: None,
+            image_width: None,
+            aspect_ratio: None,
+            samples_per_pixel: None,
+            max_depth: None,
+            threads: None,
+            seed: None,
+            pass_samples: None,
+            checkpoint: None,
+            resume: None,
+            tone_mapping: ToneMapping::default(),
+            sampler: None,
+            adaptive: None,
+            min_samples: None,
+            heat_map: None,
+            spectral: false,
+            frames: None,
+        }
+    }
+
+    // Override the scene's image settings with whatever was given on the command line.
+    pub fn apply(&self, image_settings: &mut ImageSettings) {
+        if let Some(image_width) = self.image_width {
+            image_settings.image_width = image_width;
+        }
+        if let Some(aspect_ratio) = self.aspect_ratio {
+            image_settings.aspect_ratio = aspect_ratio;
+        }
+        if let Some(samples_per_pixel) = self.samples_per_pixel {
+            image_settings.samples_per_pixel = samples_per_pixel;
+        }
+        if let Some(max_depth) = self.max_depth {
+            image_settings.max_depth = max_depth;
+        }
+    }
+}
+
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut parsed = Args::new();
+    let mut scene: Option<String> = None;
+
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.into())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |name: &str| -> Result<String, String> {
+            match inline_value.clone() {
+                Some(value) => Ok(value),
+                None => args
+                    .next()
+                    .ok_or_else(|| format!("missing value for `{}`", name)),
+            }
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-l" | "--list" => return Ok(Command::List),
+            "-o" | "--output" => {
+                let output = value(&flag)?;
+                if ImageFormat::from_path(Path::new(&output)).is_none() {
+                    return Err(format!("`{}` must end in {}", output, EXTENSIONS));
+                }
+                parsed.output = Some(output);
+            }
+            "-e" | "--export" => {
+                let export = value(&flag)?;
+                if !is_scene_file(&export) {
+                    return Err(format!(
+                        "`{}` must end in .json, .yaml or .yml to be exported",
+                        export
+                    ));
+                }
+                parsed.export = Some(export);
+            }
+            "-w" | "--width" => parsed.image_width = Some(parse_number(&flag, &value(&flag)?)?),
+            "-a" | "--aspect-ratio" => {
+                parsed.aspect_ratio = Some(parse_aspect_ratio(&value(&flag)?)?)
+            }
+            "-s" | "--spp" | "--samples-per-pixel" => {
+                parsed.samples_per_pixel = Some(parse_number(&flag, &value(&flag)?)?)
+            }
+            "-d" | "--max-depth" => parsed.max_depth = Some(parse_number(&flag, &value(&flag)?)?),
+            "-j" | "--threads" => parsed.threads = Some(parse_number(&flag, &value(&flag)?)?),
+            "--seed" => parsed.seed = Some(parse_number(&flag, &value(&flag)?)?),
+            "-p" | "--pass-spp" => parsed.pass_samples = Some(parse_number(&flag, &value(&flag)?)?),
+            "-c" | "--checkpoint" => parsed.checkpoint = Some(value(&flag)?),
+            "-r" | "--resume" => parsed.resume = Some(value(&flag)?),
+            "--adaptive" => parsed.adaptive = Some(parse_number(&flag, &value(&flag)?)?),
+            "--min-spp" => parsed.min_samples = Some(parse_number(&flag, &value(&flag)?)?),
+            "--heat-map" => {
+                let heat_map = value(&flag)?;
+                if ImageFormat::from_path(Path::new(&heat_map)).is_none() {
+                    return Err(format!("`{}` must end in {}", heat_map, EXTENSIONS));
+                }
+                parsed.heat_map = Some(heat_map);
+            }
+            "--spectral" => parsed.spectral = true,
+            "--frames" => parsed.frames = Some(parse_frames(&value(&flag)?)?),
+            "--sampler" => parsed.sampler = Some(value(&flag)?.parse()?),
+            "-t" | "--tonemap" => parsed.tone_mapping.operator = value(&flag)?.parse()?,
+            "--transfer" => parsed.tone_mapping.transfer = value(&flag)?.parse()?,
+            "-x" | "--exposure" => {
+                parsed.tone_mapping.exposure = parse_number(&flag, &value(&flag)?)?
+            }
+            _ if flag.starts_with('-') => return Err(format!("unknown option `{}`", flag)),
+            _ => {
+                if scene.is_some() {
+                    return Err(format!("unexpected argument `{}`", arg));
+                }
+                scene = Some(arg);
+            }
+        }
+    }
+
+    if let Some(scene) = scene {
+        if !BUILTIN.iter().any(|(name, _)| *name == scene)
+            && !is_scene_file(&scene)
+            && !is_gltf_file(&scene)
+        {
+            return Err(format!(
+                "unknown scene `{}` (use `--list` to see the available scenes)",
+                scene
+            ));
+        }
+        parsed.scene = scene;
+    }
+    if parsed.image_width == Some(0) {
+        return Err(String::from("`--width` must be at least 1"));
+    }
+    if parsed.samples_per_pixel == Some(0) {
+        return Err(String::from("`--spp` must be at least 1"));
+    }
+    if parsed.pass_samples == Some(0) {
+        return Err(String::from("`--pass-spp` must be at least 1"));
+    }
+    if parsed.resume.is_some() && parsed.seed.is_some() {
+        return Err(String::from(
+            "`--seed` cannot be combined with `--resume`, the checkpoint's seed is used",
+        ));
+    }
+    // Resuming keeps checkpointing to the same file unless told otherwise.
+    if parsed.checkpoint.is_none() {
+        parsed.checkpoint = parsed.resume.clone();
+    }
+    match parsed.adaptive {
+        Some(threshold) if !(threshold.is_finite() && threshold > 0.0) => {
+            return Err(String::from(
+                "`--adaptive` must be a positive error threshold",
+            ));
+        }
+        None if parsed.min_samples.is_some() => {
+            return Err(String::from("`--min-spp` only applies with `--adaptive`"));
+        }
+        _ => {}
+    }
+    let passes = parsed.checkpoint.is_some() || parsed.adaptive.is_some();
+    if passes && parsed.pass_samples.is_none() {
+        parsed.pass_samples = Some(DEFAULT_PASS_SAMPLES);
+    }
+    if !parsed.tone_mapping.exposure.is_finite() {
+        return Err(String::from(
+            "`--exposure` must be a finite number of stops",
+        ));
+    }
+    if parsed.threads == Some(0) {
+        return Err(String::from("`--threads` must be at least 1"));
+    }
+    Ok(Command::Render(Box::new(parsed)))
+}
+
+fn parse_number<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value `{}` for `{}`", value, flag))
+}
+
+// Accepts a single frame ("12") or an inclusive range ("0-47").
+fn parse_frames(value: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid frame range `{}`", value);
+    let (first, last) = match value.split_once('-') {
+        Some((first, last)) => (
+            first.trim().parse().map_err(|_| invalid())?,
+            last.trim().parse().map_err(|_| invalid())?,
+        ),
+        None => {
+            let frame = value.trim().parse().map_err(|_| invalid())?;
+            (frame, frame)
+        }
+    };
+    if first <= last {
+        Ok((first, last))
+    } else {
+        Err(invalid())
+    }
+}
+
+// Accepts either a plain ratio ("1.5") or a width:height pair ("16:9" or "16/9").
+fn parse_aspect_ratio(value: &str) -> Result<f64, String> {
+    let invalid = || format!("invalid aspect ratio `{}`", value);
+    let ratio = match value.split_once(|c| c == ':' || c == '/') {
+        Some((w, h)) => {
+            let w: f64 = w.trim().parse().map_err(|_| invalid())?;
+            let h: f64 = h.trim().parse().map_err(|_| invalid())?;
+            w / h
+        }
+        None => value.parse().map_err(|_| invalid())?,
+    };
+    if ratio.is_finite() && ratio > 0.0 {
+        Ok(ratio)
+    } else {
+        Err(invalid())
+    }
+}
+
+pub fn usage() -> String {
+    format!(
+        "\
+Usage: raytracer [SCENE] [OPTIONS]
+
+Renders one of the built-in scenes (default: {}), a scene
+description file ending in .json, .yaml or .yml, or a glTF model
+ending in .gltf or .glb. A scene file with a sequence renders one
+numbered image per frame.
+
+Options:
+  -o, --output <PATH>          output image path (default depends on the scene);
+                               .jpg and .png are 8-bit, .tif is 16-bit, and
+                               .hdr, .exr and .pfm keep linear float radiance;
+                               frames replace the last run of # in the name with
+                               their number, or add _0001 before the extension
+  -e, --export <PATH>          write the scene to a .json/.yaml file instead of rendering
+                               (one per frame of a sequence, numbered as for -o)
+  -w, --width <PIXELS>         image width
+  -a, --aspect-ratio <RATIO>   aspect ratio, e.g. 1.5 or 16:9
+  -s, --spp <N>                samples per pixel
+  -d, --max-depth <N>          maximum ray bounce depth
+  -j, --threads <N>            number of render threads (default: available cores)
+      --seed <N>               random seed, for reproducible renders (sequences use 0
+                               unless given, so frames match wherever they render)
+      --frames <FIRST-LAST>    render only these frames of a sequence, e.g. 0-47 or 12
+  -p, --pass-spp <N>           samples per pixel added by each progressive pass
+                               (default: all at once, or {} when checkpointing)
+  -c, --checkpoint <PATH>      save the accumulated samples after every pass
+  -r, --resume <PATH>          continue a checkpoint up to the requested --spp
+      --sampler <KIND>         sobol (default), halton, bluenoise, stratified
+                               or independent
+      --adaptive <ERROR>       stop sampling pixels once the relative error of their
+                               mean drops below ERROR, e.g. 0.02; --spp is the maximum
+      --min-spp <N>            samples every pixel takes first with --adaptive
+                               (default: {})
+      --heat-map <PATH>        also write an image of the samples taken per pixel
+      --spectral               trace wavelengths instead of RGB, so glass with a
+                               dispersion formula splits light into colors
+  -t, --tonemap <OPERATOR>     clamp (default), reinhard, aces or hable
+  -x, --exposure <STOPS>       exposure compensation, e.g. -1 or 0.5
+      --transfer <CURVE>       srgb (default) or gamma2, the books' square root
+  -l, --list                   list the built-in scenes
+  -h, --help                   print this help",
+        DEFAULT_SCENE, DEFAULT_PASS_SAMPLES, DEFAULT_MIN_SAMPLES
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_ranges() {
+        assert_eq!(parse_frames("3"), Ok((3, 3)));
+        assert_eq!(parse_frames("0-24"), Ok((0, 24)));
+        assert_eq!(parse_frames(" 5 - 5 "), Ok((5, 5)));
+        for value in ["9-2", "-1", "1-", "a-b", "", "1-2-3"] {
+            assert_eq!(
+                parse_frames(value),
+                Err(format!("invalid frame range `{}`", value))
+            );
+        }
+    }
+}
@@ -4,25 +4,32 @@ use crate::vec3::Vec3;
 pub type Color = Vec3;
 
 impl Color {
+    // Quantize an encoded display value, see `ToneMapping::apply`.
     pub fn write_color(&self) -> image::Rgb<u8> {
         let intensity: Interval = Interval::new(0.000, 0.999);
-        let mut r = self.x;
-        let mut g = self.y;
-        let mut b = self.z;
-        if r.is_nan() {
-            r = 0.0;
-        }
-        if g.is_nan() {
-            g = 0.0;
-        }
-        if b.is_nan() {
-            b = 0.0;
-        }
-        let r = (intensity.clamp(linear_to_gamma(r)) * 256.0) as u8;
-        let g = (intensity.clamp(linear_to_gamma(g)) * 256.0) as u8;
-        let b = (intensity.clamp(linear_to_gamma(b)) * 256.0) as u8;
+        let Color { x: r, y: g, z: b } = self.finite();
+        let r = (intensity.clamp(r) * 256.0) as u8;
+        let g = (intensity.clamp(g) * 256.0) as u8;
+        let b = (intensity.clamp(b) * 256.0) as u8;
+        image::Rgb([r, g, b])
+    }
+    pub fn write_color16(&self) -> image::Rgb<u16> {
+        let intensity: Interval = Interval::new(0.0, 1.0);
+        let Color { x: r, y: g, z: b } = self.finite();
+        let r = (intensity.clamp(r) * 65535.0).round() as u16;
+        let g = (intensity.clamp(g) * 65535.0).round() as u16;
+        let b = (intensity.clamp(b) * 65535.0).round() as u16;
         image::Rgb([r, g, b])
     }
+    // Relative luminance of linear sRGB primaries.
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
+    // Replace NaN and infinite components, left behind by degenerate samples, with black.
+    pub fn finite(&self) -> Color {
+        let clean = |c: f64| if c.is_finite() { c } else { 0.0 };
+        Color::new(clean(self.x), clean(self.y), clean(self.z))
+    }
     pub fn white() -> Color {
         Color {
             x: 1.0,
@@ -38,11 +45,3 @@ impl Color {
         }
     }
 }
-
-fn linear_to_gamma(linear: f64) -> f64 {
-    if linear > 0.0 {
-        f64::sqrt(linear)
-    } else {
-        0.0
-    }
-}
@@ -0,0 +1,577 @@
+use crate::aabb::Aabb;
+use crate::bvh::BvhNode;
+use crate::camera::{CameraSettings, ImageSettings, Shutter};
+use crate::color::Color;
+use crate::hittable::Hittable;
+use crate::hittable_list::HittableList;
+use crate::material::{
+    DiffuseLight, Lambertian, Material, NormalMapKind, NormalMapped, Opacity, Principled,
+    PrincipledParams, SpotLight,
+};
+use crate::matrix::Matrix4;
+use crate::mesh::{Face, TriangleMesh};
+use crate::obj::warn;
+use crate::scene_file::ImageDesc;
+use crate::scenes::Scene;
+use crate::sphere::Sphere;
+use crate::texture::{ChannelTexture, ImageTexture, ScaledTexture, SolidColor, Texture};
+use crate::vec3::{unit_vector, Point3, Vec3};
+use gltf::camera::Projection;
+use gltf::khr_lights_punctual::Kind;
+use gltf::mesh::Mode;
+use gltf::texture::WrappingMode;
+use gltf::Gltf;
+use image::{DynamicImage, GenericImageView, GrayImage};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Size of the spheres standing in for point and spot lights, relative to the scene
+const LIGHT_SIZE: f64 = 0.005;
+// Directional lights become a sun this wide, in degrees, this many scene sizes away.
+const SUN_ANGLE: f64 = 0.53;
+const SUN_DISTANCE: f64 = 100.0;
+
+// The geometry and lights of a glTF scene, and the view of its first camera or, without
+// one, a view of the whole model.
+pub struct GltfScene {
+    pub world: HittableList,
+    pub lights: HittableList,
+    pub camera_settings: CameraSettings,
+    pub aspect_ratio: Option<f64>,
+}
+
+pub fn is_gltf_file(name: &str) -> bool {
+    matches!(
+        Path::new(name).extension().and_then(|ext| ext.to_str()),
+        Some("gltf" | "glb")
+    )
+}
+
+// Render a glTF file on its own, with the default image settings.
+pub fn load(path: &Path) -> Result<Scene, String> {
+    let GltfScene {
+        mut world,
+        lights,
+        camera_settings,
+        aspect_ratio,
+    } = read_gltf(path)?;
+    let image = ImageDesc::default();
+    // Without lights of its own the model is lit by a sky.
+    let background = if lights.objects.is_empty() {
+        Color::new(0.70, 0.80, 1.00)
+    } else {
+        Color::black()
+    };
+    Ok(Scene {
+        world: HittableList::new_from(Arc::new(BvhNode::from_list(&mut world))),
+        lights,
+        image_settings: ImageSettings {
+            aspect_ratio: aspect_ratio.unwrap_or(image.aspect_ratio),
+            image_width: image.image_width,
+            quality: image.quality,
+            samples_per_pixel: image.samples_per_pixel,
+            max_depth: image.max_depth,
+            background,
+        },
+        camera_settings,
+    })
+}
+
+// Load the default scene of a .gltf or .glb file. Node transforms are applied to the
+// vertices, so all the meshes end up in one `TriangleMesh`. Punctual lights become small
+// glowing spheres, which are also returned in `lights` for sampling.
+pub fn read_gltf(path: &Path) -> Result<GltfScene, String> {
+    let error = |message: &dyn std::fmt::Display| format!("{}: {}", path.display(), message);
+    let data = std::fs::read(path).map_err(|err| error(&err))?;
+    let Gltf { document, blob } = Gltf::from_slice(&data).map_err(|err| error(&err))?;
+    let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+
+    let mut buffers = vec![];
+    for buffer in document.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => blob
+                .clone()
+                .ok_or_else(|| String::from("missing binary chunk")),
+            gltf::buffer::Source::Uri(uri) => read_uri(&dir, uri),
+        }
+        .map_err(|err| error(&format!("buffer {}: {}", buffer.index(), err)))?;
+        if data.len() < buffer.length() {
+            return Err(error(&format!("buffer {} is too short", buffer.index())));
+        }
+        buffers.push(data);
+    }
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| error(&"file has no scene"))?;
+
+    let mut loader = MaterialLoader {
+        dir: &dir,
+        buffers: &buffers,
+        textures: HashMap::new(),
+    };
+    let mut materials: Vec<Arc<dyn Material>> =
+        document.materials().map(|m| loader.material(&m)).collect();
+    // Primitives without a material get glTF's default, a plain white.
+    let default_material = materials.len() as u32;
+    materials.push(Arc::new(Lambertian::new(Color::new(1.0, 1.0, 1.0))));
+
+    let mut positions = vec![];
+    let mut normals = vec![];
+    let mut uvs = vec![];
+    let mut faces = vec![];
+    let mut punctual = vec![];
+    let mut camera = None;
+
+    let mut nodes: Vec<_> = scene
+        .nodes()
+        .map(|node| (node, Matrix4::IDENTITY))
+        .collect();
+    while let Some((node, parent)) = nodes.pop() {
+        let transform = parent * transpose(&node.transform().matrix());
+        nodes.extend(node.children().map(|child| (child, transform)));
+
+        if let Some(light) = node.light() {
+            punctual.push((light, transform));
+        }
+        if let Some(node_camera) = node.camera() {
+            match node_camera.projection() {
+                Projection::Perspective(perspective) if camera.is_none() => {
+                    let look_from = transform.point(&Point3::new(0.0, 0.0, 0.0));
+                    let forward = transform.vector(&Vec3::new(0.0, 0.0, -1.0));
+                    let settings = CameraSettings {
+                        vfov: (perspective.yfov() as f64).to_degrees(),
+                        look_from,
+                        look_at: look_from + unit_vector(&forward),
+                        vup: transform.vector(&Vec3::new(0.0, 1.0, 0.0)),
+                        defocus_angle: 0.0,
+                        focus_dist: 10.0,
+                        shutter: Shutter::default(),
+                    };
+                    camera = Some((settings, perspective.aspect_ratio().map(f64::from)));
+                }
+                Projection::Perspective(_) => {}
+                Projection::Orthographic(_) => warn(&format!(
+                    "{}: orthographic cameras are not supported",
+                    path.display()
+                )),
+            }
+        }
+
+        let mesh = match node.mesh() {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+        let normal_matrix = transform.normal_matrix();
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let vertices: Vec<Point3> = match reader.read_positions() {
+                Some(read) => read.map(|p| transform.point(&vec3(p))).collect(),
+                None => continue,
+            };
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(read) => read.into_u32().collect(),
+                None => (0..vertices.len() as u32).collect(),
+            };
+            let triangles: Vec<[u32; 3]> = match primitive.mode() {
+                Mode::Triangles => indices
+                    .chunks_exact(3)
+                    .map(|t| [t[0], t[1], t[2]])
+                    .collect(),
+                // Every other triangle of a strip is wound the other way.
+                Mode::TriangleStrip => (2..indices.len())
+                    .map(|i| match i % 2 {
+                        0 => [indices[i - 2], indices[i - 1], indices[i]],
+                        _ => [indices[i - 1], indices[i - 2], indices[i]],
+                    })
+                    .collect(),
+                Mode::TriangleFan => (2..indices.len())
+                    .map(|i| [indices[0], indices[i - 1], indices[i]])
+                    .collect(),
+                // Points and lines have no surface.
+                _ => continue,
+            };
+            if triangles
+                .iter()
+                .flatten()
+                .any(|&i| i as usize >= vertices.len())
+            {
+                return Err(error(&format!(
+                    "mesh {} has an index out of range",
+                    mesh.index()
+                )));
+            }
+
+            // glTF has one index for all the attributes of a vertex.
+            let offset = positions.len() as u32;
+            let has_normals = match reader.read_normals() {
+                Some(read) => {
+                    normals.extend(read.map(|n| unit_vector(&normal_matrix.vector(&vec3(n)))));
+                    normals.len() == offset as usize + vertices.len()
+                }
+                None => false,
+            };
+            // Texture coordinates run down the image, the other way from OBJ's and ours.
+            let has_uvs = match reader.read_tex_coords(0) {
+                Some(read) => {
+                    uvs.extend(read.into_f32().map(|[u, v]| (u as f64, 1.0 - v as f64)));
+                    uvs.len() == offset as usize + vertices.len()
+                }
+                None => false,
+            };
+            // Pad the attributes a primitive lacks, so the next one's indices line up.
+            normals.resize(offset as usize + vertices.len(), Vec3::new(0.0, 0.0, 0.0));
+            uvs.resize(offset as usize + vertices.len(), (0.0, 0.0));
+            positions.extend(vertices);
+
+            let material = primitive
+                .material()
+                .index()
+                .map_or(default_material, |i| i as u32);
+            faces.extend(triangles.iter().map(|t| {
+                let indices = t.map(|i| i + offset);
+                Face {
+                    vertices: indices,
+                    normals: has_normals.then_some(indices),
+                    uvs: has_uvs.then_some(indices),
+                    material,
+                }
+            }));
+        }
+    }
+
+    let mut world = HittableList::new();
+    let bounds = positions
+        .iter()
+        .map(|p| Aabb::two_point(p, p))
+        .reduce(|a, b| Aabb::two_aabb(&a, &b))
+        .unwrap_or_else(|| {
+            Aabb::two_point(&Point3::new(-1.0, -1.0, -1.0), &Point3::new(1.0, 1.0, 1.0))
+        });
+    if !faces.is_empty() {
+        let mesh = TriangleMesh::new(positions, normals, uvs, faces, materials)
+            .map_err(|err| error(&err))?;
+        world.add(Arc::new(mesh));
+    }
+
+    let center = Point3::new(
+        (bounds.x.min + bounds.x.max) / 2.0,
+        (bounds.y.min + bounds.y.max) / 2.0,
+        (bounds.z.min + bounds.z.max) / 2.0,
+    );
+    let size = Vec3::new(bounds.x.size(), bounds.y.size(), bounds.z.size()).length();
+    let mut lights = HittableList::new();
+    for (light, transform) in punctual {
+        let [r, g, b] = light.color();
+        let intensity = Color::new(r as f64, g as f64, b as f64) * light.intensity() as f64;
+        let position = transform.point(&Point3::new(0.0, 0.0, 0.0));
+        let direction = unit_vector(&transform.vector(&Vec3::new(0.0, 0.0, -1.0)));
+        // A sphere of radiance L shines with an intensity of L π r² in every direction,
+        // and a sun filling a cone of half angle θ gives an irradiance of L π sin²θ.
+        let radius = size * LIGHT_SIZE;
+        let sphere = match light.kind() {
+            Kind::Point => Sphere::new(
+                &position,
+                radius,
+                Arc::new(DiffuseLight::new(&(intensity / (PI * radius * radius)))),
+            ),
+            Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => Sphere::new(
+                &position,
+                radius,
+                Arc::new(SpotLight::new(
+                    &(intensity / (PI * radius * radius)),
+                    &direction,
+                    (inner_cone_angle as f64).to_degrees(),
+                    (outer_cone_angle as f64).to_degrees(),
+                )),
+            ),
+            Kind::Directional => {
+                let distance = size * SUN_DISTANCE;
+                let half_angle = (SUN_ANGLE / 2.0).to_radians();
+                Sphere::new(
+                    &(center - direction * distance),
+                    distance * half_angle.tan(),
+                    Arc::new(DiffuseLight::new(
+                        &(intensity / (PI * half_angle.sin().powi(2))),
+                    )),
+                )
+            }
+        };
+        let sphere: Arc<dyn Hittable> = Arc::new(sphere);
+        world.add(sphere.clone());
+        lights.add(sphere);
+    }
+
+    // Frame the model from the front, slightly above, when the file has no camera.
+    let (camera_settings, aspect_ratio) = camera.unwrap_or_else(|| {
+        let vfov: f64 = 40.0;
+        let distance = size / 2.0 / (vfov / 2.0).to_radians().sin();
+        let settings = CameraSettings {
+            vfov,
+            look_from: center + unit_vector(&Vec3::new(0.0, 0.25, 1.0)) * distance,
+            look_at: center,
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            defocus_angle: 0.0,
+            focus_dist: 10.0,
+            shutter: Shutter::default(),
+        };
+        (settings, None)
+    });
+
+    Ok(GltfScene {
+        world,
+        lights,
+        camera_settings,
+        aspect_ratio,
+    })
+}
+
+// Turns glTF's metallic-roughness materials into principled ones, sharing the textures.
+struct MaterialLoader<'a> {
+    dir: &'a Path,
+    buffers: &'a [Vec<u8>],
+    // Textures by glTF index, and whether they were read as data rather than colors
+    textures: HashMap<(usize, bool), Option<Arc<dyn Texture>>>,
+}
+
+impl<'a> MaterialLoader<'a> {
+    fn material(&mut self, m: &gltf::Material) -> Arc<dyn Material> {
+        let pbr = m.pbr_metallic_roughness();
+
+        // Anything that glows is a light, as with OBJ models.
+        let emissive = color(m.emissive_factor()) * m.emissive_strength().unwrap_or(1.0) as f64;
+        if emissive.x > 0.0 || emissive.y > 0.0 || emissive.z > 0.0 {
+            let emission = self.scaled(
+                m.emissive_texture().map(|info| info.texture()),
+                false,
+                None,
+                &emissive,
+            );
+            return Arc::new(DiffuseLight::new_tex(emission));
+        }
+
+        let [r, g, b, alpha] = pbr.base_color_factor();
+        let base_texture = pbr.base_color_texture().map(|info| info.texture());
+        let base_color = self.scaled(base_texture.clone(), false, None, &color([r, g, b]));
+        // Metalness is in the blue channel and roughness in the green one.
+        let metal_rough = pbr.metallic_roughness_texture().map(|info| info.texture());
+        let metallic = self.scaled(
+            metal_rough.clone(),
+            true,
+            Some(2),
+            &gray(pbr.metallic_factor()),
+        );
+        let roughness = self.scaled(metal_rough, true, Some(1), &gray(pbr.roughness_factor()));
+        let defaults = PrincipledParams::default();
+        let transmission = match m.transmission() {
+            Some(transmission) => self.scaled(
+                transmission
+                    .transmission_texture()
+                    .map(|info| info.texture()),
+                true,
+                Some(0),
+                &gray(transmission.transmission_factor()),
+            ),
+            None => defaults.transmission.clone(),
+        };
+        let mut material: Arc<dyn Material> = Arc::new(Principled::new(PrincipledParams {
+            base_color,
+            metallic,
+            roughness,
+            transmission,
+            ior: m.ior().map_or(defaults.ior, f64::from),
+            ..defaults
+        }));
+
+        if let Some(normal) = m.normal_texture() {
+            if let Some(map) = self.texture(&normal.texture(), true) {
+                material = Arc::new(NormalMapped::new(
+                    material,
+                    map,
+                    NormalMapKind::Normal {
+                        strength: normal.scale() as f64,
+                    },
+                ));
+            }
+        }
+
+        let cutoff = match m.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => return material,
+            gltf::material::AlphaMode::Mask => Some(m.alpha_cutoff().unwrap_or(0.5) as f64),
+            gltf::material::AlphaMode::Blend => None,
+        };
+        let opacity = |a: f64| match cutoff {
+            Some(cutoff) if a >= cutoff => 1.0,
+            Some(_) => 0.0,
+            None => a,
+        };
+        let alpha_map = base_texture.and_then(|texture| {
+            let image = self.decode(&texture.source()).ok()?;
+            if !image.color().has_alpha() {
+                return None;
+            }
+            let mut map = GrayImage::new(image.width(), image.height());
+            for (pixel, rgba) in map.pixels_mut().zip(image.to_rgba8().pixels()) {
+                let a = opacity(rgba[3] as f64 / 255.0 * alpha as f64);
+                pixel[0] = (a * 255.0).round() as u8;
+            }
+            let tex = ImageTexture::from_image(DynamicImage::ImageLuma8(map), true);
+            Some(Arc::new(repeating(tex, &texture)) as Arc<dyn Texture>)
+        });
+        match alpha_map {
+            Some(map) => Arc::new(Opacity::new(material, map)),
+            None if opacity(alpha as f64) < 1.0 => {
+                let a = opacity(alpha as f64);
+                Arc::new(Opacity::new(
+                    material,
+                    Arc::new(SolidColor::new(&Color::new(a, a, a))),
+                ))
+            }
+            None => material,
+        }
+    }
+
+    // A texture, or one of its channels, times `factor`, or just the factor as a color
+    // when there is no texture or it fails to load.
+    fn scaled(
+        &mut self,
+        texture: Option<gltf::Texture>,
+        linear: bool,
+        channel: Option<usize>,
+        factor: &Color,
+    ) -> Arc<dyn Texture> {
+        let mut tex = match texture.and_then(|texture| self.texture(&texture, linear)) {
+            Some(tex) => tex,
+            None => return Arc::new(SolidColor::new(factor)),
+        };
+        if let Some(channel) = channel {
+            tex = Arc::new(ChannelTexture::new(tex, channel));
+        }
+        if (factor.x, factor.y, factor.z) != (1.0, 1.0, 1.0) {
+            tex = Arc::new(ScaledTexture::new(tex, factor));
+        }
+        tex
+    }
+
+    // Images stored as files of their own are loaded by name, so the scene can still be
+    // exported. Ones that fail to load are left out with a warning.
+    fn texture(&mut self, texture: &gltf::Texture, linear: bool) -> Option<Arc<dyn Texture>> {
+        let key = (texture.index(), linear);
+        if let Some(tex) = self.textures.get(&key) {
+            return tex.clone();
+        }
+        let image = texture.source();
+        let tex = match image.source() {
+            gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => {
+                let path = self.dir.join(decode_percent(uri));
+                if path.is_file() {
+                    let name = path.to_string_lossy();
+                    Some(if linear {
+                        ImageTexture::linear(&name)
+                    } else {
+                        ImageTexture::new(&name)
+                    })
+                } else {
+                    warn(&format!("{}: texture not found", path.display()));
+                    None
+                }
+            }
+            _ => match self.decode(&image) {
+                Ok(decoded) => Some(ImageTexture::from_image(decoded, linear)),
+                Err(err) => {
+                    warn(&format!("image {}: {}", image.index(), err));
+                    None
+                }
+            },
+        };
+        let tex = tex.map(|tex| Arc::new(repeating(tex, texture)) as Arc<dyn Texture>);
+        self.textures.insert(key, tex.clone());
+        tex
+    }
+
+    fn decode(&self, image: &gltf::Image) -> Result<DynamicImage, String> {
+        let data = match image.source() {
+            gltf::image::Source::View { view, .. } => {
+                let buffer = &self.buffers[view.buffer().index()];
+                buffer
+                    .get(view.offset()..view.offset() + view.length())
+                    .ok_or("buffer view out of range")?
+                    .to_vec()
+            }
+            gltf::image::Source::Uri { uri, .. } => read_uri(self.dir, uri)?,
+        };
+        image::load_from_memory(&data).map_err(|err| err.to_string())
+    }
+}
+
+// glTF samplers repeat unless told to clamp.
+fn repeating(tex: ImageTexture, texture: &gltf::Texture) -> ImageTexture {
+    let sampler = texture.sampler();
+    if sampler.wrap_s() == WrappingMode::ClampToEdge
+        && sampler.wrap_t() == WrappingMode::ClampToEdge
+    {
+        tex
+    } else {
+        tex.repeating()
+    }
+}
+
+// The contents of a `data:` URI, or of a file relative to `dir`.
+fn read_uri(dir: &Path, uri: &str) -> Result<Vec<u8>, String> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (header, payload) = data.split_once(',').ok_or("malformed data URI")?;
+        if !header.ends_with(";base64") {
+            return Err(String::from("data URI is not base64"));
+        }
+        return base64::decode(payload).map_err(|err| err.to_string());
+    }
+    let path: PathBuf = dir.join(decode_percent(uri));
+    std::fs::read(&path).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+// Undo the %-escapes of a relative URI, such as `%20` for a space.
+fn decode_percent(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = (bytes[i] == b'%')
+            .then(|| uri.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn color(c: [f32; 3]) -> Color {
+    Color::new(c[0] as f64, c[1] as f64, c[2] as f64)
+}
+
+fn gray(v: f32) -> Color {
+    Color::new(v as f64, v as f64, v as f64)
+}
+
+fn vec3(v: [f32; 3]) -> Vec3 {
+    Vec3::new(v[0] as f64, v[1] as f64, v[2] as f64)
+}
+
+// glTF stores matrices column by column.
+fn transpose(columns: &[[f32; 4]; 4]) -> Matrix4 {
+    Matrix4::new([0, 1, 2, 3].map(|i| [0, 1, 2, 3].map(|j| columns[j][i] as f64)))
+}
@@ -1,7 +1,10 @@
 use crate::aabb::Aabb;
 use crate::interval::Interval;
 use crate::material::Material;
+use crate::onb::Onb;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene_file::{cannot_describe, DescribeResult, ObjectDesc, SceneWriter};
 use crate::vec3;
 use crate::vec3::{Point3, Vec3};
 use std::sync::Arc;
@@ -9,7 +12,18 @@ use std::sync::Arc;
 #[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
+    // Normal used for shading, on the side the ray came from
     pub normal: Vec3,
+    // Normal of the actual surface, on the same side as `normal`
+    pub geometric_normal: Vec3,
+    // Unit vectors completing a right-handed shading frame around `normal`, with the
+    // tangent following the direction of increasing u where the surface has one
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    // Partial derivatives of the position with respect to u and v, zero where the surface
+    // is not parameterized
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub mat: Arc<dyn Material>,
     pub t: f64,
     pub front_face: bool,
@@ -32,9 +46,15 @@ impl HitRecord {
         if !front_face {
             normal = -normal;
         }
+        let frame = Onb::new(&normal);
         Self {
             p: *p,
             normal,
+            geometric_normal: normal,
+            tangent: frame.u(),
+            bitangent: vec3::cross(&normal, &frame.u()),
+            dpdu: Vec3::black(),
+            dpdv: Vec3::black(),
             mat,
             t,
             front_face,
@@ -42,15 +62,45 @@ impl HitRecord {
             v,
         }
     }
+
+    // Record how the surface is parameterized, and align the tangent with u.
+    pub fn set_derivatives(&mut self, dpdu: &Vec3, dpdv: &Vec3) {
+        self.dpdu = *dpdu;
+        self.dpdv = *dpdv;
+        self.align_frame();
+    }
+
+    // Shade with a different normal, given on the same side as `normal`. The geometric
+    // normal stays as it is.
+    pub fn set_shading_normal(&mut self, normal: &Vec3) {
+        self.normal = *normal;
+        self.align_frame();
+    }
+
+    // Rebuild the tangent and bitangent around `normal`, following u if possible.
+    fn align_frame(&mut self) {
+        let tangent = self.dpdu - self.normal * vec3::dot(&self.normal, &self.dpdu);
+        self.tangent = if tangent.length_squared() > 1e-16 {
+            vec3::unit_vector(&tangent)
+        } else {
+            Onb::new(&self.normal).u()
+        };
+        self.bitangent = vec3::cross(&self.normal, &self.tangent);
+    }
 }
 
 pub trait Hittable: Send + Sync {
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord>; // Some(hit_record) None
     fn bounding_box(&self) -> Aabb;
-    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3) -> f64 {
+    // The density and a sample of directions from `origin` toward the object, as it is at
+    // `time`
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3, _time: f64) -> f64 {
         0.0
     }
-    fn random(&self, _origin: &Point3) -> Vec3 {
+    fn random(&self, _origin: &Point3, _time: f64, _sampler: &mut dyn Sampler) -> Vec3 {
         Vec3::new(1.0, 0.0, 0.0)
     }
+    fn describe(&self, _writer: &mut SceneWriter) -> DescribeResult<ObjectDesc> {
+        cannot_describe(std::any::type_name::<Self>())
+    }
 }
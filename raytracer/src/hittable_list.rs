@@ -2,8 +2,9 @@ use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::interval::Interval;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene_file::{DescribeResult, ObjectDesc, SceneWriter};
 use crate::vec3::{Point3, Vec3};
-use rand::{thread_rng, Rng};
 use std::sync::Arc;
 
 #[derive(Clone)]
@@ -50,18 +51,28 @@ impl Hittable for HittableList {
         self.bbox.clone()
     }
 
-    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
         let mut _size: u32 = 0;
         let mut sum: f64 = 0.0;
         for (_size, object) in (0_u32..).zip(self.objects.iter()) {
-            sum += object.pdf_value(origin, direction);
+            sum += object.pdf_value(origin, direction, time);
         }
         sum /= self.objects.len() as f64;
         sum
     }
 
-    fn random(&self, origin: &Point3) -> Vec3 {
+    fn random(&self, origin: &Point3, time: f64, sampler: &mut dyn Sampler) -> Vec3 {
         let size = self.objects.len();
-        self.objects[thread_rng().gen_range(0..size)].random(origin)
+        let index = ((sampler.get_1d() * size as f64) as usize).min(size - 1);
+        self.objects[index].random(origin, time, sampler)
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<ObjectDesc> {
+        let objects = self
+            .objects
+            .iter()
+            .map(|object| writer.object(object.as_ref()))
+            .collect::<DescribeResult<Vec<_>>>()?;
+        Ok(ObjectDesc::List { objects })
     }
 }
@@ -1,683 +1,317 @@
 mod aabb;
+mod animation;
 mod bvh;
 mod camera;
+mod checkpoint;
+mod cli;
 mod color;
+mod gltf;
 mod hittable;
 mod hittable_list;
 mod interval;
 mod material;
+mod matrix;
 mod medium;
+mod mesh;
+mod microfacet;
 mod obj;
 mod onb;
+mod output;
 mod pdf;
 mod perlin;
+mod ply;
 mod quad;
 mod ray;
 mod rtw_stb_image;
+mod rtweekend;
+mod sampler;
+mod scene_file;
+mod scenes;
+mod sequence;
+mod spectrum;
 mod sphere;
+mod stl;
 mod texture;
+mod tonemap;
+mod transform;
 mod translate;
 mod triangle;
 mod vec3;
 
-use crate::bvh::BvhNode;
-use crate::camera::{Camera, CameraSettings, ImageSettings};
-use crate::color::Color;
-use crate::hittable_list::HittableList;
-use crate::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
-use crate::medium::ConstantMedium;
-use crate::obj::read_obj;
-use crate::quad::{cuboid, Quad};
-use crate::sphere::Sphere;
-use crate::texture::{CheckerTexture, ImageTexture, NoiseTexture};
-use crate::translate::{RotateY, Translate};
-use crate::vec3::{Point3, Vec3};
+use crate::camera::{Adaptive, Camera, CameraSettings, ImageSettings};
+use crate::cli::{Args, Command};
+use crate::sampler::hash;
+use crate::scene_file::{SceneDesc, SequenceDesc};
+use crate::scenes::{Scene, BUILTIN};
 use console::style;
-use rand::{thread_rng, Rng};
-use std::sync::Arc;
-use std::{fs::File, process::exit};
+use std::fmt::Display;
+use std::path::Path;
+use std::process::exit;
 
-fn bouncing_spheres() {
-    let path = std::path::Path::new("output/book2/image2.jpg");
-    let prefix = path.parent().unwrap();
-    std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
-
-    // Materials
-    let checker = Arc::new(CheckerTexture::new_color(
-        0.32,
-        &Color::new(0.2, 0.3, 0.1),
-        &Color::new(0.9, 0.9, 0.9),
-    ));
-    let material_ground = Arc::new(Lambertian::new_tex(checker));
-    let material1 = Arc::new(Dielectric::new(1.5));
-    let material2 = Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
-    let material3 = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
-
-    // World
-    let mut world: HittableList = HittableList::new();
-    world.add(Arc::new(Sphere::new(
-        &Point3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        material_ground,
-    )));
-    world.add(Arc::new(Sphere::new(
-        &Point3::new(0.0, 1.0, 0.0),
-        1.0,
-        material1,
-    )));
-    world.add(Arc::new(Sphere::new(
-        &Point3::new(-4.0, 1.0, 0.0),
-        1.0,
-        material2,
-    )));
-    world.add(Arc::new(Sphere::new(
-        &Point3::new(4.0, 1.0, 0.0),
-        1.0,
-        material3,
-    )));
-
-    let mut rng = thread_rng();
-    for a in -11..11 {
-        for b in -11..11 {
-            let choose_mat = rng.gen_range(0.0..1.0);
-            let center = Point3::new(
-                a as f64 + 0.9 * rng.gen_range(0.0..1.0),
-                0.2,
-                b as f64 + 0.9 * rng.gen_range(0.0..1.0),
-            );
-
-            if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                let sphere_material: Arc<dyn Material> = if choose_mat < 0.8 {
-                    let albedo = Color::random() * Color::random();
-                    Arc::new(Lambertian::new(albedo))
-                } else if choose_mat < 0.95 {
-                    let albedo = Color::random_in(0.5, 1.0);
-                    let fuzz = rng.gen_range(0.0..0.5);
-                    Arc::new(Metal::new(albedo, fuzz))
-                } else {
-                    Arc::new(Dielectric::new(1.5))
-                };
-                if choose_mat < 0.8 {
-                    let center2 = center + Vec3::new(0.0, rng.gen_range(0.0..0.5), 0.0);
-                    world.add(Arc::new(Sphere::moving(
-                        &center,
-                        0.2,
-                        sphere_material,
-                        &center2,
-                    )));
-                } else {
-                    world.add(Arc::new(Sphere::new(&center, 0.2, sphere_material)));
-                }
+fn main() {
+    let args = match cli::parse(std::env::args().skip(1)) {
+        Ok(Command::Render(args)) => args,
+        Ok(Command::List) => {
+            for (name, output) in BUILTIN.iter() {
+                println!("{:<20} {}", name, output);
             }
+            exit(0);
+        }
+        Ok(Command::Help) => {
+            println!("{}", cli::usage());
+            exit(0);
+        }
+        Err(err) => {
+            eprintln!("{} {}", style("error:").red(), err);
+            eprintln!("{}", cli::usage());
+            exit(2);
         }
-    }
-
-    let world = HittableList::new_from(Arc::new(BvhNode::from_list(&mut world)));
-    let lights = Arc::new(HittableList::new());
-
-    let image_settings = ImageSettings {
-        aspect_ratio: 16.0 / 9.0,
-        image_width: 3840,
-        quality: 100,
-        samples_per_pixel: 100,
-        max_depth: 50,
-        background: Color::new(0.7, 0.8, 1.0),
-    };
-
-    let camera_settings = CameraSettings {
-        vfov: 20.0,
-        look_from: Point3::new(13.0, 2.0, 3.0),
-        look_at: Point3::new(0.0, 0.0, 0.0),
-        vup: Vec3::new(0.0, 1.0, 0.0),
-        defocus_angle: 0.6,
-        focus_dist: 10.0,
     };
 
-    let mut camera = Camera::new(image_settings, camera_settings);
-    camera.render(world, lights);
-
-    println!(
-        "Output image as \"{}\"",
-        style(path.to_str().unwrap()).yellow()
-    );
-    let output_image = image::DynamicImage::ImageRgb8(camera.img);
-    let mut output_file = File::create(path).unwrap();
-    match output_image.write_to(
-        &mut output_file,
-        image::ImageOutputFormat::Jpeg(camera.quality),
-    ) {
-        Ok(_) => {}
-        Err(_) => println!("{}", style("Outputting image fails.").red()),
-    }
-
-    exit(0);
-}
-
-fn earth() {
-    if thread_rng().gen_range(0.0..1.0) < 0.0000001 {
-        bouncing_spheres();
+    let scene_desc = scene_file::is_scene_file(&args.scene).then(|| {
+        let desc = scene_file::read(Path::new(&args.scene)).unwrap_or_else(|err| fail(err));
+        if let Some(sequence) = &desc.sequence {
+            render_sequence(&args, &desc, sequence);
+            exit(0);
+        }
+        desc
+    });
+    if args.frames.is_some() {
+        fail("`--frames` only applies to a scene file with a sequence");
     }
-    let path = std::path::Path::new("output/book2/image5.jpg");
-    let prefix = path.parent().unwrap();
-    std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
-
-    let earth_texture = Arc::new(ImageTexture::new("zbh.jpg"));
-    let earth_surface = Arc::new(Lambertian::new_tex(earth_texture));
-    let mut world = HittableList::new();
-    world.add(Arc::new(Sphere::new(
-        &Point3::new(0.0, 0.0, 0.0),
-        2.0,
-        earth_surface,
-    )));
-    let world = HittableList::new_from(Arc::new(BvhNode::from_list(&mut world)));
-    let lights = Arc::new(HittableList::new());
-
-    let image_settings = ImageSettings {
-        aspect_ratio: 16.0 / 9.0,
-        image_width: 1920,
-        quality: 100,
-        samples_per_pixel: 100,
-        max_depth: 50,
-        background: Color::new(0.7, 0.8, 1.0),
-    };
 
-    let camera_settings = CameraSettings {
-        vfov: 20.0,
-        look_from: Point3::new(0.0, 0.0, 12.0),
-        look_at: Point3::new(0.0, 0.0, 0.0),
-        vup: Vec3::new(0.0, 1.0, 0.0),
-        defocus_angle: 0.0,
-        focus_dist: 10.0,
+    let checkpoint = args.resume.as_ref().map(|resume| {
+        let checkpoint = checkpoint::load(std::path::Path::new(resume)).and_then(|checkpoint| {
+            checkpoint
+                .check_scene(&args.scene)
+                .map(|_| checkpoint)
+                .map_err(|err| format!("{}: {}", resume, err))
+        });
+        checkpoint.unwrap_or_else(|err| {
+            eprintln!("{} {}", style("error:").red(), err);
+            exit(1);
+        })
+    });
+
+    // The scenes draw random numbers too, so a resumed render must start from the same seed.
+    let seed = match &checkpoint {
+        Some(checkpoint) => checkpoint.seed,
+        None => args.seed.unwrap_or_else(rand::random),
     };
-
-    let mut camera = Camera::new(image_settings, camera_settings);
-    camera.render(world, lights);
-
     println!(
-        "Output image as \"{}\"",
-        style(path.to_str().unwrap()).yellow()
+        "Rendering \"{}\" with seed {}",
+        style(&args.scene).green(),
+        seed
     );
-    let output_image = image::DynamicImage::ImageRgb8(camera.img);
-    let mut output_file = File::create(path).unwrap();
-    match output_image.write_to(
-        &mut output_file,
-        image::ImageOutputFormat::Jpeg(camera.quality),
-    ) {
-        Ok(_) => {}
-        Err(_) => println!("{}", style("Outputting image fails.").red()),
-    }
-
-    exit(0);
-}
-
-fn perlin() {
-    let path = std::path::Path::new("output/book2/image15.jpg");
-    let prefix = path.parent().unwrap();
-    std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
-
-    let pertext = Arc::new(NoiseTexture::new(4.0));
-    let mut world = HittableList::new();
-    world.add(Arc::new(Sphere::new(
-        &Point3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        Arc::new(Lambertian::new_tex(pertext.clone())),
-    )));
-    world.add(Arc::new(Sphere::new(
-        &Point3::new(0.0, 2.0, 0.0),
-        2.0,
-        Arc::new(Lambertian::new_tex(pertext)),
-    )));
-    let world = HittableList::new_from(Arc::new(BvhNode::from_list(&mut world)));
-    let lights = Arc::new(HittableList::new());
-
-    let image_settings = ImageSettings {
-        aspect_ratio: 16.0 / 9.0,
-        image_width: 400,
-        quality: 100,
-        samples_per_pixel: 100,
-        max_depth: 50,
-        background: Color::new(0.7, 0.8, 1.0),
+    rtweekend::seed(seed);
+
+    let scene = if let Some(desc) = &scene_desc {
+        scene_file::build(Path::new(&args.scene), desc).map_err(|err| err.to_string())
+    } else if gltf::is_gltf_file(&args.scene) {
+        gltf::load(std::path::Path::new(&args.scene))
+    } else {
+        Ok(scenes::build(&args.scene).unwrap())
     };
-
-    let camera_settings = CameraSettings {
-        vfov: 20.0,
-        look_from: Point3::new(13.0, 2.0, 3.0),
-        look_at: Point3::new(0.0, 0.0, 0.0),
-        vup: Vec3::new(0.0, 1.0, 0.0),
-        defocus_angle: 0.0,
-        focus_dist: 10.0,
+    let Scene {
+        world,
+        lights,
+        mut image_settings,
+        camera_settings,
+    } = match scene {
+        Ok(scene) => scene,
+        Err(err) => {
+            eprintln!("{} {}", style("error:").red(), err);
+            exit(1);
+        }
     };
-
-    let mut camera = Camera::new(image_settings, camera_settings);
-    camera.render(world, lights);
-
-    println!(
-        "Output image as \"{}\"",
-        style(path.to_str().unwrap()).yellow()
-    );
-    let output_image = image::DynamicImage::ImageRgb8(camera.img);
-    let mut output_file = File::create(path).unwrap();
-    match output_image.write_to(
-        &mut output_file,
-        image::ImageOutputFormat::Jpeg(camera.quality),
-    ) {
-        Ok(_) => {}
-        Err(_) => println!("{}", style("Outputting image fails.").red()),
+    args.apply(&mut image_settings);
+
+    if let Some(export) = &args.export {
+        let path = std::path::Path::new(export);
+        let camera = Camera::new(image_settings, camera_settings);
+        let written = scene_file::export(&world, &lights, &camera, path)
+            .map_err(|err| format!("{}: {}", path.display(), err))
+            .and_then(|desc| scene_file::write(path, &desc).map_err(|err| err.to_string()));
+        if let Err(err) = written {
+            eprintln!("{} {}", style("error:").red(), err);
+            exit(1);
+        }
+        println!("Scene written to \"{}\"", style(export).yellow());
+        exit(0);
     }
 
-    exit(0);
-}
-
-fn quads() {
-    let path = std::path::Path::new("output/book2/image16.jpg");
-    let prefix = path.parent().unwrap();
-    std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
-
-    let pertext = Arc::new(NoiseTexture::new(4.0));
-    let left_red = Arc::new(Lambertian::new(Color::new(1.0, 0.2, 0.2)));
-    let back_green = Arc::new(Lambertian::new_tex(pertext));
-    let right_blue = Arc::new(Lambertian::new(Color::new(0.2, 0.2, 1.0)));
-    let upper_orange = Arc::new(Lambertian::new(Color::new(1.0, 0.5, 0.0)));
-    let lower_teal = Arc::new(Lambertian::new(Color::new(0.2, 0.8, 0.8)));
-
-    let mut world = HittableList::new();
-    world.add(Arc::new(Quad::new(
-        &Point3::new(-3.0, -2.0, 5.0),
-        &Vec3::new(0.0, 0.0, -4.0),
-        &Vec3::new(0.0, 4.0, 0.0),
-        left_red,
-    )));
-    world.add(Arc::new(Quad::new(
-        &Point3::new(-2.0, -2.0, 0.0),
-        &Vec3::new(4.0, 0.0, 0.0),
-        &Vec3::new(0.0, 4.0, 0.0),
-        back_green,
-    )));
-    world.add(Arc::new(Quad::new(
-        &Point3::new(3.0, -2.0, 1.0),
-        &Vec3::new(0.0, 0.0, 4.0),
-        &Vec3::new(0.0, 4.0, 0.0),
-        right_blue,
-    )));
-    world.add(Arc::new(Quad::new(
-        &Point3::new(-2.0, 3.0, 1.0),
-        &Vec3::new(4.0, 0.0, 0.0),
-        &Vec3::new(0.0, 0.0, 4.0),
-        upper_orange,
-    )));
-    world.add(Arc::new(Quad::new(
-        &Point3::new(-2.0, -3.0, 5.0),
-        &Vec3::new(4.0, 0.0, 0.0),
-        &Vec3::new(0.0, 0.0, -4.0),
-        lower_teal,
-    )));
-    let world = HittableList::new_from(Arc::new(BvhNode::from_list(&mut world)));
-    let lights = Arc::new(HittableList::new());
-
-    let image_settings = ImageSettings {
-        aspect_ratio: 1.0,
-        image_width: 400,
-        quality: 100,
-        samples_per_pixel: 100,
-        max_depth: 50,
-        background: Color::new(0.7, 0.8, 1.0),
-    };
-
-    let camera_settings = CameraSettings {
-        vfov: 80.0,
-        look_from: Point3::new(0.0, 0.0, 9.0),
-        look_at: Point3::new(0.0, 0.0, 0.0),
-        vup: Vec3::new(0.0, 1.0, 0.0),
-        defocus_angle: 0.0,
-        focus_dist: 10.0,
+    let output = match &args.output {
+        Some(output) => output.clone(),
+        None => match scenes::default_output(&args.scene) {
+            Some(output) => String::from(output),
+            None => {
+                let stem = std::path::Path::new(&args.scene).file_stem().unwrap();
+                format!("output/{}.jpg", stem.to_string_lossy())
+            }
+        },
     };
+    let path = std::path::Path::new(&output);
+    if let Some(prefix) = path.parent() {
+        std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
+    }
 
-    let mut camera = Camera::new(image_settings, camera_settings);
-    camera.render(world, lights);
-
-    println!(
-        "Output image as \"{}\"",
-        style(path.to_str().unwrap()).yellow()
-    );
-    let output_image = image::DynamicImage::ImageRgb8(camera.img);
-    let mut output_file = File::create(path).unwrap();
-    match output_image.write_to(
-        &mut output_file,
-        image::ImageOutputFormat::Jpeg(camera.quality),
-    ) {
-        Ok(_) => {}
-        Err(_) => println!("{}", style("Outputting image fails.").red()),
+    let mut camera = configure_camera(&args, image_settings, camera_settings, seed);
+    if let Some(checkpoint) = checkpoint {
+        let samples = checkpoint.samples();
+        if let Err(err) = checkpoint.restore(&mut camera) {
+            eprintln!(
+                "{} {}: {}",
+                style("error:").red(),
+                args.resume.unwrap(),
+                err
+            );
+            exit(1);
+        }
+        println!(
+            "Resuming from {} of {} samples per pixel",
+            samples, camera.samples_per_pixel
+        );
     }
+    camera.render(world, lights, |camera| {
+        // Keep a checkpoint and a preview of the image so far after every pass.
+        if let Some(checkpoint) = &args.checkpoint {
+            let saved = checkpoint::save(std::path::Path::new(checkpoint), &args.scene, camera)
+                .and_then(|_| output::write_image(path, camera));
+            if let Err(err) = saved {
+                eprintln!("{} {}", style("Saving checkpoint fails:").red(), err);
+            }
+        }
+    });
 
+    write_images(&camera, path, args.heat_map.as_deref().map(Path::new));
     exit(0);
 }
 
-fn cornell_box() {
-    let path = std::path::Path::new("output/advanced/imagetest2.jpg");
-    let prefix = path.parent().unwrap();
-    std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
-
-    let diffuse = Arc::new(DiffuseLight::new(&Color::new(5.0, 5.0, 5.0)));
-    //let red = Arc::new(Lambertian::new(Color::new(0.65, 0.05, 0.05)));
-    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
-    //let green = Arc::new(Lambertian::new(Color::new(0.12, 0.45, 0.15)));
-    let yellow = Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
-    let level = Arc::new(Lambertian::new_tex(Arc::new(ImageTexture::new("1.png"))));
-    let mut world = HittableList::new();
-    let mut lights = HittableList::new();
-    // world.add(Arc::new(Quad::new(
-    //     &Point3::new(555.0, 0.0, 0.0),
-    //     &Vec3::new(0.0, 555.0, 0.0),
-    //     &Vec3::new(0.0, 0.0, 555.0),
-    //     green,
-    // )));
-    world.add(Arc::new(Quad::new(
-        &Point3::new(-500.0, 0.0, -200.0),
-        &Vec3::new(0.0, 555.0, 0.0),
-        &Vec3::new(0.0, 0.0, 1555.0),
-        yellow.clone(),
-    )));
-    world.add(Arc::new(Quad::new(
-        &Point3::new(0.0, 554.0, 0.0),
-        &Vec3::new(555.0, 0.0, 0.0),
-        &Vec3::new(0.0, 0.0, 555.0),
-        diffuse.clone(),
-    )));
-    lights.add(Arc::new(Quad::new(
-        &Point3::new(0.0, 554.0, 0.0),
-        &Vec3::new(555.0, 0.0, 0.0),
-        &Vec3::new(0.0, 0.0, 555.0),
-        diffuse,
-    )));
-    world.add(Arc::new(Quad::new(
-        &Point3::new(-1000.0, 0.0, -200.0),
-        &Vec3::new(4000.0, 0.0, 0.0),
-        &Vec3::new(0.0, 0.0, 1555.0),
-        yellow.clone(),
-    )));
-    world.add(Arc::new(Quad::new(
-        &Point3::new(-1000.0, 555.0, -200.0),
-        &Vec3::new(4000.0, 0.0, 0.0),
-        &Vec3::new(0.0, 0.0, 1555.0),
-        white.clone(),
-    )));
-    world.add(Arc::new(Quad::new(
-        &Point3::new(1500.0, 0.0, 1000.0),
-        &Vec3::new(-1500.0, 0.0, 0.0),
-        &Vec3::new(0.0, 555.0, 0.0),
-        level,
-    )));
-
-    let obj = read_obj("monster.obj", 300.0);
-    let obj = RotateY::new(Arc::new(obj), -30.0);
-    let obj = Translate::new(Arc::new(obj), &Vec3::new(-100.0, 100.0, 300.0));
-    world.add(Arc::new(obj));
-    let obj = read_obj("coffin.obj", 300.0);
-    let obj = RotateY::new(Arc::new(obj), 15.0);
-    let obj = Translate::new(Arc::new(obj), &Vec3::new(-400.0, 0.0, 995.0));
-    world.add(Arc::new(obj));
-
-    let obj = read_obj("ClownMask.obj", 300.0);
-    let obj = RotateY::new(Arc::new(obj), 15.0);
-    let obj = Translate::new(Arc::new(obj), &Vec3::new(0.0, 100.0, 995.0));
-    world.add(Arc::new(obj));
-
-    // let obj = read_obj("Stopsign.obj", 30.0);
-    // let obj = RotateY::new(Arc::new(obj), 30.0);
-    // let obj = Translate::new(Arc::new(obj), &Vec3::new(-200.0, 100.0, 500.0));
-    // world.add(Arc::new(obj));
-
-    // let obj = read_obj("RubberDuck.obj", 20.0);
-    // let obj = RotateY::new(Arc::new(obj), 90.0);
-    // let obj = Translate::new(Arc::new(obj), &Vec3::new(-200.0, 100.0, 0.0));
-    // world.add(Arc::new(obj));
-
-    let obj = read_obj("RubberDuck.obj", 20.0);
-    let obj = RotateY::new(Arc::new(obj), 150.0);
-    let obj = Translate::new(Arc::new(obj), &Vec3::new(300.0, 0.0, 555.0));
-    world.add(Arc::new(obj));
-
-    let obj = read_obj("RubberDuck.obj", 20.0);
-    let obj = RotateY::new(Arc::new(obj), 120.0);
-    let obj = Translate::new(Arc::new(obj), &Vec3::new(200.0, 0.0, 455.0));
-    world.add(Arc::new(obj));
-
-    let obj = read_obj("RubberDuck.obj", 20.0);
-    let obj = RotateY::new(Arc::new(obj), 90.0);
-    let obj = Translate::new(Arc::new(obj), &Vec3::new(100.0, 0.0, 355.0));
-    world.add(Arc::new(obj));
-
-    let obj = read_obj("RubberDuck.obj", 20.0);
-    let obj = RotateY::new(Arc::new(obj), 60.0);
-    let obj = Translate::new(Arc::new(obj), &Vec3::new(200.0, 0.0, 255.0));
-    world.add(Arc::new(obj));
-
-    let obj = read_obj("RubberDuck.obj", 20.0);
-    let obj = RotateY::new(Arc::new(obj), 30.0);
-    let obj = Translate::new(Arc::new(obj), &Vec3::new(300.0, 0.0, 155.0));
-    world.add(Arc::new(obj));
-    let obj = read_obj("astronaut.obj", 400.0);
-    let obj = RotateY::new(Arc::new(obj), 150.0);
-    let obj = Translate::new(Arc::new(obj), &Vec3::new(400.0, 100.0, 150.0));
-    world.add(Arc::new(obj));
-    // world.add(Arc::new(Triangle::new(
-    //     &Point3::new(0.0, 0.0, 400.0),
-    //     &Point3::new(256.0, 0.0, 555.0),
-    //     &Point3::new(0.0, 256.0, 555.0),
-    //     white,
-    // )));
-
-    let world = HittableList::new_from(Arc::new(BvhNode::from_list(&mut world)));
-    // let lights = HittableList::new_from(Arc::new(BvhNode::from_list(&mut lights)));
-
-    let image_settings = ImageSettings {
-        aspect_ratio: 16.0 / 9.0,
-        image_width: 1920,
-        quality: 100,
-        samples_per_pixel: 1000,
-        max_depth: 50,
-        background: Color::black(),
-    };
-
-    let camera_settings = CameraSettings {
-        vfov: 40.0,
-        look_from: Point3::new(278.0, 400.0, -800.0),
-        look_at: Point3::new(278.0, 278.0, 0.0),
-        vup: Vec3::new(0.0, 1.0, 0.0),
-        defocus_angle: 0.0,
-        focus_dist: 10.0,
-    };
+fn fail(err: impl Display) -> ! {
+    eprintln!("{} {}", style("error:").red(), err);
+    exit(1);
+}
 
+fn configure_camera(
+    args: &Args,
+    image_settings: ImageSettings,
+    camera_settings: CameraSettings,
+    seed: u64,
+) -> Camera {
     let mut camera = Camera::new(image_settings, camera_settings);
-    camera.render(world, Arc::new(lights));
+    if let Some(threads) = args.threads {
+        camera.threads = threads;
+    }
+    camera.seed = seed;
+    camera.tone_mapping = args.tone_mapping;
+    if let Some(sampler) = args.sampler {
+        camera.sampler = sampler;
+    }
+    camera.spectral = args.spectral;
+    camera.adaptive = args.adaptive.map(|threshold| Adaptive {
+        threshold,
+        min_samples: args.min_samples.unwrap_or(cli::DEFAULT_MIN_SAMPLES),
+    });
+    if let Some(pass_samples) = args.pass_samples {
+        camera.pass_samples = pass_samples;
+    }
+    camera
+}
 
+fn write_images(camera: &Camera, path: &Path, heat_map: Option<&Path>) {
     println!(
         "Output image as \"{}\"",
         style(path.to_str().unwrap()).yellow()
     );
-    let output_image = image::DynamicImage::ImageRgb8(camera.img);
-    let mut output_file = File::create(path).unwrap();
-    match output_image.write_to(
-        &mut output_file,
-        image::ImageOutputFormat::Jpeg(camera.quality),
-    ) {
-        Ok(_) => {}
-        Err(_) => println!("{}", style("Outputting image fails.").red()),
+    if let Err(err) = output::write_image(path, camera) {
+        println!("{} {}", style("Outputting image fails:").red(), err);
+    }
+    if let Some(heat_map) = heat_map {
+        if let Some(prefix) = heat_map.parent() {
+            std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
+        }
+        println!(
+            "Sample heat map as \"{}\"",
+            style(heat_map.to_str().unwrap()).yellow()
+        );
+        if let Err(err) = output::write_heat_map(heat_map, camera) {
+            println!("{} {}", style("Outputting heat map fails:").red(), err);
+        }
     }
-
-    exit(0);
 }
 
-fn main() {
-    if thread_rng().gen_range(0.0..1.0) < 0.0000001 {
-        bouncing_spheres();
-    } else if thread_rng().gen_range(0.0..1.0) < 0.0000001 {
-        earth();
-    } else if thread_rng().gen_range(0.0..1.0) < 0.0000001 {
-        perlin();
-    } else if thread_rng().gen_range(0.0..1.0) < 0.0000001 {
-        quads();
-    } else if thread_rng().gen_range(0.0..1.0) < 0.9999991 {
-        cornell_box();
+// Render the frames of a sequence one after another, each to its own numbered image. A
+// frame's seed depends only on the base seed and its number, so frames split across
+// machines with `--frames` come out as they would in one run.
+fn render_sequence(args: &Args, desc: &SceneDesc, sequence: &SequenceDesc) {
+    if args.checkpoint.is_some() {
+        fail("`--checkpoint` and `--resume` cannot be used with a sequence");
     }
-    let path = std::path::Path::new("output/book2/image23.jpg");
-    let prefix = path.parent().unwrap();
-    std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
-
-    let mut boxes1 = HittableList::new();
-    let ground = Arc::new(Lambertian::new(Color::new(0.48, 0.83, 0.53)));
-
-    let boxes_per_sides = 20;
-    for i in 0..boxes_per_sides {
-        for j in 0..boxes_per_sides {
-            let w = 100.0;
-            let x0 = -1000.0 + i as f64 * w;
-            let y0 = 0.0;
-            let z0 = -1000.0 + j as f64 * w;
-            let x1 = x0 + w;
-            let y1 = thread_rng().gen_range(1.0..101.0);
-            let z1 = z0 + w;
-
-            boxes1.add(cuboid(
-                &Point3::new(x0, y0, z0),
-                &Point3::new(x1, y1, z1),
-                ground.clone(),
-            ));
-        }
+    if let Err(err) = sequence::check(sequence) {
+        fail(format!("{}: {}", args.scene, err));
     }
-
-    let mut world = HittableList::new();
-    let mut lights = HittableList::new();
-    world.add(Arc::new(BvhNode::from_list(&mut boxes1)));
-
-    let light = Arc::new(DiffuseLight::new(&Color::new(15.0, 15.0, 15.0)));
-    world.add(Arc::new(Quad::new(
-        &Point3::new(123.0, 554.0, 147.0),
-        &Vec3::new(300.0, 0.0, 0.0),
-        &Vec3::new(0.0, 0.0, 265.0),
-        light.clone(),
-    )));
-    lights.add(Arc::new(Quad::new(
-        &Point3::new(123.0, 554.0, 147.0),
-        &Vec3::new(300.0, 0.0, 0.0),
-        &Vec3::new(0.0, 0.0, 265.0),
-        light,
-    )));
-
-    let center1 = Point3::new(400.0, 400.0, 200.0);
-    let center2 = center1 + Vec3::new(30.0, 0.0, 0.0);
-    let sphere_material = Arc::new(Lambertian::new(Color::new(0.7, 0.3, 0.1)));
-    world.add(Arc::new(Sphere::moving(
-        &center1,
-        50.0,
-        sphere_material,
-        &center2,
-    )));
-
-    world.add(Arc::new(Sphere::new(
-        &Point3::new(260.0, 150.0, 45.0),
-        50.0,
-        Arc::new(Dielectric::new(1.5)),
-    )));
-    world.add(Arc::new(Sphere::new(
-        &Point3::new(0.0, 150.0, 145.0),
-        50.0,
-        Arc::new(Metal::new(Color::new(0.8, 0.8, 0.9), 1.0)),
-    )));
-
-    let mut boundary = Arc::new(Sphere::new(
-        &Point3::new(360.0, 150.0, 145.0),
-        70.0,
-        Arc::new(Dielectric::new(1.5)),
-    ));
-    world.add(boundary.clone());
-    world.add(Arc::new(ConstantMedium::new(
-        boundary,
-        0.2,
-        &Color::new(0.2, 0.4, 0.9),
-    )));
-    boundary = Arc::new(Sphere::new(
-        &Point3::new(0.0, 0.0, 0.0),
-        5000.0,
-        Arc::new(Dielectric::new(1.5)),
-    ));
-    world.add(Arc::new(ConstantMedium::new(
-        boundary,
-        0.0001,
-        &Color::white(),
-    )));
-
-    let e_mat = Arc::new(Lambertian::new_tex(Arc::new(ImageTexture::new(
-        "earthmap.jpg",
-    ))));
-    world.add(Arc::new(Sphere::new(
-        &Point3::new(400.0, 200.0, 400.0),
-        100.0,
-        e_mat,
-    )));
-    let per_text = Arc::new(NoiseTexture::new(0.2));
-    world.add(Arc::new(Sphere::new(
-        &Point3::new(220.0, 280.0, 300.0),
-        80.0,
-        Arc::new(Lambertian::new_tex(per_text)),
-    )));
-
-    let mut boxes2 = HittableList::new();
-    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
-    let ns = 1000;
-    for _j in 0..ns {
-        boxes2.add(Arc::new(Sphere::new(
-            &Point3::random_in(0.0, 165.0),
-            10.0,
-            white.clone(),
-        )));
+    let (first, last) = args.frames.unwrap_or((0, sequence.frames - 1));
+    if last >= sequence.frames {
+        fail(format!(
+            "`--frames` goes past the last frame, {}",
+            sequence.frames - 1
+        ));
     }
+    let seed = args.seed.unwrap_or(0);
+    println!(
+        "Rendering frames {} to {} of \"{}\" with seed {}",
+        first,
+        last,
+        style(&args.scene).green(),
+        seed
+    );
 
-    world.add(Arc::new(Translate::new(
-        Arc::new(RotateY::new(
-            Arc::new(BvhNode::from_list(&mut boxes2)),
-            15.0,
-        )),
-        &Vec3::new(-100.0, 270.0, 395.0),
-    )));
-
-    let world = HittableList::new_from(Arc::new(BvhNode::from_list(&mut world)));
-    let lights = Arc::new(lights);
-
-    let image_settings = ImageSettings {
-        aspect_ratio: 1.0,
-        image_width: 600,
-        quality: 100,
-        samples_per_pixel: 2500,
-        max_depth: 40,
-        background: Color::black(),
-    };
-
-    let camera_settings = CameraSettings {
-        vfov: 40.0,
-        look_from: Point3::new(478.0, 278.0, -600.0),
-        look_at: Point3::new(278.0, 278.0, 0.0),
-        vup: Vec3::new(0.0, 1.0, 0.0),
-        defocus_angle: 0.0,
-        focus_dist: 10.0,
+    let scene_path = Path::new(&args.scene);
+    let stem = scene_path.file_stem().unwrap().to_string_lossy();
+    let output = match &args.output {
+        Some(output) => output.clone(),
+        None => format!("output/{}_####.jpg", stem),
     };
+    for frame in first..=last {
+        let frame_seed = hash(&[seed, frame as u64]);
+        rtweekend::seed(frame_seed);
+        let scene = sequence::frame(desc, sequence, frame).and_then(|frame_desc| {
+            scene_file::build(scene_path, &frame_desc).map_err(|err| err.to_string())
+        });
+        let Scene {
+            world,
+            lights,
+            mut image_settings,
+            camera_settings,
+        } = scene.unwrap_or_else(|err| fail(err));
+        args.apply(&mut image_settings);
+
+        if let Some(export) = &args.export {
+            let export = sequence::frame_path(Path::new(export), frame);
+            let path = Path::new(&export);
+            let camera = Camera::new(image_settings, camera_settings);
+            let written = scene_file::export(&world, &lights, &camera, path)
+                .map_err(|err| format!("{}: {}", path.display(), err))
+                .and_then(|desc| scene_file::write(path, &desc).map_err(|err| err.to_string()));
+            if let Err(err) = written {
+                fail(err);
+            }
+            println!("Frame {} written to \"{}\"", frame, style(&export).yellow());
+            continue;
+        }
 
-    let mut camera = Camera::new(image_settings, camera_settings);
-    camera.render(world, lights);
-
-    println!(
-        "Output image as \"{}\"",
-        style(path.to_str().unwrap()).yellow()
-    );
-    let output_image = image::DynamicImage::ImageRgb8(camera.img);
-    let mut output_file = File::create(path).unwrap();
-    match output_image.write_to(
-        &mut output_file,
-        image::ImageOutputFormat::Jpeg(camera.quality),
-    ) {
-        Ok(_) => {}
-        Err(_) => println!("{}", style("Outputting image fails.").red()),
+        let path = sequence::frame_path(Path::new(&output), frame);
+        let path = Path::new(&path);
+        if let Some(prefix) = path.parent() {
+            std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
+        }
+        let heat_map = args
+            .heat_map
+            .as_ref()
+            .map(|heat_map| sequence::frame_path(Path::new(heat_map), frame));
+        println!("Frame {} of {}", frame, sequence.frames);
+        let mut camera = configure_camera(args, image_settings, camera_settings, frame_seed);
+        camera.render(world, lights, |_| {});
+        write_images(&camera, path, heat_map.as_deref().map(Path::new));
     }
-
-    exit(0);
 }
@@ -1,10 +1,16 @@
 use crate::color::Color;
 use crate::hittable::HitRecord;
-use crate::pdf::{CosinePDF, Pdf, SpherePDF};
+use crate::microfacet::{self, fresnel_dielectric, Fresnel, TrowbridgeReitz};
+use crate::onb::Onb;
+use crate::pdf::{CosinePDF, DielectricPDF, GgxPDF, LobePDF, MixturePDF, Pdf, SpherePDF};
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene_file::{
+    array, cannot_describe, DescribeResult, IorDesc, MaterialDesc, PrincipledDesc, SceneWriter,
+};
+use crate::spectrum::Ior;
 use crate::texture::{SolidColor, Texture};
-use crate::vec3::{dot, random_unit_vector, reflect, refract, unit_vector, Point3};
-use rand::Rng;
+use crate::vec3::{cross, dot, random_unit_vector, reflect, refract, unit_vector, Point3, Vec3};
 use std::sync::Arc;
 
 pub struct ScatterRecord {
@@ -18,12 +24,29 @@ pub trait Material: Send + Sync {
     fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, _u: f64, _v: f64, _p: &Point3) -> Color {
         Color::black()
     }
-    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<ScatterRecord> {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
         None
     }
     fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
         0.0
     }
+    // The BSDF times the cosine of the scattered direction, for materials whose response
+    // is not just the scattering PDF scaled by the attenuation.
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        Color::white() * self.scattering_pdf(r_in, rec, scattered)
+    }
+    // Whether the scattered direction depends on wavelength in spectral renders.
+    fn dispersive(&self) -> bool {
+        false
+    }
+    fn describe(&self, _writer: &mut SceneWriter) -> DescribeResult<MaterialDesc> {
+        cannot_describe(std::any::type_name::<Self>())
+    }
 }
 
 #[derive(Clone)]
@@ -43,7 +66,12 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        rec: &HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
         let attenuation = self.tex.value(rec.u, rec.v, &rec.p);
         let pdf_ptr = Arc::new(CosinePDF::new(&rec.normal));
         let skip_pdf = false;
@@ -63,6 +91,11 @@ impl Material for Lambertian {
             cosine / std::f64::consts::PI
         }
     }
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<MaterialDesc> {
+        Ok(MaterialDesc::Lambertian {
+            albedo: writer.texture(&self.tex)?,
+        })
+    }
 }
 
 #[derive(Clone)]
@@ -78,9 +111,14 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
         let mut reflected = reflect(&r_in.direction(), &rec.normal);
-        reflected = unit_vector(&reflected) + random_unit_vector() * self.fuzz;
+        reflected = unit_vector(&reflected) + random_unit_vector(sampler) * self.fuzz;
         let scattered = Ray::new(&rec.p, &reflected, r_in.time());
         let attenuation = self.albedo;
 
@@ -96,43 +134,92 @@ impl Material for Metal {
             None
         }
     }
+    fn describe(&self, _writer: &mut SceneWriter) -> DescribeResult<MaterialDesc> {
+        Ok(MaterialDesc::Metal {
+            albedo: array(&self.albedo),
+            fuzz: self.fuzz,
+        })
+    }
 }
 
+// Glass and other clear media. The interface is either perfectly smooth or a rough GGX
+// microfacet surface, and light is absorbed by the medium inside according to the
+// Beer-Lambert law, which assumes the object is closed.
 pub struct Dielectric {
-    refraction_index: f64,
+    ior: Ior,
+    roughness: f64,
+    distribution: Option<TrowbridgeReitz>,
+    // Fraction of each channel absorbed per unit distance travelled inside
+    absorption: Color,
 }
 
 impl Dielectric {
     pub(crate) fn new(refraction_index: f64) -> Self {
-        Self { refraction_index }
+        Self::glass(Ior::Constant(refraction_index), 0.0, Color::black())
+    }
+    pub fn glass(ior: Ior, roughness: f64, absorption: Color) -> Self {
+        Self {
+            ior,
+            roughness,
+            distribution: (roughness > 0.0).then(|| TrowbridgeReitz::new(roughness)),
+            absorption,
+        }
     }
-    pub fn reflectance(cos: f64, refraction_index: f64) -> f64 {
-        let mut r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
-        r0 *= r0;
-        r0 + (1.0 - r0) * f64::powf(1.0 - cos, 5.0)
+    // Index of refraction of the far side of the surface relative to the near side, at the
+    // wavelength of the ray.
+    fn eta(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        let refraction_index = self.ior.at(r_in.wavelength());
+        if rec.front_face {
+            refraction_index
+        } else {
+            1.0 / refraction_index
+        }
+    }
+    // Light surviving the trip from where the ray entered to where it is leaving.
+    fn transmittance(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        if rec.front_face {
+            return Color::white();
+        }
+        let distance = rec.t * r_in.direction().length();
+        Color::new(
+            (-self.absorption.x * distance).exp(),
+            (-self.absorption.y * distance).exp(),
+            (-self.absorption.z * distance).exp(),
+        )
     }
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
-        let attenuation = Color::white();
-        let ri = if rec.front_face {
-            1.0 / self.refraction_index
-        } else {
-            self.refraction_index
-        };
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        let attenuation = self.transmittance(r_in, rec);
+        if let Some(distribution) = self.distribution {
+            return Some(ScatterRecord {
+                attenuation,
+                pdf_ptr: Some(Arc::new(DielectricPDF::new(
+                    &rec.normal,
+                    &-r_in.direction(),
+                    distribution,
+                    self.eta(r_in, rec),
+                ))),
+                skip_pdf: false,
+                skip_pdf_ray: None,
+            });
+        }
 
+        let eta = self.eta(r_in, rec);
         let unit_direction = unit_vector(&r_in.direction());
         let cos_theta = f64::min(dot(&(-unit_direction), &rec.normal), 1.0);
-        let sin_theta = f64::sqrt(1.0 - cos_theta * cos_theta);
-
-        let mut rng = rand::thread_rng();
-        let direction =
-            if ri * sin_theta > 1.0 || Self::reflectance(cos_theta, ri) > rng.gen_range(0.0..1.0) {
-                reflect(&unit_direction, &rec.normal)
-            } else {
-                refract(&unit_direction, &rec.normal, ri)
-            };
+
+        let direction = if fresnel_dielectric(cos_theta, eta) > sampler.get_1d() {
+            reflect(&unit_direction, &rec.normal)
+        } else {
+            refract(&unit_direction, &rec.normal, 1.0 / eta)
+        };
 
         let scattered = Ray::new(&rec.p, &direction, r_in.time());
 
@@ -144,6 +231,415 @@ impl Material for Dielectric {
         };
         Some(srec)
     }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let Some(distribution) = &self.distribution else {
+            return 0.0;
+        };
+        let (wo, wi) = local_directions(r_in, rec, scattered);
+        microfacet::dielectric_pdf(distribution, self.eta(r_in, rec), &wo, &wi)
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let Some(distribution) = &self.distribution else {
+            return Color::black();
+        };
+        let (wo, wi) = local_directions(r_in, rec, scattered);
+        Color::white() * microfacet::dielectric(distribution, self.eta(r_in, rec), &wo, &wi)
+    }
+    fn dispersive(&self) -> bool {
+        self.ior.is_dispersive()
+    }
+    fn describe(&self, _writer: &mut SceneWriter) -> DescribeResult<MaterialDesc> {
+        Ok(MaterialDesc::Dielectric {
+            refraction_index: IorDesc::from(self.ior),
+            roughness: self.roughness,
+            absorption: array(&self.absorption),
+        })
+    }
+}
+
+// An infinitely thin sheet of glass, such as a window pane modelled as a single quad.
+// Light passing through is not bent, and the reflectance includes the light bouncing
+// back and forth between the two faces.
+pub struct ThinDielectric {
+    refraction_index: f64,
+}
+
+impl ThinDielectric {
+    pub fn new(refraction_index: f64) -> Self {
+        Self { refraction_index }
+    }
+}
+
+impl Material for ThinDielectric {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        let unit_direction = unit_vector(&r_in.direction());
+        let cos_theta = f64::min(dot(&(-unit_direction), &rec.normal), 1.0);
+        let mut r = fresnel_dielectric(cos_theta, self.refraction_index);
+        if r < 1.0 {
+            let t = 1.0 - r;
+            r += t * t * r / (1.0 - r * r);
+        }
+
+        let direction = if r > sampler.get_1d() {
+            reflect(&unit_direction, &rec.normal)
+        } else {
+            unit_direction
+        };
+        Some(ScatterRecord {
+            attenuation: Color::white(),
+            pdf_ptr: None,
+            skip_pdf: true,
+            skip_pdf_ray: Some(Ray::new(&rec.p, &direction, r_in.time())),
+        })
+    }
+    fn describe(&self, _writer: &mut SceneWriter) -> DescribeResult<MaterialDesc> {
+        Ok(MaterialDesc::ThinDielectric {
+            refraction_index: self.refraction_index,
+        })
+    }
+}
+
+// Rough dielectric or metallic surface with a GGX microfacet specular lobe. Dielectrics
+// reflect 4% at normal incidence and add a diffuse base underneath; metals tint their
+// reflection with the base color instead. Blended by `metalness`.
+pub struct Microfacet {
+    base_color: Arc<dyn Texture>,
+    distribution: TrowbridgeReitz,
+    roughness: f64,
+    metalness: f64,
+    // Measured optical constants, replacing the base color and metalness
+    conductor: Option<(Color, Color)>,
+}
+
+impl Microfacet {
+    pub fn new(base_color: Arc<dyn Texture>, roughness: f64, metalness: f64) -> Self {
+        Self {
+            base_color,
+            distribution: TrowbridgeReitz::new(roughness),
+            roughness,
+            metalness: metalness.clamp(0.0, 1.0),
+            conductor: None,
+        }
+    }
+    // A metal described by its complex index of refraction eta + i k per channel.
+    pub fn conductor(eta: Color, k: Color, roughness: f64) -> Self {
+        Self {
+            base_color: Arc::new(SolidColor::new(&Color::white())),
+            distribution: TrowbridgeReitz::new(roughness),
+            roughness,
+            metalness: 1.0,
+            conductor: Some((eta, k)),
+        }
+    }
+    fn has_diffuse(&self) -> bool {
+        self.conductor.is_none() && self.metalness < 1.0
+    }
+}
+
+impl Material for Microfacet {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        let specular: Arc<dyn Pdf> = Arc::new(GgxPDF::new(
+            &rec.normal,
+            &-r_in.direction(),
+            self.distribution,
+        ));
+        let pdf_ptr: Arc<dyn Pdf> = if self.has_diffuse() {
+            Arc::new(MixturePDF::new(
+                Arc::new(CosinePDF::new(&rec.normal)),
+                specular,
+            ))
+        } else {
+            specular
+        };
+        Some(ScatterRecord {
+            // The color depends on the scattered direction, so it all comes from `eval`.
+            attenuation: Color::white(),
+            pdf_ptr: Some(pdf_ptr),
+            skip_pdf: false,
+            skip_pdf_ray: None,
+        })
+    }
+    // The density `scatter` samples directions with.
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let (wo, wi) = local_directions(r_in, rec, scattered);
+        let specular = self.distribution.pdf(&wo, &wi);
+        if self.has_diffuse() {
+            0.5 * specular + 0.5 * wi.z.max(0.0) / std::f64::consts::PI
+        } else {
+            specular
+        }
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let (wo, wi) = local_directions(r_in, rec, scattered);
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color::black();
+        }
+
+        if let Some((eta, k)) = self.conductor {
+            let fresnel = Fresnel::Conductor { eta, k };
+            return microfacet::specular(&self.distribution, &fresnel, &wo, &wi);
+        }
+        let base_color = self.base_color.value(rec.u, rec.v, &rec.p);
+        let f0 =
+            Color::new(0.04, 0.04, 0.04) * (1.0 - self.metalness) + base_color * self.metalness;
+        let fresnel = Fresnel::Schlick { f0 };
+        let specular = microfacet::specular(&self.distribution, &fresnel, &wo, &wi);
+        // Light not reflected by the coating reaches the diffuse base.
+        let wm = unit_vector(&(wo + wi));
+        let transmitted = Color::white() - fresnel.eval(dot(&wo, &wm));
+        let diffuse =
+            base_color * transmitted * ((1.0 - self.metalness) * wi.z / std::f64::consts::PI);
+        specular + diffuse
+    }
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<MaterialDesc> {
+        Ok(match self.conductor {
+            Some((eta, k)) => MaterialDesc::Conductor {
+                eta: array(&eta),
+                k: array(&k),
+                roughness: self.roughness,
+            },
+            None => MaterialDesc::Microfacet {
+                base_color: writer.texture(&self.base_color)?,
+                roughness: self.roughness,
+                metalness: self.metalness,
+            },
+        })
+    }
+}
+
+// The inputs of a `Principled` surface, each of which may vary across it. Scalar inputs
+// are read from the red channel of their texture.
+pub struct PrincipledParams {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    // Dielectric reflectance at normal incidence, where 0.5 is the usual 4%
+    pub specular: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    // How much the sheen takes on the hue of the base color
+    pub sheen_tint: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_roughness: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub ior: f64,
+}
+
+impl Default for PrincipledParams {
+    fn default() -> Self {
+        let constant =
+            |v: f64| -> Arc<dyn Texture> { Arc::new(SolidColor::new(&Color::new(v, v, v))) };
+        Self {
+            base_color: constant(0.8),
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            sheen: constant(0.0),
+            sheen_tint: constant(0.5),
+            clearcoat: constant(0.0),
+            clearcoat_roughness: constant(0.1),
+            transmission: constant(0.0),
+            ior: 1.5,
+        }
+    }
+}
+
+// Layered material in the spirit of the Disney and OpenPBR principled BSDFs: a Burley
+// diffuse base with sheen, a GGX specular layer that turns into a metal with `metallic`,
+// an optional smooth glass base in place of the diffuse one, and a clear coat on top.
+pub struct Principled {
+    params: PrincipledParams,
+}
+
+// The lobes of a `Principled` surface at one hit point.
+struct PrincipledLobes {
+    base_color: Color,
+    roughness: f64,
+    metallic: f64,
+    f0: Color,
+    sheen: Color,
+    clearcoat: f64,
+    distribution: TrowbridgeReitz,
+    clearcoat_distribution: TrowbridgeReitz,
+    // Fraction of the base that is glass rather than diffuse
+    glass: f64,
+}
+
+impl Principled {
+    pub fn new(params: PrincipledParams) -> Self {
+        Self { params }
+    }
+
+    fn lobes(&self, rec: &HitRecord) -> PrincipledLobes {
+        let p = &self.params;
+        let scalar = |tex: &Arc<dyn Texture>| tex.value(rec.u, rec.v, &rec.p).x.clamp(0.0, 1.0);
+        let base_color = p.base_color.value(rec.u, rec.v, &rec.p);
+        let metallic = scalar(&p.metallic);
+        let roughness = scalar(&p.roughness);
+
+        let luminance = base_color.luminance();
+        let tint = if luminance > 0.0 {
+            base_color / luminance
+        } else {
+            Color::white()
+        };
+        let sheen_tint = scalar(&p.sheen_tint);
+        let dielectric_f0 = Color::white() * (0.08 * scalar(&p.specular));
+        PrincipledLobes {
+            base_color,
+            roughness,
+            metallic,
+            f0: dielectric_f0 * (1.0 - metallic) + base_color * metallic,
+            sheen: (Color::white() * (1.0 - sheen_tint) + tint * sheen_tint) * scalar(&p.sheen),
+            clearcoat: scalar(&p.clearcoat),
+            distribution: TrowbridgeReitz::new(roughness),
+            clearcoat_distribution: TrowbridgeReitz::new(scalar(&p.clearcoat_roughness)),
+            glass: (1.0 - metallic) * scalar(&p.transmission),
+        }
+    }
+
+    // Chooses between the opaque lobes in proportion to roughly how much each reflects.
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, lobes: &PrincipledLobes) -> LobePDF {
+        let wo = -unit_vector(&r_in.direction());
+        let cos_o = dot(&wo, &rec.normal);
+        let diffuse = (1.0 - lobes.metallic) * (lobes.base_color + lobes.sheen).luminance();
+        let specular = Fresnel::Schlick { f0: lobes.f0 }.eval(cos_o).luminance();
+        let clearcoat = lobes.clearcoat * schlick(0.04, cos_o);
+        LobePDF::new(vec![
+            (diffuse, Arc::new(CosinePDF::new(&rec.normal))),
+            (
+                specular.max(0.01),
+                Arc::new(GgxPDF::new(&rec.normal, &wo, lobes.distribution)),
+            ),
+            (
+                clearcoat,
+                Arc::new(GgxPDF::new(&rec.normal, &wo, lobes.clearcoat_distribution)),
+            ),
+        ])
+    }
+
+    // Refract into or reflect off the glass base.
+    fn scatter_glass(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        lobes: &PrincipledLobes,
+        sampler: &mut dyn Sampler,
+    ) -> ScatterRecord {
+        let eta = if rec.front_face {
+            self.params.ior
+        } else {
+            1.0 / self.params.ior
+        };
+        let unit_direction = unit_vector(&r_in.direction());
+        let cos_theta = f64::min(dot(&(-unit_direction), &rec.normal), 1.0);
+
+        let (direction, attenuation) = if fresnel_dielectric(cos_theta, eta) > sampler.get_1d() {
+            (reflect(&unit_direction, &rec.normal), Color::white())
+        } else {
+            (
+                refract(&unit_direction, &rec.normal, 1.0 / eta),
+                lobes.base_color,
+            )
+        };
+        ScatterRecord {
+            attenuation,
+            pdf_ptr: None,
+            skip_pdf: true,
+            skip_pdf_ray: Some(Ray::new(&rec.p, &direction, r_in.time())),
+        }
+    }
+}
+
+impl Material for Principled {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        let lobes = self.lobes(rec);
+        // Rays only get inside through the glass, so they always leave through it too.
+        if lobes.glass > 0.0 && (!rec.front_face || sampler.get_1d() < lobes.glass) {
+            return Some(self.scatter_glass(r_in, rec, &lobes, sampler));
+        }
+        Some(ScatterRecord {
+            // Choosing the glass as often as it is weighted cancels its share out.
+            attenuation: Color::white(),
+            pdf_ptr: Some(Arc::new(self.pdf(r_in, rec, &lobes))),
+            skip_pdf: false,
+            skip_pdf_ray: None,
+        })
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let lobes = self.lobes(rec);
+        self.pdf(r_in, rec, &lobes).value(&scattered.direction())
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let (wo, wi) = local_directions(r_in, rec, scattered);
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color::black();
+        }
+        let lobes = self.lobes(rec);
+        let wm = unit_vector(&(wo + wi));
+        let cos_d = dot(&wi, &wm);
+
+        // Burley's diffuse, which brightens towards grazing angles on rough surfaces.
+        let fd90 = 0.5 + 2.0 * lobes.roughness * cos_d * cos_d;
+        let retro = (1.0 + (fd90 - 1.0) * (1.0 - wi.z).powi(5))
+            * (1.0 + (fd90 - 1.0) * (1.0 - wo.z).powi(5));
+        let diffuse = lobes.base_color * (retro / std::f64::consts::PI);
+        let sheen = lobes.sheen * (1.0 - cos_d).powi(5);
+        let base = (diffuse + sheen) * ((1.0 - lobes.metallic) * wi.z);
+
+        let fresnel = Fresnel::Schlick { f0: lobes.f0 };
+        let specular = microfacet::specular(&lobes.distribution, &fresnel, &wo, &wi);
+
+        let coat_fresnel = Fresnel::Schlick {
+            f0: Color::new(0.04, 0.04, 0.04),
+        };
+        let clearcoat =
+            microfacet::specular(&lobes.clearcoat_distribution, &coat_fresnel, &wo, &wi);
+        // Whatever the coat reflects never reaches the layers below.
+        let under = 1.0 - lobes.clearcoat * schlick(0.04, dot(&wo, &wm));
+        (base + specular) * under + clearcoat * lobes.clearcoat
+    }
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<MaterialDesc> {
+        let p = &self.params;
+        Ok(MaterialDesc::Principled(Box::new(PrincipledDesc {
+            base_color: writer.texture(&p.base_color)?,
+            metallic: Some(writer.scalar(&p.metallic)?),
+            roughness: Some(writer.scalar(&p.roughness)?),
+            specular: Some(writer.scalar(&p.specular)?),
+            sheen: Some(writer.scalar(&p.sheen)?),
+            sheen_tint: Some(writer.scalar(&p.sheen_tint)?),
+            clearcoat: Some(writer.scalar(&p.clearcoat)?),
+            clearcoat_roughness: Some(writer.scalar(&p.clearcoat_roughness)?),
+            transmission: Some(writer.scalar(&p.transmission)?),
+            ior: p.ior,
+        })))
+    }
+}
+
+fn schlick(f0: f64, cos_theta: f64) -> f64 {
+    f0 + (1.0 - f0) * (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+}
+
+// The outgoing and incoming directions in the shading frame, normal along +z.
+fn local_directions(r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> (Vec3, Vec3) {
+    let uvw = Onb::new(&rec.normal);
+    let wo = uvw.to_local(&-unit_vector(&r_in.direction()));
+    let wi = uvw.to_local(&unit_vector(&scattered.direction()));
+    (wo, wi)
 }
 
 pub struct DiffuseLight {
@@ -156,7 +652,7 @@ impl DiffuseLight {
             tex: Arc::new(SolidColor::new(emit)),
         }
     }
-    pub fn _new_tex(tex: Arc<dyn Texture>) -> Self {
+    pub fn new_tex(tex: Arc<dyn Texture>) -> Self {
         Self { tex }
     }
 }
@@ -169,6 +665,54 @@ impl Material for DiffuseLight {
             self.tex.value(u, v, p)
         }
     }
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<MaterialDesc> {
+        Ok(MaterialDesc::DiffuseLight {
+            emit: writer.texture(&self.tex)?,
+        })
+    }
+}
+
+// A light that only shines within a cone around `direction`, fading out between the inner
+// and the outer angle.
+pub struct SpotLight {
+    emit: Color,
+    direction: Vec3,
+    cos_inner: f64,
+    cos_outer: f64,
+}
+
+impl SpotLight {
+    // Angles are in degrees from the direction.
+    pub fn new(emit: &Color, direction: &Vec3, inner_angle: f64, outer_angle: f64) -> Self {
+        Self {
+            emit: *emit,
+            direction: unit_vector(direction),
+            cos_inner: inner_angle.to_radians().cos(),
+            cos_outer: outer_angle.to_radians().cos(),
+        }
+    }
+}
+
+impl Material for SpotLight {
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, _u: f64, _v: f64, _p: &Point3) -> Color {
+        if !rec.front_face {
+            return Color::black();
+        }
+        let cosine = dot(&-unit_vector(&r_in.direction()), &self.direction);
+        if cosine >= self.cos_inner {
+            return self.emit;
+        }
+        let falloff = (cosine - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        self.emit * falloff.clamp(0.0, 1.0).powi(2)
+    }
+    fn describe(&self, _writer: &mut SceneWriter) -> DescribeResult<MaterialDesc> {
+        Ok(MaterialDesc::SpotLight {
+            emit: array(&self.emit),
+            direction: array(&self.direction),
+            inner_angle: self.cos_inner.acos().to_degrees(),
+            outer_angle: self.cos_outer.acos().to_degrees(),
+        })
+    }
 }
 
 pub struct Isotropic {
@@ -176,18 +720,23 @@ pub struct Isotropic {
 }
 
 impl Isotropic {
-    pub fn new(albedo: &Color) -> Self {
+    pub fn _new(albedo: &Color) -> Self {
         Self {
             tex: Arc::new(SolidColor::new(albedo)),
         }
     }
-    pub fn _new_tex(tex: Arc<dyn Texture>) -> Self {
+    pub fn new_tex(tex: Arc<dyn Texture>) -> Self {
         Self { tex }
     }
 }
 
 impl Material for Isotropic {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        rec: &HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
         let attenuation = self.tex.value(rec.u, rec.v, &rec.p);
         let pdf_ptr = Arc::new(SpherePDF::_new());
         let skip_pdf = false;
@@ -202,4 +751,185 @@ impl Material for Isotropic {
     fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
         1.0 / (4.0 * std::f64::consts::PI)
     }
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<MaterialDesc> {
+        Ok(MaterialDesc::Isotropic {
+            albedo: writer.texture(&self.tex)?,
+        })
+    }
+}
+
+// How `NormalMapped` reads its texture.
+#[derive(Copy, Clone)]
+pub enum NormalMapKind {
+    // A tangent space normal map: the color in 0..1 encodes a unit vector in the frame
+    // (tangent, bitangent, normal), and `strength` scales how far it tilts.
+    Normal { strength: f64 },
+    // A height field in the red channel, displaced along the normal by `scale` scene units.
+    Bump { scale: f64 },
+}
+
+// Step in u and v for the finite differences of a bump map
+const BUMP_DELTA: f64 = 1.0 / 2048.0;
+
+// Wraps another material and shades it with a normal perturbed by a texture.
+pub struct NormalMapped {
+    material: Arc<dyn Material>,
+    map: Arc<dyn Texture>,
+    kind: NormalMapKind,
+}
+
+impl NormalMapped {
+    pub fn new(material: Arc<dyn Material>, map: Arc<dyn Texture>, kind: NormalMapKind) -> Self {
+        Self {
+            material,
+            map,
+            kind,
+        }
+    }
+
+    // The hit record with the perturbed shading normal and a frame rebuilt around it.
+    fn perturb(&self, r_in: &Ray, rec: &HitRecord) -> HitRecord {
+        // Work with the outward normal, as the maps are authored for the front side.
+        let side = if rec.front_face { 1.0 } else { -1.0 };
+        let n = rec.normal * side;
+        let outward = match self.kind {
+            NormalMapKind::Normal { strength } => {
+                let c = self.map.value(rec.u, rec.v, &rec.p);
+                let t = rec.tangent;
+                let b = cross(&n, &t);
+                t * ((2.0 * c.x - 1.0) * strength)
+                    + b * ((2.0 * c.y - 1.0) * strength)
+                    + n * (2.0 * c.z - 1.0).max(0.0)
+            }
+            NormalMapKind::Bump { scale } => {
+                if rec.dpdu.length_squared() == 0.0 || rec.dpdv.length_squared() == 0.0 {
+                    return rec.clone();
+                }
+                let height = |du: f64, dv: f64| {
+                    let p = rec.p + rec.dpdu * du + rec.dpdv * dv;
+                    self.map.value(rec.u + du, rec.v + dv, &p).x * scale
+                };
+                let h = height(0.0, 0.0);
+                let dhdu = (height(BUMP_DELTA, 0.0) - h) / BUMP_DELTA;
+                let dhdv = (height(0.0, BUMP_DELTA) - h) / BUMP_DELTA;
+                let m = cross(&(rec.dpdu + n * dhdu), &(rec.dpdv + n * dhdv));
+                // Parameterizations can be left-handed; keep the side of the original normal.
+                if dot(&m, &n) < 0.0 {
+                    -m
+                } else {
+                    m
+                }
+            }
+        };
+        if outward.length_squared() == 0.0 {
+            return rec.clone();
+        }
+        let mut normal = unit_vector(&outward) * side;
+
+        // A normal tilted away from the viewer would leave it looking at the back of the
+        // shading hemisphere, so bend it back until the viewer is just in front.
+        let wo = -unit_vector(&r_in.direction());
+        let cos_o = dot(&normal, &wo);
+        if cos_o < 0.01 {
+            normal = unit_vector(&(normal + wo * (0.01 - cos_o)));
+        }
+
+        let mut shading = rec.clone();
+        shading.set_shading_normal(&normal);
+        shading
+    }
+}
+
+impl Material for NormalMapped {
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: &Point3) -> Color {
+        self.material.emitted(r_in, rec, u, v, p)
+    }
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        self.material
+            .scatter(r_in, &self.perturb(r_in, rec), sampler)
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.material
+            .scattering_pdf(r_in, &self.perturb(r_in, rec), scattered)
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.material
+            .eval(r_in, &self.perturb(r_in, rec), scattered)
+    }
+    fn dispersive(&self) -> bool {
+        self.material.dispersive()
+    }
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<MaterialDesc> {
+        let material = writer.material(&self.material)?;
+        Ok(match self.kind {
+            NormalMapKind::Normal { strength } => MaterialDesc::NormalMap {
+                material,
+                map: writer.texture(&self.map)?,
+                strength,
+            },
+            NormalMapKind::Bump { scale } => MaterialDesc::BumpMap {
+                material,
+                height: writer.scalar(&self.map)?,
+                scale,
+            },
+        })
+    }
+}
+
+// Wraps another material and lets light pass straight through where the surface is not
+// fully opaque, as for cutout leaves or fading decals. Opacity is the texture's red channel.
+pub struct Opacity {
+    material: Arc<dyn Material>,
+    opacity: Arc<dyn Texture>,
+}
+
+impl Opacity {
+    pub fn new(material: Arc<dyn Material>, opacity: Arc<dyn Texture>) -> Self {
+        Self { material, opacity }
+    }
+}
+
+impl Material for Opacity {
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: &Point3) -> Color {
+        let opacity = self.opacity.value(rec.u, rec.v, &rec.p).x.clamp(0.0, 1.0);
+        self.material.emitted(r_in, rec, u, v, p) * opacity
+    }
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        // Choosing between the two in proportion to the opacity leaves both unweighted.
+        let opacity = self.opacity.value(rec.u, rec.v, &rec.p).x.clamp(0.0, 1.0);
+        if opacity >= 1.0 || sampler.get_1d() < opacity {
+            return self.material.scatter(r_in, rec, sampler);
+        }
+        Some(ScatterRecord {
+            attenuation: Color::white(),
+            pdf_ptr: None,
+            skip_pdf: true,
+            skip_pdf_ray: Some(Ray::new(&rec.p, &r_in.direction(), r_in.time())),
+        })
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.material.scattering_pdf(r_in, rec, scattered)
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.material.eval(r_in, rec, scattered)
+    }
+    fn dispersive(&self) -> bool {
+        self.material.dispersive()
+    }
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<MaterialDesc> {
+        Ok(MaterialDesc::Opacity {
+            material: writer.material(&self.material)?,
+            opacity: writer.scalar(&self.opacity)?,
+        })
+    }
 }
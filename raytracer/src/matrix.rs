@@ -0,0 +1,274 @@
+use crate::vec3::{cross, dot, unit_vector, Point3, Vec3};
+use std::ops::Mul;
+
+// A 4x4 matrix stored row by row, acting on column vectors. Only affine transforms are
+// used, whose last row is 0 0 0 1.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+    pub const IDENTITY: Self = Self {
+        m: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    };
+
+    pub fn new(m: [[f64; 4]; 4]) -> Self {
+        Self { m }
+    }
+
+    pub fn translation(offset: &Vec3) -> Self {
+        let mut t = Self::IDENTITY;
+        t.m[0][3] = offset.x;
+        t.m[1][3] = offset.y;
+        t.m[2][3] = offset.z;
+        t
+    }
+
+    pub fn scaling(factors: &Vec3) -> Self {
+        let mut s = Self::IDENTITY;
+        s.m[0][0] = factors.x;
+        s.m[1][1] = factors.y;
+        s.m[2][2] = factors.z;
+        s
+    }
+
+    // A counterclockwise rotation by `angle` degrees when looking down `axis`.
+    pub fn rotation(axis: &Vec3, angle: f64) -> Self {
+        Quaternion::from_axis_angle(axis, angle).matrix()
+    }
+
+    pub fn is_affine(&self) -> bool {
+        self.m[3] == [0.0, 0.0, 0.0, 1.0]
+    }
+
+    // Determinant of the linear part, how much the transform scales volumes.
+    pub fn determinant(&self) -> f64 {
+        let (x, y, z) = self.columns();
+        dot(&x, &cross(&y, &z))
+    }
+
+    // The inverse of an affine transform, or None if it flattens space.
+    pub fn inverse(&self) -> Option<Self> {
+        let det = self.determinant();
+        if det.abs() < 1e-12 || !det.is_finite() {
+            return None;
+        }
+        // The rows of the inverse of the linear part are the cross products of its
+        // columns over the determinant.
+        let (x, y, z) = self.columns();
+        let rows = [
+            cross(&y, &z) / det,
+            cross(&z, &x) / det,
+            cross(&x, &y) / det,
+        ];
+        let mut inverse = Self::IDENTITY;
+        for (row, r) in inverse.m.iter_mut().zip(rows) {
+            *row = [r.x, r.y, r.z, 0.0];
+        }
+        let offset = inverse.vector(&Vec3::new(self.m[0][3], self.m[1][3], self.m[2][3]));
+        Some(Matrix4::translation(&-offset) * inverse)
+    }
+
+    // Normals transform by the inverse transpose of the linear part. A singular transform
+    // gives infinite normals, so callers check for that with `inverse` first.
+    pub fn normal_matrix(&self) -> Self {
+        let (x, y, z) = self.columns();
+        let det = self.determinant();
+        let columns = [
+            cross(&y, &z) / det,
+            cross(&z, &x) / det,
+            cross(&x, &y) / det,
+        ];
+        let mut normal = Self::IDENTITY;
+        for (i, row) in normal.m.iter_mut().take(3).enumerate() {
+            for (j, column) in columns.iter().enumerate() {
+                row[j] = column[i];
+            }
+        }
+        normal
+    }
+
+    pub fn point(&self, p: &Point3) -> Point3 {
+        self.vector(p) + Vec3::new(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+
+    // Transform a direction, which the translation does not move.
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        let row = |i: usize| self.m[i][0] * v.x + self.m[i][1] * v.y + self.m[i][2] * v.z;
+        Vec3::new(row(0), row(1), row(2))
+    }
+
+    fn columns(&self) -> (Vec3, Vec3, Vec3) {
+        let column = |j: usize| Vec3::new(self.m[0][j], self.m[1][j], self.m[2][j]);
+        (column(0), column(1), column(2))
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self::new(
+            [0, 1, 2, 3]
+                .map(|i| [0, 1, 2, 3].map(|j| (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum())),
+        )
+    }
+}
+
+// A rotation as a unit quaternion, which unlike a matrix can be interpolated.
+#[derive(Copy, Clone)]
+pub struct Quaternion {
+    pub w: f64,
+    pub v: Vec3,
+}
+
+impl Quaternion {
+    pub const IDENTITY: Self = Self {
+        w: 1.0,
+        v: Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        },
+    };
+
+    pub fn from_axis_angle(axis: &Vec3, angle: f64) -> Self {
+        let (sin, cos) = (angle.to_radians() / 2.0).sin_cos();
+        Self {
+            w: cos,
+            v: unit_vector(axis) * sin,
+        }
+    }
+
+    // The axis and the angle in degrees, from 0 to 360
+    pub fn axis_angle(&self) -> (Vec3, f64) {
+        let length = self.v.length();
+        if length < 1e-12 {
+            return (Vec3::new(0.0, 1.0, 0.0), 0.0);
+        }
+        (self.v / length, 2.0 * length.atan2(self.w).to_degrees())
+    }
+
+    fn dot(&self, other: &Self) -> f64 {
+        self.w * other.w + dot(&self.v, &other.v)
+    }
+
+    // The angle in degrees of the shortest rotation from `self` to `other`
+    pub fn angle_to(&self, other: &Self) -> f64 {
+        2.0 * self.dot(other).abs().min(1.0).acos().to_degrees()
+    }
+
+    // Spherical interpolation along the shorter arc, from `self` at 0 to `other` at 1
+    pub fn slerp(&self, other: &Self, f: f64) -> Self {
+        // q and -q are the same rotation, so pick the one closer to `self`.
+        let cos = self.dot(other);
+        let (sign, cos) = if cos < 0.0 { (-1.0, -cos) } else { (1.0, cos) };
+        let (a, b) = if cos > 0.9995 {
+            // Nearly parallel, where a linear blend is as good and stays stable
+            (1.0 - f, f)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (((1.0 - f) * theta).sin() / sin, (f * theta).sin() / sin)
+        };
+        let w = a * self.w + b * sign * other.w;
+        let v = self.v * a + other.v * (b * sign);
+        let length = (w * w + v.length_squared()).sqrt();
+        Self {
+            w: w / length,
+            v: v / length,
+        }
+    }
+
+    pub fn matrix(&self) -> Matrix4 {
+        let (w, x, y, z) = (self.w, self.v.x, self.v.y, self.v.z);
+        Matrix4::new([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: &Matrix4, b: &Matrix4) {
+        for (row_a, row_b) in a.m.iter().zip(&b.m) {
+            for (x, y) in row_a.iter().zip(row_b) {
+                assert!((x - y).abs() < 1e-9, "{:?} != {:?}", a.m, b.m);
+            }
+        }
+    }
+
+    fn skewed() -> Matrix4 {
+        Matrix4::translation(&Vec3::new(1.0, -2.0, 3.0))
+            * Matrix4::rotation(&Vec3::new(1.0, 2.0, 0.5), 37.0)
+            * Matrix4::scaling(&Vec3::new(2.0, 0.5, -3.0))
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        let m = skewed();
+        let inverse = m.inverse().unwrap();
+        assert_near(&(inverse * m), &Matrix4::IDENTITY);
+        assert_near(&(m * inverse), &Matrix4::IDENTITY);
+        assert!(inverse.is_affine());
+    }
+
+    #[test]
+    fn flat_transforms_have_no_inverse() {
+        assert!(Matrix4::scaling(&Vec3::new(1.0, 0.0, 1.0))
+            .inverse()
+            .is_none());
+        assert!(Matrix4::scaling(&Vec3::new(1.0, f64::NAN, 1.0))
+            .inverse()
+            .is_none());
+    }
+
+    #[test]
+    fn normals_stay_perpendicular() {
+        let m = skewed();
+        let normal = m.normal_matrix();
+        // A plane's normal and two directions along it
+        let n = Vec3::new(1.0, 1.0, 1.0);
+        for t in [Vec3::new(1.0, -1.0, 0.0), Vec3::new(0.0, 1.0, -1.0)] {
+            assert!(dot(&normal.vector(&n), &m.vector(&t)).abs() < 1e-9);
+        }
+        // The translation does not reach normals.
+        assert_eq!(normal.m[0][3], 0.0);
+        assert_eq!(normal.m[1][3], 0.0);
+        assert_eq!(normal.m[2][3], 0.0);
+    }
+
+    #[test]
+    fn rotations_are_their_own_normal_matrix() {
+        let r = Matrix4::rotation(&Vec3::new(0.0, 1.0, 0.0), 90.0);
+        assert_near(&r.normal_matrix(), &r);
+        let p = r.point(&Point3::new(1.0, 0.0, 0.0));
+        assert!((p - Point3::new(0.0, 0.0, -1.0)).length() < 1e-12);
+    }
+}
@@ -1,5 +1,4 @@
 use crate::aabb::Aabb;
-use rand::{thread_rng, Rng};
 use std::sync::Arc;
 
 use crate::color::Color;
@@ -7,28 +6,28 @@ use crate::hittable::{HitRecord, Hittable};
 use crate::interval::Interval;
 use crate::material::{Isotropic, Material};
 use crate::ray::Ray;
-use crate::texture::Texture;
+use crate::sampler::{hash, to_unit};
+use crate::scene_file::{DescribeResult, ObjectDesc, SceneWriter};
+use crate::texture::{SolidColor, Texture};
 use crate::vec3::Vec3;
 
 pub struct ConstantMedium {
     boundary: Arc<dyn Hittable>,
     neg_inv_density: f64,
+    albedo: Arc<dyn Texture>,
     phase_function: Arc<dyn Material>,
 }
 
 impl ConstantMedium {
     pub fn new(boundary: Arc<dyn Hittable>, density: f64, albedo: &Color) -> Self {
-        Self {
-            boundary,
-            neg_inv_density: -1.0 / density,
-            phase_function: Arc::new(Isotropic::new(albedo)),
-        }
+        Self::new_tex(boundary, density, Arc::new(SolidColor::new(albedo)))
     }
-    pub fn _new_tex(boundary: Arc<dyn Hittable>, density: f64, tex: Arc<dyn Texture>) -> Self {
+    pub fn new_tex(boundary: Arc<dyn Hittable>, density: f64, tex: Arc<dyn Texture>) -> Self {
         Self {
             boundary,
             neg_inv_density: -1.0 / density,
-            phase_function: Arc::new(Isotropic::_new_tex(tex)),
+            albedo: tex.clone(),
+            phase_function: Arc::new(Isotropic::new_tex(tex)),
         }
     }
 }
@@ -73,17 +72,25 @@ impl Hittable for ConstantMedium {
 
         let ray_len = r.direction().length();
         let dis_in_boundary = (rec2.t - rec1.t) * ray_len;
-        let hit_dis =
-            self.neg_inv_density * f64::log(thread_rng().gen_range(0.0..1.0), std::f64::consts::E);
+        // The ray's seed mixed with where it enters, so that every medium along the ray
+        // scatters independently of the others.
+        let random = to_unit(hash(&[r.seed(), rec1.t.to_bits()]));
+        let hit_dis = self.neg_inv_density * f64::ln(1.0 - random);
 
         if hit_dis > dis_in_boundary {
             return None;
         }
         let t = rec1.t + hit_dis / ray_len;
 
+        // Scattering inside the volume has no surface to orient by, so any frame will do.
         let rec: HitRecord = HitRecord {
             p: r.at(t),
             normal: Vec3::new(1.0, 0.0, 0.0),
+            geometric_normal: Vec3::new(1.0, 0.0, 0.0),
+            tangent: Vec3::new(0.0, 1.0, 0.0),
+            bitangent: Vec3::new(0.0, 0.0, 1.0),
+            dpdu: Vec3::black(),
+            dpdv: Vec3::black(),
             mat: self.phase_function.clone(),
             t,
             front_face: true,
@@ -96,4 +103,12 @@ impl Hittable for ConstantMedium {
     fn bounding_box(&self) -> Aabb {
         self.boundary.bounding_box()
     }
+
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<ObjectDesc> {
+        Ok(ObjectDesc::ConstantMedium {
+            boundary: Box::new(writer.object(self.boundary.as_ref())?),
+            density: -1.0 / self.neg_inv_density,
+            albedo: writer.texture(&self.albedo)?,
+        })
+    }
 }
@@ -0,0 +1,382 @@
+use crate::aabb::Aabb;
+use crate::color::Color;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::{Lambertian, Material};
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene_file::{array, DescribeResult, FaceDesc, ObjectDesc, SceneWriter};
+use crate::texture::VertexColorTexture;
+use crate::triangle::{apply_vertex_data, uv_derivatives};
+use crate::vec3::{cross, dot, unit_vector, Point3, Vec3};
+use std::sync::Arc;
+
+// Most faces kept in one leaf of a mesh's BVH
+const LEAF_FACES: usize = 4;
+
+// One triangle of a mesh, as indices into the mesh's buffers.
+#[derive(Copy, Clone)]
+pub struct Face {
+    pub vertices: [u32; 3],
+    pub normals: Option<[u32; 3]>,
+    pub uvs: Option<[u32; 3]>,
+    // Index into the mesh's materials
+    pub material: u32,
+}
+
+// A node of a mesh's BVH. A leaf covers `count` faces from `start`. An inner node has a
+// count of zero, its left child right after it and its right child at `start`.
+struct Node {
+    bbox: Aabb,
+    start: u32,
+    count: u32,
+}
+
+// Triangles sharing vertex buffers, with a BVH of their own over the faces. Materials are
+// looked up per face, so a whole model can be one primitive.
+pub struct TriangleMesh {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    faces: Vec<Face>,
+    materials: Vec<Arc<dyn Material>>,
+    nodes: Vec<Node>,
+    // Running total of the face areas, for sampling points on the mesh
+    cumulative_area: Vec<f64>,
+}
+
+impl TriangleMesh {
+    pub fn new(
+        positions: Vec<Point3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<(f64, f64)>,
+        faces: Vec<Face>,
+        materials: Vec<Arc<dyn Material>>,
+    ) -> Result<Self, String> {
+        if faces.is_empty() {
+            return Err(String::from("mesh has no faces"));
+        }
+        let in_range = |indices: &[u32; 3], len: usize| indices.iter().all(|&i| (i as usize) < len);
+        for (i, face) in faces.iter().enumerate() {
+            let valid = in_range(&face.vertices, positions.len())
+                && face.normals.map_or(true, |n| in_range(&n, normals.len()))
+                && face.uvs.map_or(true, |uv| in_range(&uv, uvs.len()))
+                && (face.material as usize) < materials.len();
+            if !valid {
+                return Err(format!("face {} refers to an index out of range", i));
+            }
+        }
+
+        let mut mesh = Self {
+            positions,
+            normals,
+            uvs,
+            faces: vec![],
+            materials,
+            nodes: vec![],
+            cumulative_area: vec![],
+        };
+        let boxes: Vec<Aabb> = faces
+            .iter()
+            .map(|face| {
+                let [a, b, c] = mesh.corners(face);
+                Aabb::two_aabb(&Aabb::two_point(&a, &b), &Aabb::two_point(&a, &c))
+            })
+            .collect();
+        let centroids: Vec<Point3> = faces
+            .iter()
+            .map(|face| {
+                let [a, b, c] = mesh.corners(face);
+                (a + b + c) / 3.0
+            })
+            .collect();
+        let mut order: Vec<usize> = (0..faces.len()).collect();
+        mesh.build(&mut order, 0, &boxes, &centroids);
+
+        // Store the faces in tree order, so every leaf covers a contiguous range.
+        mesh.faces = order.iter().map(|&i| faces[i]).collect();
+        let mut total = 0.0;
+        mesh.cumulative_area = mesh
+            .faces
+            .iter()
+            .map(|face| {
+                let [a, b, c] = mesh.corners(face);
+                total += cross(&(b - a), &(c - a)).length() / 2.0;
+                total
+            })
+            .collect();
+        Ok(mesh)
+    }
+
+    fn corners(&self, face: &Face) -> [Point3; 3] {
+        face.vertices.map(|i| self.positions[i as usize])
+    }
+
+    // Split the faces in `order`, which start at `start` in the final face order, at the
+    // median of their centroids along the axis where those spread the most.
+    fn build(&mut self, order: &mut [usize], start: usize, boxes: &[Aabb], centroids: &[Point3]) {
+        let mut bbox = boxes[order[0]].clone();
+        let mut centroid_box = Aabb::two_point(&centroids[order[0]], &centroids[order[0]]);
+        for &i in order.iter() {
+            bbox = Aabb::two_aabb(&bbox, &boxes[i]);
+            centroid_box = Aabb::two_aabb(
+                &centroid_box,
+                &Aabb::two_point(&centroids[i], &centroids[i]),
+            );
+        }
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            bbox,
+            start: start as u32,
+            count: order.len() as u32,
+        });
+        if order.len() <= LEAF_FACES {
+            return;
+        }
+
+        let axis = centroid_box.longest_axis();
+        let mid = order.len() / 2;
+        order.select_nth_unstable_by(mid, |&a, &b| {
+            centroids[a][axis].total_cmp(&centroids[b][axis])
+        });
+        let (left, right) = order.split_at_mut(mid);
+        self.build(left, start, boxes, centroids);
+        self.nodes[index].start = self.nodes.len() as u32;
+        self.nodes[index].count = 0;
+        self.build(right, start + mid, boxes, centroids);
+    }
+
+    // Möller–Trumbore intersection, giving the distance and the weights of the second and
+    // third vertex.
+    fn intersect(&self, face: &Face, r: &Ray, ray_t: &Interval) -> Option<(f64, f64, f64)> {
+        let [a, b, c] = self.corners(face);
+        let e1 = b - a;
+        let e2 = c - a;
+        let pvec = cross(&r.direction(), &e2);
+        let det = dot(&e1, &pvec);
+        if det == 0.0 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = r.origin() - a;
+        let alpha = dot(&tvec, &pvec) * inv_det;
+        if !(0.0..=1.0).contains(&alpha) {
+            return None;
+        }
+        let qvec = cross(&tvec, &e1);
+        let beta = dot(&r.direction(), &qvec) * inv_det;
+        if beta < 0.0 || alpha + beta > 1.0 {
+            return None;
+        }
+        let t = dot(&e2, &qvec) * inv_det;
+        if !ray_t.contains(t) {
+            return None;
+        }
+        Some((t, alpha, beta))
+    }
+
+    fn total_area(&self) -> f64 {
+        *self.cumulative_area.last().unwrap()
+    }
+}
+
+// Geometry from formats with one index per vertex and no materials, such as PLY and STL.
+// Normals, texture coordinates and colors are either empty or given for every vertex.
+#[derive(Default)]
+pub struct VertexMesh {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub colors: Vec<Color>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl VertexMesh {
+    // Replace the normals with the average of the faces around each vertex, weighted by
+    // their area.
+    pub fn smooth_normals(&mut self) {
+        let mut normals = vec![Vec3::new(0.0, 0.0, 0.0); self.positions.len()];
+        for t in &self.triangles {
+            let [a, b, c] = t.map(|i| self.positions[i as usize]);
+            let area_normal = cross(&(b - a), &(c - a));
+            for &i in t {
+                normals[i as usize] += area_normal;
+            }
+        }
+        self.normals = normals
+            .iter()
+            .map(|n| {
+                if n.length_squared() > 0.0 {
+                    unit_vector(n)
+                } else {
+                    *n
+                }
+            })
+            .collect();
+    }
+
+    // A mesh of `material`, or without one, a diffuse mesh colored by its vertex colors
+    // or a plain gray.
+    pub fn into_mesh(self, material: Option<Arc<dyn Material>>) -> Result<TriangleMesh, String> {
+        let VertexMesh {
+            positions,
+            normals,
+            uvs,
+            colors,
+            triangles,
+        } = self;
+        let count = positions.len();
+        for (name, len) in [
+            ("normals", normals.len()),
+            ("texture coordinates", uvs.len()),
+            ("colors", colors.len()),
+        ] {
+            if len != 0 && len != count {
+                return Err(format!("{} vertices but {} {}", count, len, name));
+            }
+        }
+        if triangles.iter().flatten().any(|&i| i as usize >= count) {
+            return Err(String::from("a face refers to a vertex out of range"));
+        }
+
+        let has_normals = !normals.is_empty();
+        let use_colors = material.is_none() && !colors.is_empty();
+        let material: Arc<dyn Material> = match material {
+            Some(material) => material,
+            None if use_colors => {
+                let tex = VertexColorTexture::new(colors, triangles.clone());
+                Arc::new(Lambertian::new_tex(Arc::new(tex)))
+            }
+            None => Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.8))),
+        };
+        // Vertex colors take over the texture coordinates, which then tell faces apart.
+        let uvs = if use_colors {
+            (0..triangles.len())
+                .flat_map(VertexColorTexture::face_uvs)
+                .collect()
+        } else {
+            uvs
+        };
+        let has_uvs = !uvs.is_empty();
+        let faces = triangles
+            .iter()
+            .enumerate()
+            .map(|(i, &t)| Face {
+                vertices: t,
+                normals: has_normals.then_some(t),
+                uvs: match (use_colors, has_uvs) {
+                    (true, _) => Some([0, 1, 2].map(|k| 3 * i as u32 + k)),
+                    (false, true) => Some(t),
+                    (false, false) => None,
+                },
+                material: 0,
+            })
+            .collect();
+        TriangleMesh::new(positions, normals, uvs, faces, vec![material])
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let mut closest = None;
+        let mut ray_t = ray_t;
+        let mut stack = [0_u32; 64];
+        let mut depth = 1;
+        while depth > 0 {
+            depth -= 1;
+            let index = stack[depth] as usize;
+            let node = &self.nodes[index];
+            if !node.bbox.hit(r, ray_t.clone()) {
+                continue;
+            }
+            if node.count == 0 {
+                stack[depth] = node.start;
+                stack[depth + 1] = index as u32 + 1;
+                depth += 2;
+                continue;
+            }
+            let start = node.start as usize;
+            for f in start..start + node.count as usize {
+                if let Some((t, alpha, beta)) = self.intersect(&self.faces[f], r, &ray_t) {
+                    ray_t.max = t;
+                    closest = Some((f, t, alpha, beta));
+                }
+            }
+        }
+
+        let (f, t, alpha, beta) = closest?;
+        let face = &self.faces[f];
+        let [a, b, c] = self.corners(face);
+        let e1 = b - a;
+        let e2 = c - a;
+        let normal = unit_vector(&cross(&e1, &e2));
+        let mut rec = HitRecord::new(
+            &r.at(t),
+            t,
+            &normal,
+            r,
+            self.materials[face.material as usize].clone(),
+            alpha,
+            beta,
+        );
+        let normals = face.normals.map(|n| n.map(|i| self.normals[i as usize]));
+        let uvs = face.uvs.map(|uv| uv.map(|i| self.uvs[i as usize]));
+        let (dpdu, dpdv) = uv_derivatives(&e1, &e2, uvs);
+        apply_vertex_data(&mut rec, &normal, (&dpdu, &dpdv), normals, uvs);
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.nodes[0].bbox.clone()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        if let Some(rec) = self.hit(
+            &Ray::new(origin, direction, time),
+            Interval::new(0.001, f64::INFINITY),
+        ) {
+            let distance_squared = rec.t * rec.t * direction.length_squared();
+            let cosine = dot(direction, &rec.geometric_normal).abs() / direction.length();
+
+            distance_squared / (cosine * self.total_area())
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self, origin: &Point3, _time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        // Uniform over the whole surface: pick a face by area, then a point on it.
+        let target = sampler.get_1d() * self.total_area();
+        let f = self
+            .cumulative_area
+            .partition_point(|&area| area < target)
+            .min(self.faces.len() - 1);
+        let [a, b, c] = self.corners(&self.faces[f]);
+        let (r1, r2) = sampler.get_2d();
+        let p = a + (b - a) * (1.0 - r1.sqrt()) + (c - a) * (r2 * r1.sqrt());
+        p - *origin
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<ObjectDesc> {
+        Ok(ObjectDesc::Mesh {
+            positions: self.positions.iter().map(array).collect(),
+            normals: self.normals.iter().map(array).collect(),
+            uvs: self.uvs.iter().map(|&(u, v)| [u, v]).collect(),
+            faces: self
+                .faces
+                .iter()
+                .map(|face| FaceDesc {
+                    vertices: face.vertices,
+                    normals: face.normals,
+                    uvs: face.uvs,
+                    material: face.material,
+                })
+                .collect(),
+            materials: self
+                .materials
+                .iter()
+                .map(|mat| writer.material(mat))
+                .collect::<DescribeResult<Vec<_>>>()?,
+        })
+    }
+}
@@ -0,0 +1,239 @@
+use crate::color::Color;
+use crate::sampler::Sampler;
+use crate::vec3::{cross, dot, unit_vector, Vec3};
+
+// The GGX (Trowbridge-Reitz) distribution of microfacet normals with Smith's masking and
+// shadowing. Directions are in the local shading frame, with the normal along +z.
+#[derive(Copy, Clone)]
+pub struct TrowbridgeReitz {
+    alpha: f64,
+}
+
+impl TrowbridgeReitz {
+    // `roughness` is the perceptual roughness in [0, 1]; it is squared as is customary.
+    pub fn new(roughness: f64) -> Self {
+        let roughness = roughness.clamp(0.0, 1.0);
+        Self {
+            // Keep near-mirror surfaces numerically sane.
+            alpha: (roughness * roughness).max(1e-3),
+        }
+    }
+
+    // Density of microfacets facing `wm`.
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        if wm.z <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let t = wm.z * wm.z * (a2 - 1.0) + 1.0;
+        a2 / (std::f64::consts::PI * t * t)
+    }
+
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0.0 {
+            return f64::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        (-1.0 + (1.0 + self.alpha * self.alpha * tan2).sqrt()) / 2.0
+    }
+
+    // Fraction of microfacets visible from `w`.
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Fraction of microfacets visible from both directions (height-correlated).
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Sample a microfacet normal visible from `wo`, following Heitz's "Sampling the GGX
+    // Distribution of Visible Normals".
+    pub fn sample_wm(&self, wo: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (u1, u2) = sampler.get_2d();
+        // Stretch the view direction so the distribution becomes a hemisphere.
+        let vh = unit_vector(&Vec3::new(self.alpha * wo.x, self.alpha * wo.y, wo.z));
+        let lensq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if lensq > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / lensq.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = cross(&vh, &t1);
+
+        // Sample the projected half disk.
+        let r = u1.sqrt();
+        let phi = 2.0 * std::f64::consts::PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        unit_vector(&Vec3::new(
+            self.alpha * nh.x,
+            self.alpha * nh.y,
+            nh.z.max(1e-6),
+        ))
+    }
+
+    // Density of directions `wi` reflected off normals drawn by `sample_wm`.
+    pub fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let wm = unit_vector(&(*wo + *wi));
+        self.g1(wo) * self.d(&wm) / (4.0 * wo.z)
+    }
+}
+
+// How much light a surface reflects at each angle.
+#[derive(Copy, Clone)]
+pub enum Fresnel {
+    // Schlick's approximation from the reflectance at normal incidence
+    Schlick { f0: Color },
+    // Exact reflectance of a conductor with complex index of refraction eta + i k
+    Conductor { eta: Color, k: Color },
+}
+
+impl Fresnel {
+    pub fn eval(&self, cos_theta: f64) -> Color {
+        let cos_theta = cos_theta.clamp(0.0, 1.0);
+        match self {
+            Fresnel::Schlick { f0 } => *f0 + (Color::white() - *f0) * (1.0 - cos_theta).powi(5),
+            Fresnel::Conductor { eta, k } => Color::new(
+                fresnel_conductor(cos_theta, eta.x, k.x),
+                fresnel_conductor(cos_theta, eta.y, k.y),
+                fresnel_conductor(cos_theta, eta.z, k.z),
+            ),
+        }
+    }
+}
+
+// Unpolarized reflectance of an interface between dielectrics, where `eta` is the index of
+// refraction of the far side relative to the side the light arrives from.
+pub fn fresnel_dielectric(cos_theta: f64, eta: f64) -> f64 {
+    let (cos_i, eta) = if cos_theta < 0.0 {
+        (-cos_theta.max(-1.0), 1.0 / eta)
+    } else {
+        (cos_theta.min(1.0), eta)
+    };
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        // Total internal reflection
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+// Unpolarized reflectance of a conductor for light arriving from a dielectric.
+fn fresnel_conductor(cos_theta: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_theta * cos_theta;
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rp + rs)
+}
+
+// Cosine weighted reflectance of the specular lobe for light from `wi` leaving along `wo`.
+pub fn specular(distribution: &TrowbridgeReitz, fresnel: &Fresnel, wo: &Vec3, wi: &Vec3) -> Color {
+    if wo.z <= 0.0 || wi.z <= 0.0 {
+        return Color::black();
+    }
+    let wm = unit_vector(&(*wo + *wi));
+    let f = fresnel.eval(dot(wo, &wm));
+    f * (distribution.d(&wm) * distribution.g(wo, wi) / (4.0 * wo.z))
+}
+
+// Direction of `wo` refracted through a surface with normal `n` on its side, or `None` on
+// total internal reflection. `eta` is the relative index of refraction of the far side.
+fn refract(wo: &Vec3, n: &Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = dot(n, wo);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-*wo / eta + *n * (cos_i / eta - cos_t))
+}
+
+// The microfacet normal that turns `wo` into `wi` by reflection or refraction, facing +z,
+// or `None` for configurations no microfacet produces.
+fn dielectric_half_vector(eta: f64, wo: &Vec3, wi: &Vec3) -> Option<Vec3> {
+    let reflect = wi.z > 0.0;
+    let wm = if reflect { *wo + *wi } else { *wi * eta + *wo };
+    if wo.z <= 0.0 || wi.z == 0.0 || wm.length_squared() == 0.0 {
+        return None;
+    }
+    let wm = unit_vector(&wm);
+    let wm = if wm.z < 0.0 { -wm } else { wm };
+    // Both directions must lie on the correct sides of the microfacet.
+    if dot(&wm, wi) * wi.z < 0.0 || dot(&wm, wo) * wo.z < 0.0 {
+        return None;
+    }
+    Some(wm)
+}
+
+// Cosine weighted BSDF of a rough interface between dielectrics (Walter et al., "Microfacet
+// Models for Refraction through Rough Surfaces"). `eta` is the index of refraction below the
+// surface relative to the side of `wo`, which is above it.
+pub fn dielectric(distribution: &TrowbridgeReitz, eta: f64, wo: &Vec3, wi: &Vec3) -> f64 {
+    let Some(wm) = dielectric_half_vector(eta, wo, wi) else {
+        return 0.0;
+    };
+    let r = fresnel_dielectric(dot(wo, &wm), eta);
+    if wi.z > 0.0 {
+        return distribution.d(&wm) * distribution.g(wo, wi) * r / (4.0 * wo.z);
+    }
+    let denom = dot(wi, &wm) + dot(wo, &wm) / eta;
+    let denom = denom * denom;
+    // Radiance is compressed into the smaller solid angle of the denser medium.
+    distribution.d(&wm) * distribution.g(wo, wi) * (1.0 - r) * (dot(wi, &wm) * dot(wo, &wm)).abs()
+        / (wo.z * denom * eta * eta)
+}
+
+// Density of the directions `sample_dielectric` produces.
+pub fn dielectric_pdf(distribution: &TrowbridgeReitz, eta: f64, wo: &Vec3, wi: &Vec3) -> f64 {
+    let Some(wm) = dielectric_half_vector(eta, wo, wi) else {
+        return 0.0;
+    };
+    let r = fresnel_dielectric(dot(wo, &wm), eta);
+    let visible = distribution.g1(wo) * distribution.d(&wm) * dot(wo, &wm).max(0.0) / wo.z;
+    if wi.z > 0.0 {
+        r * visible / (4.0 * dot(wo, &wm))
+    } else {
+        let denom = dot(wi, &wm) + dot(wo, &wm) / eta;
+        (1.0 - r) * visible * dot(wi, &wm).abs() / (denom * denom)
+    }
+}
+
+// Reflect off or refract through a visible microfacet, choosing by its Fresnel reflectance.
+pub fn sample_dielectric(
+    distribution: &TrowbridgeReitz,
+    eta: f64,
+    wo: &Vec3,
+    sampler: &mut dyn Sampler,
+) -> Vec3 {
+    let wm = distribution.sample_wm(wo, sampler);
+    let r = fresnel_dielectric(dot(wo, &wm), eta);
+    let reflected = wm * (2.0 * dot(wo, &wm)) - *wo;
+    if sampler.get_1d() < r {
+        return reflected;
+    }
+    refract(wo, &wm, eta).unwrap_or(reflected)
+}
@@ -1,111 +1,330 @@
-use crate::bvh::BvhNode;
 use crate::color::Color;
 use crate::hittable_list::HittableList;
-use crate::material::{Lambertian, Material, Metal};
-use crate::texture::ImageTexture;
-use crate::triangle::Triangle;
-use crate::vec3::Point3;
+use crate::material::{
+    Dielectric, DiffuseLight, Lambertian, Material, Microfacet, NormalMapKind, NormalMapped,
+    Opacity, Principled, PrincipledParams,
+};
+use crate::mesh::{Face, TriangleMesh};
+use crate::spectrum::Ior;
+use crate::texture::{ImageTexture, SolidColor, Texture};
+use crate::vec3::{Point3, Vec3};
+use console::style;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-pub fn read_obj(obj_filename: &str, scale: f64) -> HittableList {
-    let mut object = HittableList::new();
+// Load an OBJ model as one mesh, with its MTL libraries and textures looked up relative
+// to the OBJ file. Positions are multiplied by `scale`.
+pub fn read_obj(path: &Path, scale: f64) -> Result<HittableList, String> {
+    let error = |message: &dyn std::fmt::Display| format!("{}: {}", path.display(), message);
+    let file = File::open(path).map_err(|err| error(&err))?;
+    let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
 
-    let filename = String::from(obj_filename);
-
-    let obj = tobj::load_obj(
-        format!("objects/{}", filename),
+    let libraries = RefCell::new(vec![]);
+    let (models, materials) = tobj::load_obj_buf(
+        &mut BufReader::new(file),
         &tobj::LoadOptions {
             single_index: false,
             triangulate: false,
             ignore_points: true,
             ignore_lines: true,
         },
-    );
-    assert!(obj.is_ok());
-
-    let (models, materials) = obj.expect("Failed to load OBJ file");
-    let mut mat_is_ok = false;
-    if materials.is_ok() {
-        mat_is_ok = true;
-    } else {
-    }
+        |library| {
+            let library = dir.join(library);
+            libraries.borrow_mut().push(library.clone());
+            tobj::load_mtl(library)
+        },
+    )
+    .map_err(|err| error(&err))?;
 
-    for (_i, m) in models.iter().enumerate() {
+    let mut loader = MtlLoader {
+        dir,
+        scale,
+        images: HashMap::new(),
+    };
+    let mut mesh_materials: Vec<Arc<dyn Material>> = match materials {
+        Ok(materials) => materials.iter().map(|m| loader.material(m)).collect(),
+        Err(err) => {
+            // The geometry is still worth rendering, so carry on without the library.
+            let library = libraries.borrow().last().cloned().unwrap_or_default();
+            warn(&format!(
+                "{}: {}, using a gray material",
+                library.display(),
+                err
+            ));
+            vec![]
+        }
+    };
+    // Faces without a usable material of their own share a gray one at the end.
+    let default_material = mesh_materials.len() as u32;
+    mesh_materials.push(Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.8))));
+
+    // All the models go into one mesh, so their indices are offset past the earlier ones.
+    let mut positions = vec![];
+    let mut normals = vec![];
+    let mut uvs = vec![];
+    let mut faces = vec![];
+    for m in &models {
         let mesh = &m.mesh;
+        let material = match mesh.material_id {
+            Some(id) if id < default_material as usize => id as u32,
+            _ => default_material,
+        };
+
+        // Polygons are split into fans of triangles. Normals and texture coordinates are
+        // only used when every corner has them.
+        let arities: Vec<usize> = if mesh.face_arities.is_empty() {
+            vec![3; mesh.indices.len() / 3]
+        } else {
+            mesh.face_arities.iter().map(|&n| n as usize).collect()
+        };
+        let has_normals =
+            !mesh.normals.is_empty() && mesh.normal_indices.len() == mesh.indices.len();
+        let has_uvs =
+            !mesh.texcoords.is_empty() && mesh.texcoord_indices.len() == mesh.indices.len();
+        let position_offset = positions.len() as u32;
+        let normal_offset = normals.len() as u32;
+        let uv_offset = uvs.len() as u32;
+        let offset = |indices: &[u32], corners: [usize; 3], offset: u32| {
+            corners.map(|k| indices[k] + offset)
+        };
 
-        let mut mat: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.8)));
-        if mat_is_ok {
-            if let Some(id) = mesh.material_id {
-                if let Some(diffuse) = materials.clone().unwrap()[id].diffuse {
-                    mat = Arc::new(Lambertian::new(Color::new(
-                        diffuse[0] as f64,
-                        diffuse[1] as f64,
-                        diffuse[2] as f64,
-                    )));
-                } else if let Some(specular) = materials.clone().unwrap()[id].specular {
-                    if let Some(shininess) = materials.clone().unwrap()[id].shininess {
-                        mat = Arc::new(Metal::new(
-                            Color::new(specular[0] as f64, specular[1] as f64, specular[2] as f64),
-                            shininess as f64,
-                        ));
-                    } else {
-                    }
-                } else if let Some(diffuse_texture) =
-                    &materials.clone().unwrap()[id].diffuse_texture
-                {
-                    let tex = Arc::new(ImageTexture::new(diffuse_texture));
-                    mat = Arc::new(Lambertian::new_tex(tex));
-                } else {
+        let mut first = 0;
+        for arity in arities {
+            for i in 1..arity.saturating_sub(1) {
+                let corners = [first, first + i, first + i + 1];
+                faces.push(Face {
+                    vertices: offset(&mesh.indices, corners, position_offset),
+                    normals: has_normals
+                        .then(|| offset(&mesh.normal_indices, corners, normal_offset)),
+                    uvs: has_uvs.then(|| offset(&mesh.texcoord_indices, corners, uv_offset)),
+                    material,
+                });
+            }
+            first += arity;
+        }
+
+        positions.extend(
+            mesh.positions
+                .chunks_exact(3)
+                .map(|p| Point3::new(p[0] as f64, p[1] as f64, p[2] as f64) * scale),
+        );
+        normals.extend(
+            mesh.normals
+                .chunks_exact(3)
+                .map(|n| Vec3::new(n[0] as f64, n[1] as f64, n[2] as f64)),
+        );
+        uvs.extend(
+            mesh.texcoords
+                .chunks_exact(2)
+                .map(|uv| (uv[0] as f64, uv[1] as f64)),
+        );
+    }
+
+    let mesh = TriangleMesh::new(positions, normals, uvs, faces, mesh_materials)
+        .map_err(|err| error(&err))?;
+    Ok(HittableList::new_from(Arc::new(mesh)))
+}
+
+pub fn warn(message: &str) {
+    eprintln!("{} {}", style("warning:").yellow(), message);
+}
+
+fn color(c: [f32; 3]) -> Color {
+    Color::new(c[0] as f64, c[1] as f64, c[2] as f64)
+}
+
+// Turns MTL materials into ours, sharing the images they refer to.
+struct MtlLoader {
+    dir: PathBuf,
+    scale: f64,
+    images: HashMap<(PathBuf, bool), Arc<dyn Texture>>,
+}
+
+impl MtlLoader {
+    fn material(&mut self, m: &tobj::Material) -> Arc<dyn Material> {
+        let params = &m.unknown_param;
+        let diffuse = self.color_texture(m.diffuse_texture.as_deref(), m.diffuse, 0.8);
+
+        // Anything that glows is a light, whatever else it says.
+        let emission_map = params.get("map_Ke").map(|map| map.as_str());
+        if emission_map.is_some() || m.emissive.map_or(false, |ke| ke != [0.0; 3]) {
+            let emission = self.color_texture(emission_map, m.emissive, 0.0);
+            return Arc::new(DiffuseLight::new_tex(emission));
+        }
+
+        let opacity = match (m.dissolve, params.get("Tr").and_then(|tr| parse(tr))) {
+            (Some(d), _) => d as f64,
+            (None, Some(tr)) => 1.0 - tr,
+            (None, None) => 1.0,
+        };
+        let ior = m.optical_density.map_or(1.5, |ni| ni as f64);
+        let illum = m.illumination_model.unwrap_or(
+            if m.specular.map_or(false, |ks| ks != [0.0; 3]) && m.shininess.is_some() {
+                2
+            } else {
+                1
+            },
+        );
+        // Phong exponents become GGX roughness through alpha = sqrt(2 / (Ns + 2)).
+        let roughness = m
+            .shininess
+            .map_or(0.5, |ns| (2.0 / (ns.max(0.0) as f64 + 2.0)).powf(0.25));
+        // Glass models are only taken literally for materials that are see-through, as
+        // some exporters write illum 4 for everything.
+        let glass = matches!(illum, 4 | 6 | 7 | 9) && opacity < 1.0;
+
+        let mut material: Arc<dyn Material> = if let Some(principled) = self.principled(m) {
+            principled
+        } else if glass {
+            Arc::new(Dielectric::glass(Ior::Constant(ior), 0.0, Color::black()))
+        } else {
+            match illum {
+                // Reflection without refraction: a metal tinted by Ks.
+                3 | 5 | 8 => {
+                    let specular =
+                        self.color_texture(m.specular_texture.as_deref(), m.specular, 1.0);
+                    Arc::new(Microfacet::new(specular, roughness, 1.0))
                 }
+                // A highlight over the diffuse color: plastic.
+                2 | 4 | 6 | 7 | 9 if m.specular.map_or(false, |ks| ks != [0.0; 3]) => {
+                    Arc::new(Microfacet::new(diffuse, roughness, 0.0))
+                }
+                // Color only, or diffuse only.
+                _ => Arc::new(Lambertian::new_tex(diffuse)),
+            }
+        };
+
+        if let Some(map) = params.get("norm") {
+            let (file, strength) = map_file(map);
+            if let Some(tex) = self.image(&file, true) {
+                let strength = strength.unwrap_or(1.0);
+                material = Arc::new(NormalMapped::new(
+                    material,
+                    tex,
+                    NormalMapKind::Normal { strength },
+                ));
+            }
+        } else if let Some(map) = &m.normal_texture {
+            // Heights are in model units, times the `-bm` multiplier.
+            let (file, multiplier) = map_file(map);
+            if let Some(tex) = self.image(&file, true) {
+                let scale = multiplier.unwrap_or(1.0) * self.scale;
+                material = Arc::new(NormalMapped::new(
+                    material,
+                    tex,
+                    NormalMapKind::Bump { scale },
+                ));
             }
         }
 
-        if mesh.face_arities.len() != 0 {
-            let mut next_face = 0;
-            for f in 0..mesh.face_arities.len() {
-                let end = next_face + mesh.face_arities[f] as usize;
-                let face_indices: Vec<_> = mesh.indices[next_face..end].iter().collect();
-                next_face = end;
-
-                let mut p: [Point3; 3] = [Color::white(); 3];
-                let mut t = 0;
-                let p0 = *face_indices[0] as usize;
-                p[0] = Point3::new(
-                    mesh.positions[p0 * 3] as f64 * scale,
-                    mesh.positions[p0 * 3 + 1] as f64 * scale,
-                    mesh.positions[p0 * 3 + 2] as f64 * scale,
-                );
-                for v in face_indices {
-                    t += 1;
-                    p[1] = p[2];
-                    p[2] = Point3::new(
-                        mesh.positions[*v as usize * 3] as f64 * scale,
-                        mesh.positions[*v as usize * 3 + 1] as f64 * scale,
-                        mesh.positions[*v as usize * 3 + 2] as f64 * scale,
-                    );
-
-                    if t >= 3 {
-                        object.add(Arc::new(Triangle::new(&p[0], &p[1], &p[2], mat.clone())));
-                    }
+        if !glass {
+            let map = m.dissolve_texture.as_deref().map(map_file);
+            if let Some(tex) = map.and_then(|(file, _)| self.image(&file, true)) {
+                material = Arc::new(Opacity::new(material, tex));
+            } else if opacity < 1.0 {
+                let tex = Arc::new(SolidColor::new(&Color::new(opacity, opacity, opacity)));
+                material = Arc::new(Opacity::new(material, tex));
+            }
+        }
+        material
+    }
+
+    // Physically based materials written with the `Pr`/`Pm`/`Ps`/`Pc`/`Pcr` extension to
+    // MTL, each of which may also be given as a `map_` texture.
+    fn principled(&mut self, m: &tobj::Material) -> Option<Arc<dyn Material>> {
+        const KEYS: [&str; 5] = ["Pr", "Pm", "Ps", "Pc", "Pcr"];
+        let params = &m.unknown_param;
+        if !KEYS
+            .iter()
+            .any(|key| params.contains_key(*key) || params.contains_key(&format!("map_{}", key)))
+        {
+            return None;
+        }
+
+        let mut principled = PrincipledParams {
+            base_color: self.color_texture(m.diffuse_texture.as_deref(), m.diffuse, 0.8),
+            ..PrincipledParams::default()
+        };
+        let mut scalar = |key: &str, default: Arc<dyn Texture>| -> Arc<dyn Texture> {
+            if let Some(map) = params.get(&format!("map_{}", key)) {
+                if let Some(tex) = self.image(&map_file(map).0, true) {
+                    return tex;
                 }
             }
+            match params.get(key).and_then(|v| parse(v)) {
+                Some(v) => Arc::new(SolidColor::new(&Color::new(v, v, v))),
+                None => default,
+            }
+        };
+        principled.roughness = scalar("Pr", principled.roughness);
+        principled.metallic = scalar("Pm", principled.metallic);
+        principled.sheen = scalar("Ps", principled.sheen);
+        principled.clearcoat = scalar("Pc", principled.clearcoat);
+        principled.clearcoat_roughness = scalar("Pcr", principled.clearcoat_roughness);
+        if let Some(ior) = m.optical_density {
+            principled.ior = ior as f64;
+        }
+        Some(Arc::new(Principled::new(principled)))
+    }
+
+    // A color map if it loads, otherwise the constant color, otherwise gray `default`.
+    fn color_texture(
+        &mut self,
+        map: Option<&str>,
+        color: Option<[f32; 3]>,
+        default: f64,
+    ) -> Arc<dyn Texture> {
+        if let Some(tex) = map.and_then(|map| self.image(&map_file(map).0, false)) {
+            return tex;
+        }
+        let color = color.map_or(Color::new(default, default, default), self::color);
+        Arc::new(SolidColor::new(&color))
+    }
+
+    // Images are shared between materials. Missing ones are left out with a warning.
+    fn image(&mut self, file: &str, linear: bool) -> Option<Arc<dyn Texture>> {
+        let path = self.dir.join(file.replace('\\', "/"));
+        if let Some(tex) = self.images.get(&(path.clone(), linear)) {
+            return Some(tex.clone());
+        }
+        if !path.is_file() {
+            warn(&format!("{}: texture not found", path.display()));
+            return None;
+        }
+        let name = path.to_string_lossy();
+        let tex: Arc<dyn Texture> = if linear {
+            Arc::new(ImageTexture::linear(&name))
         } else {
-            let mut p: [Point3; 3] = [Color::white(); 3];
-            let mut t = 0;
-            for v in &mesh.indices {
-                p[t] = Point3::new(
-                    mesh.positions[*v as usize * 3] as f64 * scale,
-                    mesh.positions[*v as usize * 3 + 1] as f64 * scale,
-                    mesh.positions[*v as usize * 3 + 2] as f64 * scale,
-                );
-                t += 1;
-                if t == 3 {
-                    object.add(Arc::new(Triangle::new(&p[0], &p[1], &p[2], mat.clone())));
-                    t = 0;
-                }
+            Arc::new(ImageTexture::new(&name))
+        };
+        self.images.insert((path, linear), tex.clone());
+        Some(tex)
+    }
+}
+
+fn parse(value: &str) -> Option<f64> {
+    value.trim().parse().ok()
+}
+
+// Split a texture statement such as `-bm 0.5 -s 2 2 bricks.png` into the file name and
+// the `-bm` multiplier. Other options are skipped.
+fn map_file(statement: &str) -> (String, Option<f64>) {
+    let mut words = statement.split_whitespace().peekable();
+    let mut multiplier = None;
+    while let Some(option) = words.next_if(|word| word.starts_with('-')) {
+        match option {
+            "-bm" => multiplier = words.next().and_then(parse),
+            // Options taking a word rather than numbers
+            "-blendu" | "-blendv" | "-cc" | "-clamp" | "-imfchan" | "-type" => {
+                words.next();
             }
+            // Everything else takes one to three numbers
+            _ => while words.next_if(|word| parse(word).is_some()).is_some() {},
         }
     }
-    object = HittableList::new_from(Arc::new(BvhNode::from_list(&mut object)));
-    object
+    (words.collect::<Vec<_>>().join(" "), multiplier)
 }
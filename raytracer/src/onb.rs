@@ -1,4 +1,4 @@
-use crate::vec3::{cross, unit_vector, Vec3};
+use crate::vec3::{cross, dot, unit_vector, Vec3};
 use std::ops::{Index, IndexMut};
 
 pub struct Onb {
@@ -33,6 +33,10 @@ impl Onb {
     pub fn local(&self, a: &Vec3) -> Vec3 {
         self.u() * a.x + self.v() * a.y + self.w() * a.z
     }
+    // The inverse of `local`: express a world space vector in this basis.
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(dot(a, &self.u()), dot(a, &self.v()), dot(a, &self.w()))
+    }
 }
 
 impl Index<usize> for Onb {
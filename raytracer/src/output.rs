@@ -0,0 +1,294 @@
+use crate::camera::Camera;
+use crate::color::Color;
+use image::codecs::hdr::HdrEncoder;
+use image::codecs::tiff::TiffEncoder;
+use image::{ColorType, DynamicImage, ImageBuffer, ImageOutputFormat, Rgb, RgbImage};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum ImageFormat {
+    // 8-bit, gamma encoded
+    Jpeg,
+    Png,
+    // 16-bit, gamma encoded
+    Tiff,
+    // Linear floating point radiance
+    Hdr,
+    Exr,
+    Pfm,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            "png" => Some(ImageFormat::Png),
+            "tif" | "tiff" => Some(ImageFormat::Tiff),
+            "hdr" => Some(ImageFormat::Hdr),
+            "exr" => Some(ImageFormat::Exr),
+            "pfm" => Some(ImageFormat::Pfm),
+            _ => None,
+        }
+    }
+}
+
+pub const EXTENSIONS: &str = "jpg, png, tif, hdr, exr or pfm";
+
+// Write the camera's framebuffer, picking the format from the file extension. Float formats
+// keep the raw linear radiance; the others go through the camera's tone mapping first.
+pub fn write_image(path: &Path, camera: &Camera) -> Result<(), String> {
+    let image = Image {
+        width: camera.image_width,
+        height: camera.image_height,
+        pixels: &camera.framebuffer,
+        quality: camera.quality,
+    };
+    image.write(path, |c| camera.tone_mapping.apply(c))
+}
+
+// Write how many samples every pixel took, black for the fewest and white for the most.
+pub fn write_heat_map(path: &Path, camera: &Camera) -> Result<(), String> {
+    let least = camera.sample_counts.iter().copied().min().unwrap_or(0);
+    let most = camera.sample_counts.iter().copied().max().unwrap_or(0);
+    let range = (most - least).max(1) as f64;
+    let heat: Vec<Color> = camera
+        .sample_counts
+        .iter()
+        .map(|&count| heat_color((count - least) as f64 / range))
+        .collect();
+    let image = Image {
+        width: camera.image_width,
+        height: camera.image_height,
+        pixels: &heat,
+        quality: camera.quality,
+    };
+    image.write(path, |c| c)
+}
+
+// Black through red and yellow to white.
+fn heat_color(t: f64) -> Color {
+    let channel = |start: f64| ((t - start) * 3.0).clamp(0.0, 1.0);
+    Color::new(channel(0.0), channel(1.0 / 3.0), channel(2.0 / 3.0))
+}
+
+struct Image<'a> {
+    width: u32,
+    height: u32,
+    // Linear values, row by row from the top left
+    pixels: &'a [Color],
+    quality: u8,
+}
+
+impl Image<'_> {
+    // `display` encodes a linear value for the 8 and 16-bit formats.
+    fn write(&self, path: &Path, display: impl Fn(Color) -> Color) -> Result<(), String> {
+        let format = ImageFormat::from_path(path)
+            .ok_or_else(|| format!("unsupported image format, expected {}", EXTENSIONS))?;
+        let file = File::create(path).map_err(|err| err.to_string())?;
+        let mut out = BufWriter::new(file);
+        let (width, height) = (self.width, self.height);
+        let pixel = |i: u32, j: u32| display(self.pixels[(j * width + i) as usize]);
+
+        match format {
+            ImageFormat::Jpeg | ImageFormat::Png => {
+                let img = RgbImage::from_fn(width, height, |i, j| pixel(i, j).write_color());
+                let output_format = if format == ImageFormat::Jpeg {
+                    ImageOutputFormat::Jpeg(self.quality)
+                } else {
+                    ImageOutputFormat::Png
+                };
+                DynamicImage::ImageRgb8(img)
+                    .write_to(&mut out, output_format)
+                    .map_err(|err| err.to_string())?;
+            }
+            ImageFormat::Tiff => {
+                // The encoder expects 16-bit samples as native-endian bytes.
+                let img: ImageBuffer<Rgb<u16>, Vec<u16>> =
+                    ImageBuffer::from_fn(width, height, |i, j| pixel(i, j).write_color16());
+                let bytes: Vec<u8> = img.iter().flat_map(|v| v.to_ne_bytes()).collect();
+                TiffEncoder::new(&mut out)
+                    .encode(&bytes, width, height, ColorType::Rgb16)
+                    .map_err(|err| err.to_string())?;
+            }
+            ImageFormat::Hdr => {
+                let pixels: Vec<Rgb<f32>> = self
+                    .pixels
+                    .iter()
+                    .map(|c| {
+                        let c = c.finite();
+                        Rgb([c.x as f32, c.y as f32, c.z as f32])
+                    })
+                    .collect();
+                HdrEncoder::new(&mut out)
+                    .encode(&pixels, width as usize, height as usize)
+                    .map_err(|err| err.to_string())?;
+            }
+            ImageFormat::Exr => {
+                write_exr(&mut out, width, height, self.pixels).map_err(|err| err.to_string())?
+            }
+            ImageFormat::Pfm => {
+                write_pfm(&mut out, width, height, self.pixels).map_err(|err| err.to_string())?
+            }
+        }
+        out.flush().map_err(|err| err.to_string())
+    }
+}
+
+// Portable float map: a tiny text header followed by little-endian floats, bottom row first.
+fn write_pfm(
+    out: &mut impl Write,
+    width: u32,
+    height: u32,
+    pixels: &[Color],
+) -> std::io::Result<()> {
+    write!(out, "PF\n{} {}\n-1.0\n", width, height)?;
+    for j in (0..height).rev() {
+        for i in 0..width {
+            let c = pixels[(j * width + i) as usize].finite();
+            for v in [c.x, c.y, c.z] {
+                out.write_all(&(v as f32).to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Single-part, uncompressed, scanline OpenEXR file with 32-bit float R, G and B channels.
+fn write_exr(
+    out: &mut impl Write,
+    width: u32,
+    height: u32,
+    pixels: &[Color],
+) -> std::io::Result<()> {
+    const FLOAT: i32 = 2;
+    let w = width as i32;
+    let h = height as i32;
+
+    let mut header: Vec<u8> = vec![];
+    header.extend_from_slice(&20000630_i32.to_le_bytes());
+    header.extend_from_slice(&2_i32.to_le_bytes());
+
+    // Channels must be listed in alphabetical order.
+    let mut channels: Vec<u8> = vec![];
+    for name in ["B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&FLOAT.to_le_bytes());
+        channels.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+        channels.extend_from_slice(&1_i32.to_le_bytes()); // x sampling
+        channels.extend_from_slice(&1_i32.to_le_bytes()); // y sampling
+    }
+    channels.push(0);
+    let window: Vec<u8> = [0, 0, w - 1, h - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+
+    let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+        header.extend_from_slice(name.as_bytes());
+        header.push(0);
+        header.extend_from_slice(kind.as_bytes());
+        header.push(0);
+        header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        header.extend_from_slice(value);
+    };
+    attribute("channels", "chlist", &channels);
+    attribute("compression", "compression", &[0]);
+    attribute("dataWindow", "box2i", &window);
+    attribute("displayWindow", "box2i", &window);
+    attribute("lineOrder", "lineOrder", &[0]);
+    attribute("pixelAspectRatio", "float", &1.0_f32.to_le_bytes());
+    attribute("screenWindowCenter", "v2f", &[0; 8]);
+    attribute("screenWindowWidth", "float", &1.0_f32.to_le_bytes());
+    header.push(0);
+
+    // Offset table: one entry per scanline block, each holding y, byte count and the pixels.
+    let line_size = width as u64 * 3 * 4;
+    let first_line = header.len() as u64 + height as u64 * 8;
+    for j in 0..height as u64 {
+        header.extend_from_slice(&(first_line + j * (8 + line_size)).to_le_bytes());
+    }
+    out.write_all(&header)?;
+
+    for j in 0..height {
+        out.write_all(&(j as i32).to_le_bytes())?;
+        out.write_all(&(line_size as i32).to_le_bytes())?;
+        let row = &pixels[(j * width) as usize..((j + 1) * width) as usize];
+        for channel in [2, 1, 0] {
+            for c in row {
+                out.write_all(&(c.finite()[channel] as f32).to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    fn floats(bytes: &[u8]) -> Vec<f32> {
+        bytes
+            .chunks(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect()
+    }
+
+    fn int(bytes: &[u8], at: usize) -> i32 {
+        i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn pfm_writes_rows_bottom_up() {
+        let pixels = [
+            Color::new(1.0, 2.0, 3.0),
+            Color::new(4.0, f64::NAN, f64::INFINITY),
+        ];
+        let mut out = vec![];
+        write_pfm(&mut out, 1, 2, &pixels).unwrap();
+        let header = b"PF\n1 2\n-1.0\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(floats(&out[header.len()..]), [4.0, 0.0, 0.0, 1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn exr_offsets_point_at_scanlines() {
+        let pixels = [
+            Color::new(1.0, 2.0, 3.0),
+            Color::new(4.0, 5.0, 6.0),
+            Color::new(7.0, 8.0, 9.0),
+            Color::new(10.0, 11.0, 12.0),
+        ];
+        let mut out = vec![];
+        write_exr(&mut out, 2, 2, &pixels).unwrap();
+        assert_eq!(int(&out, 0), 20000630);
+        assert_eq!(int(&out, 4), 2);
+
+        // The header ends with an empty attribute name, followed by the offset table.
+        let table = out
+            .windows(b"screenWindowWidth".len())
+            .position(|w| w == b"screenWindowWidth")
+            .unwrap()
+            + b"screenWindowWidth\0float\0".len()
+            + 4
+            + 4
+            + 1;
+        let line_size = 2 * 3 * 4;
+        for j in 0..2 {
+            let at = table + j * 8;
+            let offset = u64::from_le_bytes(out[at..at + 8].try_into().unwrap()) as usize;
+            assert_eq!(int(&out, offset), j as i32);
+            assert_eq!(int(&out, offset + 4), line_size as i32);
+            // Channels in alphabetical order, B G R, each a run over the row
+            let row = floats(&out[offset + 8..offset + 8 + line_size]);
+            let (a, b) = (&pixels[2 * j], &pixels[2 * j + 1]);
+            let expected = [a.z, b.z, a.y, b.y, a.x, b.x].map(|v| v as f32);
+            assert_eq!(row, expected);
+        }
+        assert_eq!(out.len(), table + 2 * 8 + 2 * (8 + line_size));
+    }
+}
@@ -1,14 +1,15 @@
 use crate::hittable::Hittable;
+use crate::microfacet::{self, TrowbridgeReitz};
 use crate::onb::Onb;
+use crate::sampler::Sampler;
 use crate::vec3::{dot, random_cosine_direction, random_unit_vector, unit_vector, Point3, Vec3};
-use rand::{thread_rng, Rng};
 use std::sync::Arc;
 
 pub trait Pdf: Send + Sync {
     fn value(&self, _dir: &Vec3) -> f64 {
         0.0
     }
-    fn generate(&self) -> Vec3 {
+    fn generate(&self, _sampler: &mut dyn Sampler) -> Vec3 {
         Vec3::black()
     }
 }
@@ -25,8 +26,8 @@ impl Pdf for SpherePDF {
     fn value(&self, _dir: &Vec3) -> f64 {
         1.0 / (4.0 * std::f64::consts::PI)
     }
-    fn generate(&self) -> Vec3 {
-        random_unit_vector()
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        random_unit_vector(sampler)
     }
 }
 
@@ -46,31 +47,98 @@ impl Pdf for CosinePDF {
         let cosine_theta = dot(&unit_vector(dir), &self.uvw.w());
         f64::max(0.0, cosine_theta / std::f64::consts::PI)
     }
-    fn generate(&self) -> Vec3 {
-        self.uvw.local(&random_cosine_direction())
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        self.uvw.local(&random_cosine_direction(sampler))
+    }
+}
+
+// Reflection off GGX microfacet normals visible from the outgoing direction.
+pub struct GgxPDF {
+    uvw: Onb,
+    wo: Vec3,
+    distribution: TrowbridgeReitz,
+}
+
+impl GgxPDF {
+    // `wo` points back along the incoming ray, away from the surface.
+    pub fn new(normal: &Vec3, wo: &Vec3, distribution: TrowbridgeReitz) -> Self {
+        let uvw = Onb::new(normal);
+        let wo = uvw.to_local(&unit_vector(wo));
+        Self {
+            uvw,
+            wo,
+            distribution,
+        }
+    }
+}
+
+impl Pdf for GgxPDF {
+    fn value(&self, dir: &Vec3) -> f64 {
+        let wi = self.uvw.to_local(&unit_vector(dir));
+        self.distribution.pdf(&self.wo, &wi)
+    }
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let wm = self.distribution.sample_wm(&self.wo, sampler);
+        let wi = wm * (2.0 * dot(&self.wo, &wm)) - self.wo;
+        self.uvw.local(&wi)
+    }
+}
+
+// Reflection off and refraction through a rough dielectric interface.
+pub struct DielectricPDF {
+    uvw: Onb,
+    wo: Vec3,
+    distribution: TrowbridgeReitz,
+    eta: f64,
+}
+
+impl DielectricPDF {
+    // `eta` is the index of refraction behind the surface relative to the side of `wo`.
+    pub fn new(normal: &Vec3, wo: &Vec3, distribution: TrowbridgeReitz, eta: f64) -> Self {
+        let uvw = Onb::new(normal);
+        let wo = uvw.to_local(&unit_vector(wo));
+        Self {
+            uvw,
+            wo,
+            distribution,
+            eta,
+        }
+    }
+}
+
+impl Pdf for DielectricPDF {
+    fn value(&self, dir: &Vec3) -> f64 {
+        let wi = self.uvw.to_local(&unit_vector(dir));
+        microfacet::dielectric_pdf(&self.distribution, self.eta, &self.wo, &wi)
+    }
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let wi = microfacet::sample_dielectric(&self.distribution, self.eta, &self.wo, sampler);
+        self.uvw.local(&wi)
     }
 }
 
 pub struct HittablePDF {
     objects: Arc<dyn Hittable>,
     origin: Point3,
+    time: f64,
 }
 
 impl HittablePDF {
-    pub fn new(objects: Arc<dyn Hittable>, origin: &Point3) -> Self {
+    pub fn new(objects: Arc<dyn Hittable>, origin: &Point3, time: f64) -> Self {
         Self {
             objects,
             origin: *origin,
+            time,
         }
     }
 }
 
 impl Pdf for HittablePDF {
     fn value(&self, dir: &Vec3) -> f64 {
-        self.objects.pdf_value(&self.origin, dir)
+        self.objects.pdf_value(&self.origin, dir, self.time)
     }
-    fn generate(&self) -> Vec3 {
-        self.objects.random(&self.origin)
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        self.objects.random(&self.origin, self.time, sampler)
     }
 }
 
@@ -89,11 +157,46 @@ impl Pdf for MixturePDF {
     fn value(&self, dir: &Vec3) -> f64 {
         0.5 * self.p[0].value(dir) + 0.5 * self.p[1].value(dir)
     }
-    fn generate(&self) -> Vec3 {
-        if thread_rng().gen_range(0.0..1.0) < 0.5 {
-            self.p[0].generate()
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        if sampler.get_1d() < 0.5 {
+            self.p[0].generate(sampler)
         } else {
-            self.p[1].generate()
+            self.p[1].generate(sampler)
+        }
+    }
+}
+
+// Picks one of several lobes with probability proportional to its weight, so the lobes
+// that reflect the most light get the most samples.
+pub struct LobePDF {
+    lobes: Vec<(f64, Arc<dyn Pdf>)>,
+}
+
+impl LobePDF {
+    // Lobes with no weight are dropped; at least one lobe must have some.
+    pub fn new(lobes: Vec<(f64, Arc<dyn Pdf>)>) -> Self {
+        let lobes: Vec<_> = lobes.into_iter().filter(|(w, _)| *w > 0.0).collect();
+        let total: f64 = lobes.iter().map(|(w, _)| w).sum();
+        let lobes = lobes.into_iter().map(|(w, p)| (w / total, p)).collect();
+        Self { lobes }
+    }
+}
+
+impl Pdf for LobePDF {
+    fn value(&self, dir: &Vec3) -> f64 {
+        self.lobes.iter().map(|(w, p)| w * p.value(dir)).sum()
+    }
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let mut u = sampler.get_1d();
+        for (w, p) in &self.lobes {
+            if u < *w {
+                return p.generate(sampler);
+            }
+            u -= w;
+        }
+        match self.lobes.last() {
+            Some((_, p)) => p.generate(sampler),
+            None => Vec3::black(),
         }
     }
 }
@@ -1,7 +1,11 @@
+use crate::rtweekend::random_seed;
 use crate::vec3::{dot, Point3, Vec3};
-use rand::{thread_rng, Rng};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
+// The tables are drawn from a generator of their own, so the seed alone rebuilds them.
 pub struct Perlin {
+    seed: u64,
     rand_vec: Vec<Vec3>,
     perm_x: Vec<u32>,
     perm_y: Vec<u32>,
@@ -12,18 +16,30 @@ impl Perlin {
     const POINT_COUNT: usize = 256;
 
     pub fn new() -> Self {
+        Self::with_seed(random_seed())
+    }
+    pub fn with_seed(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
         let mut rand_vec: Vec<Vec3> = vec![];
         for _i in 0..Self::POINT_COUNT {
-            rand_vec.push(Vec3::random_in(-1.0, 1.0));
+            rand_vec.push(Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            ));
         }
 
         Self {
+            seed,
             rand_vec,
-            perm_x: Self::perlin_generate_perm(),
-            perm_y: Self::perlin_generate_perm(),
-            perm_z: Self::perlin_generate_perm(),
+            perm_x: Self::perlin_generate_perm(&mut rng),
+            perm_y: Self::perlin_generate_perm(&mut rng),
+            perm_z: Self::perlin_generate_perm(&mut rng),
         }
     }
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
     pub fn noise(&self, p: &Point3) -> f64 {
         let mut u = p.x - p.x.floor();
         let mut v = p.y - p.y.floor();
@@ -63,17 +79,17 @@ impl Perlin {
 
         accum.abs()
     }
-    fn perlin_generate_perm() -> Vec<u32> {
+    fn perlin_generate_perm(rng: &mut StdRng) -> Vec<u32> {
         let mut p: Vec<u32> = vec![];
         for i in 0..Self::POINT_COUNT {
             p.push(i as u32);
         }
-        Self::permute(&mut p, Self::POINT_COUNT);
+        Self::permute(&mut p, Self::POINT_COUNT, rng);
         p
     }
-    fn permute(p: &mut [u32], n: usize) {
+    fn permute(p: &mut [u32], n: usize, rng: &mut StdRng) {
         for i in (1..n - 1).rev() {
-            let target = thread_rng().gen_range(0..i);
+            let target = rng.gen_range(0..i);
             p.swap(i, target);
         }
     }
@@ -0,0 +1,467 @@
+use crate::color::Color;
+use crate::hittable_list::HittableList;
+use crate::material::Material;
+use crate::mesh::VertexMesh;
+use crate::texture::gamma_to_linear;
+use crate::vec3::{Point3, Vec3};
+use std::path::Path;
+use std::sync::Arc;
+
+// Load a PLY model, ASCII or binary, as one mesh. Polygons are split into triangles, and
+// vertex colors color the mesh when no material is given. Normals in the file are used
+// unless `smooth` asks for ones averaged from the faces.
+pub fn read_ply(
+    path: &Path,
+    scale: f64,
+    smooth: bool,
+    material: Option<Arc<dyn Material>>,
+) -> Result<HittableList, String> {
+    let error = |message: &dyn std::fmt::Display| format!("{}: {}", path.display(), message);
+    let data = std::fs::read(path).map_err(|err| error(&err))?;
+    let mut mesh = parse(&data).map_err(|err| error(&err))?;
+    for p in mesh.positions.iter_mut() {
+        *p *= scale;
+    }
+    if smooth {
+        mesh.smooth_normals();
+    }
+    let mesh = mesh.into_mesh(material).map_err(|err| error(&err))?;
+    Ok(HittableList::new_from(Arc::new(mesh)))
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Copy, Clone)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(format!("unknown property type `{}`", name)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // The largest value of an integer type, which stands for full intensity in colors
+    fn max(self) -> f64 {
+        match self {
+            Scalar::I8 => i8::MAX as f64,
+            Scalar::U8 => u8::MAX as f64,
+            Scalar::I16 => i16::MAX as f64,
+            Scalar::U16 => u16::MAX as f64,
+            Scalar::I32 => i32::MAX as f64,
+            Scalar::U32 => u32::MAX as f64,
+            Scalar::F32 | Scalar::F64 => 1.0,
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    kind: Scalar,
+    // Type of the item count, for list properties
+    count: Option<Scalar>,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// Reads the values of the body one at a time, whatever the format.
+struct Body<'a> {
+    format: Format,
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Body<'a> {
+    // Whether the rest of a binary body can hold `count` values of `size` bytes, so that
+    // counts the file cannot back are refused before reading towards them. ASCII values
+    // have no fixed size, and reading them stops at the end of the data.
+    fn has_room(&self, count: usize, size: usize) -> bool {
+        self.format == Format::Ascii
+            || count
+                .checked_mul(size)
+                .map_or(false, |bytes| bytes <= self.data.len() - self.position)
+    }
+
+    fn read(&mut self, kind: Scalar) -> Result<f64, String> {
+        if self.format == Format::Ascii {
+            let rest = &self.data[self.position..];
+            let start = rest
+                .iter()
+                .position(|b| !b.is_ascii_whitespace())
+                .ok_or("unexpected end of file")?;
+            let len = rest[start..]
+                .iter()
+                .position(|b| b.is_ascii_whitespace())
+                .unwrap_or(rest.len() - start);
+            self.position += start + len;
+            let word = String::from_utf8_lossy(&rest[start..start + len]);
+            return word
+                .parse()
+                .map_err(|_| format!("`{}` is not a number", word));
+        }
+
+        let bytes = self
+            .data
+            .get(self.position..self.position + kind.size())
+            .ok_or("unexpected end of file")?;
+        self.position += kind.size();
+        let mut buffer = [0; 8];
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        if self.format == Format::BigEndian {
+            buffer[..bytes.len()].reverse();
+        }
+        let [b0, b1, b2, b3, ..] = buffer;
+        Ok(match kind {
+            Scalar::I8 => b0 as i8 as f64,
+            Scalar::U8 => b0 as f64,
+            Scalar::I16 => i16::from_le_bytes([b0, b1]) as f64,
+            Scalar::U16 => u16::from_le_bytes([b0, b1]) as f64,
+            Scalar::I32 => i32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Scalar::U32 => u32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Scalar::F32 => f32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Scalar::F64 => f64::from_le_bytes(buffer),
+        })
+    }
+}
+
+fn parse(data: &[u8]) -> Result<VertexMesh, String> {
+    const END: &[u8] = b"end_header";
+    let end = data
+        .windows(END.len())
+        .position(|window| window == END)
+        .ok_or("not a PLY file")?;
+    // The body starts on the line after `end_header`.
+    let body_start = data[end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(data.len(), |i| end + i + 1);
+    let header = String::from_utf8_lossy(&data[..end]);
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err(String::from("not a PLY file"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", name, _] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::LittleEndian,
+                    "binary_big_endian" => Format::BigEndian,
+                    _ => return Err(format!("unknown format `{}`", name)),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: String::from(*name),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("bad element count `{}`", count))?,
+                properties: vec![],
+            }),
+            ["property", "list", count, kind, name] => elements
+                .last_mut()
+                .ok_or("property before any element")?
+                .properties
+                .push(Property {
+                    name: String::from(*name),
+                    kind: Scalar::parse(kind)?,
+                    count: Some(Scalar::parse(count)?),
+                }),
+            ["property", kind, name] => elements
+                .last_mut()
+                .ok_or("property before any element")?
+                .properties
+                .push(Property {
+                    name: String::from(*name),
+                    kind: Scalar::parse(kind)?,
+                    count: None,
+                }),
+            [] | ["comment", ..] | ["obj_info", ..] => {}
+            _ => return Err(format!("unexpected header line `{}`", line)),
+        }
+    }
+    let mut body = Body {
+        format: format.ok_or("missing format")?,
+        data: &data[body_start..],
+        position: 0,
+    };
+
+    let mut mesh = VertexMesh::default();
+    for element in &elements {
+        if element.count > 0 && element.properties.is_empty() {
+            return Err(format!("element `{}` has no properties", element.name));
+        }
+        // A list takes at least the room of its count.
+        let record = element
+            .properties
+            .iter()
+            .map(|property| property.count.unwrap_or(property.kind).size())
+            .sum();
+        if !body.has_room(element.count, record) {
+            return Err(format!(
+                "{} {} elements do not fit in the file",
+                element.count, element.name
+            ));
+        }
+        match element.name.as_str() {
+            "vertex" => read_vertices(&mut body, element, &mut mesh)?,
+            "face" => read_faces(&mut body, element, &mut mesh)?,
+            // Edges, materials and anything else are read past.
+            _ => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        read_property(&mut body, property)?;
+                    }
+                }
+            }
+        }
+    }
+    if mesh.triangles.is_empty() {
+        return Err(String::from("no faces"));
+    }
+    // Checked here as well as when the mesh is built, since smoothing the normals comes
+    // first.
+    let vertices = mesh.positions.len();
+    if mesh
+        .triangles
+        .iter()
+        .flatten()
+        .any(|&i| i as usize >= vertices)
+    {
+        return Err(String::from("a face refers to a vertex out of range"));
+    }
+    Ok(mesh)
+}
+
+fn read_property(body: &mut Body, property: &Property) -> Result<Vec<f64>, String> {
+    match property.count {
+        Some(count) => {
+            let count = body.read(count)? as usize;
+            if !body.has_room(count, property.kind.size()) {
+                return Err(format!("a list of {} does not fit in the file", count));
+            }
+            (0..count).map(|_| body.read(property.kind)).collect()
+        }
+        None => Ok(vec![body.read(property.kind)?]),
+    }
+}
+
+fn read_vertices(body: &mut Body, element: &Element, mesh: &mut VertexMesh) -> Result<(), String> {
+    let slot = |names: &[&str]| {
+        names.iter().find_map(|name| {
+            element
+                .properties
+                .iter()
+                .position(|property| property.name == *name && property.count.is_none())
+        })
+    };
+    let slots = |names: [&[&str]; 3]| -> Option<[usize; 3]> {
+        Some([slot(names[0])?, slot(names[1])?, slot(names[2])?])
+    };
+    let position = slots([&["x"], &["y"], &["z"]]).ok_or("vertices have no position")?;
+    let normal = slots([&["nx"], &["ny"], &["nz"]]);
+    let color = slots([
+        &["red", "r", "diffuse_red"],
+        &["green", "g", "diffuse_green"],
+        &["blue", "b", "diffuse_blue"],
+    ]);
+    let uv = slot(&["u", "s", "texture_u", "texture_s"]).zip(slot(&[
+        "v",
+        "t",
+        "texture_v",
+        "texture_t",
+    ]));
+
+    let mut values = vec![0.0; element.properties.len()];
+    for _ in 0..element.count {
+        for (value, property) in values.iter_mut().zip(&element.properties) {
+            // Lists on vertices are not used, so only the first item is kept.
+            *value = read_property(body, property)?
+                .first()
+                .copied()
+                .unwrap_or(0.0);
+        }
+        let [x, y, z] = position.map(|i| values[i]);
+        mesh.positions.push(Point3::new(x, y, z));
+        if let Some(normal) = normal {
+            let [x, y, z] = normal.map(|i| values[i]);
+            mesh.normals.push(Vec3::new(x, y, z));
+        }
+        if let Some(color) = color {
+            // Integer colors are gamma encoded from zero to the type's maximum.
+            let [r, g, b] = color.map(|i| {
+                let kind = element.properties[i].kind;
+                gamma_to_linear(values[i] / kind.max())
+            });
+            mesh.colors.push(Color::new(r, g, b));
+        }
+        if let Some((u, v)) = uv {
+            mesh.uvs.push((values[u], values[v]));
+        }
+    }
+    Ok(())
+}
+
+fn read_faces(body: &mut Body, element: &Element, mesh: &mut VertexMesh) -> Result<(), String> {
+    let indices = element
+        .properties
+        .iter()
+        .position(|property| {
+            property.count.is_some()
+                && (property.name == "vertex_indices" || property.name == "vertex_index")
+        })
+        .ok_or("faces have no vertex indices")?;
+    for _ in 0..element.count {
+        for (i, property) in element.properties.iter().enumerate() {
+            let values = read_property(body, property)?;
+            if i != indices {
+                continue;
+            }
+            if values.iter().any(|&v| v < 0.0) {
+                return Err(String::from("negative vertex index"));
+            }
+            // Polygons become fans of triangles.
+            let polygon: Vec<u32> = values.iter().map(|&v| v as u32).collect();
+            for k in 1..polygon.len().saturating_sub(1) {
+                mesh.triangles
+                    .push([polygon[0], polygon[k], polygon[k + 1]]);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "element vertex 4\n\
+                          property float x\nproperty float y\nproperty float z\n\
+                          element face 1\nproperty list uchar int vertex_indices\n\
+                          end_header\n";
+
+    // A unit square as one quad, in a binary layout.
+    fn binary(format: &str, bytes: fn(f32) -> [u8; 4], int: fn(i32) -> [u8; 4]) -> Vec<u8> {
+        let mut data = format!("ply\nformat {} 1.0\n{}", format, HEADER).into_bytes();
+        for p in [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0],
+        ] {
+            for c in p {
+                data.extend(bytes(c));
+            }
+        }
+        data.push(4);
+        for i in 0..4 {
+            data.extend(int(i));
+        }
+        data
+    }
+
+    fn check_square(mesh: &VertexMesh) {
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.positions[2].x, 1.0);
+        assert_eq!(mesh.positions[2].y, 1.0);
+        assert_eq!(mesh.triangles, [[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn ascii_quad_becomes_a_fan() {
+        let data = format!(
+            "ply\nformat ascii 1.0\ncomment a square\n{}0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n",
+            HEADER
+        );
+        check_square(&parse(data.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn binary_little_endian() {
+        let data = binary("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        check_square(&parse(&data).unwrap());
+    }
+
+    #[test]
+    fn binary_big_endian() {
+        let data = binary("binary_big_endian", f32::to_be_bytes, i32::to_be_bytes);
+        check_square(&parse(&data).unwrap());
+    }
+
+    #[test]
+    fn index_out_of_range() {
+        let data = format!(
+            "ply\nformat ascii 1.0\n{}0 0 0\n1 0 0\n1 1 0\n0 1 0\n3 0 1 4\n",
+            HEADER
+        );
+        let err = parse(data.as_bytes()).err().unwrap();
+        assert_eq!(err, "a face refers to a vertex out of range");
+    }
+
+    #[test]
+    fn truncated_ascii_body() {
+        let data = format!("ply\nformat ascii 1.0\n{}0 0 0\n1 0 0\n", HEADER);
+        let err = parse(data.as_bytes()).err().unwrap();
+        assert_eq!(err, "unexpected end of file");
+    }
+
+    #[test]
+    fn counts_larger_than_the_file() {
+        let data = b"ply\nformat binary_little_endian 1.0\n\
+                     element vertex 18446744073709551615\n\
+                     property float x\nproperty float y\nproperty float z\n\
+                     end_header\n";
+        assert!(parse(data).err().unwrap().contains("do not fit"));
+
+        let mut data = binary("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        // A list count of 255 with only four indices after it
+        let at = data.len() - 17;
+        data[at] = 255;
+        assert!(parse(&data).err().unwrap().contains("does not fit"));
+    }
+
+    #[test]
+    fn elements_without_properties() {
+        let data = b"ply\nformat ascii 1.0\nelement vertex 1000000000\nend_header\n";
+        let err = parse(data).err().unwrap();
+        assert_eq!(err, "element `vertex` has no properties");
+    }
+}
@@ -5,8 +5,9 @@ use crate::hittable_list::HittableList;
 use crate::interval::Interval;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene_file::{array, DescribeResult, ObjectDesc, SceneWriter};
 use crate::vec3::{cross, dot, unit_vector, Point3, Vec3};
-use rand::{thread_rng, Rng};
 use std::sync::Arc;
 
 pub struct Quad {
@@ -66,7 +67,7 @@ impl Hittable for Quad {
         if !range.contains(alpha) || !range.contains(beta) {
             return None;
         }
-        let rec = HitRecord::new(
+        let mut rec = HitRecord::new(
             &intersection,
             t,
             &self.normal,
@@ -75,6 +76,7 @@ impl Hittable for Quad {
             alpha,
             beta,
         );
+        rec.set_derivatives(&self.u, &self.v);
         Some(rec)
     }
 
@@ -82,9 +84,9 @@ impl Hittable for Quad {
         self.bbox.clone()
     }
 
-    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
         if let Some(rec) = self.hit(
-            &Ray::new(origin, direction, 0.0),
+            &Ray::new(origin, direction, time),
             Interval::new(0.001, f64::INFINITY),
         ) {
             let distance_squared = rec.t * rec.t * direction.length_squared();
@@ -96,12 +98,20 @@ impl Hittable for Quad {
         }
     }
 
-    fn random(&self, origin: &Point3) -> Vec3 {
-        let p = self.q
-            + (self.u * thread_rng().gen_range(0.0..1.0))
-            + (self.v * thread_rng().gen_range(0.0..1.0));
+    fn random(&self, origin: &Point3, _time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        let (a, b) = sampler.get_2d();
+        let p = self.q + (self.u * a) + (self.v * b);
         p - *origin
     }
+
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<ObjectDesc> {
+        Ok(ObjectDesc::Quad {
+            q: array(&self.q),
+            u: array(&self.u),
+            v: array(&self.v),
+            material: writer.material(&self.mat)?,
+        })
+    }
 }
 
 pub fn cuboid(a: &Point3, b: &Point3, mat: Arc<dyn Material>) -> Arc<dyn Hittable> {
@@ -7,6 +7,11 @@ pub struct Ray {
     _orig: Point3,
     dir: Vec3,
     tm: f64,
+    // Hero wavelength in nanometers when rendering spectrally
+    wavelength: Option<f64>,
+    // Drawn from the sampler for each bounce, for the random choices made while finding
+    // the hit, such as where a medium scatters
+    seed: u64,
 }
 
 impl Ray {
@@ -15,8 +20,28 @@ impl Ray {
             _orig: *origin,
             dir: *direction,
             tm,
+            wavelength: None,
+            seed: 0,
         }
     }
+    // The same ray in another space, keeping its time, wavelength and seed.
+    pub fn transformed(&self, origin: &Point3, direction: &Vec3) -> Self {
+        Self {
+            _orig: *origin,
+            dir: *direction,
+            ..*self
+        }
+    }
+    pub fn with_wavelength(&self, wavelength: f64) -> Self {
+        Self {
+            wavelength: Some(wavelength),
+            ..*self
+        }
+    }
+
+    pub fn with_seed(&self, seed: u64) -> Self {
+        Self { seed, ..*self }
+    }
 
     pub fn origin(&self) -> Point3 {
         self._orig
@@ -27,6 +52,12 @@ impl Ray {
     pub fn time(&self) -> f64 {
         self.tm
     }
+    pub fn wavelength(&self) -> Option<f64> {
+        self.wavelength
+    }
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
 
     pub fn at(&self, t: f64) -> Point3 {
         self._orig + self.dir * t
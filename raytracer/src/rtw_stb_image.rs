@@ -1,32 +1,49 @@
 use image::{DynamicImage, GenericImageView};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Clone)]
 pub struct RTWImage {
     image: Option<DynamicImage>,
+    // Absolute path of the file the image was read from
+    path: Option<PathBuf>,
     image_width: u32,
     image_height: u32,
 }
 
 impl RTWImage {
     pub fn new(image_filename: &str) -> Self {
+        Self::open(image_filename).unwrap_or_else(|| {
+            eprintln!("ERROR: Could not load image file '{}'.", image_filename);
+            RTWImage {
+                image: None,
+                path: None,
+                image_width: 0,
+                image_height: 0,
+            }
+        })
+    }
+
+    // The image in the file, looked for as given and then in `images/`, or None if it
+    // cannot be read.
+    pub fn open(image_filename: &str) -> Option<Self> {
         let mut rtw_image = RTWImage {
             image: None,
+            path: None,
             image_width: 0,
             image_height: 0,
         };
+        (rtw_image.load(image_filename) || rtw_image.load(&format!("images/{}", image_filename)))
+            .then_some(rtw_image)
+    }
 
-        let filename = String::from(image_filename);
-
-        if rtw_image.load(&filename) {
-            return rtw_image;
+    // An image already decoded, such as one embedded in a model file.
+    pub fn from_image(image: DynamicImage) -> Self {
+        RTWImage {
+            path: None,
+            image_width: image.width(),
+            image_height: image.height(),
+            image: Some(image),
         }
-        if rtw_image.load(&format!("images/{}", filename)) {
-            return rtw_image;
-        }
-
-        eprintln!("ERROR: Could not load image file '{}'.", image_filename);
-        rtw_image
     }
 
     fn load(&mut self, filename: &str) -> bool {
@@ -35,12 +52,17 @@ impl RTWImage {
                 self.image_width = img.width();
                 self.image_height = img.height();
                 self.image = Some(img);
+                self.path = std::fs::canonicalize(filename).ok();
                 true
             }
             Err(_) => false,
         }
     }
 
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
     pub fn width(&self) -> u32 {
         self.image_width
     }
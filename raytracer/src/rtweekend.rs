@@ -0,0 +1,27 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+// Reseed the random number generator of the calling thread.
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+// Returns a random real in [0, 1).
+pub fn random_double() -> f64 {
+    RNG.with(|rng| rng.borrow_mut().gen_range(0.0..1.0))
+}
+
+// Returns a random real in [min, max).
+pub fn random_double_range(min: f64, max: f64) -> f64 {
+    RNG.with(|rng| rng.borrow_mut().gen_range(min..max))
+}
+
+// Returns a seed for a generator of its own.
+pub fn random_seed() -> u64 {
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
@@ -0,0 +1,527 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+// Dimensions 0-1 place the sample in the pixel, 2-3 on the lens, 4 picks the time and 5 the
+// wavelengths of spectral renders.
+pub const CAMERA_DIMENSIONS: u32 = 6;
+// Every bounce gets its own block of dimensions, whether it uses all of them or not, so the
+// same bounce of every sample draws from the same well distributed dimensions.
+pub const BOUNCE_DIMENSIONS: u32 = 8;
+
+// Source of the random numbers used while tracing one pixel sample: ray generation,
+// scattering and PDF sampling all draw from it. Media sample inside `Hittable::hit`, which
+// has no sampler, from a seed the ray carries that is drawn from it at every bounce.
+pub trait Sampler {
+    // Move to sample `index` of pixel (i, j), counting from the first sample of the render.
+    fn start_pixel_sample(&mut self, i: u32, j: u32, index: u32);
+    // Skip ahead to the dimensions reserved for the given bounce.
+    fn start_bounce(&mut self, bounce: u32);
+    // Returns a real in [0, 1).
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+
+impl SamplerKind {
+    pub fn build(self, seed: u64, samples_per_pixel: u32) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(seed, samples_per_pixel)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler::new(seed)),
+        }
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" | "random" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            "bluenoise" | "blue-noise" => Ok(SamplerKind::BlueNoise),
+            _ => Err(format!(
+                "unknown sampler `{}`, expected independent, stratified, halton, sobol or bluenoise",
+                s
+            )),
+        }
+    }
+}
+
+// Where a sampler is in the sequence: which pixel, which sample and which dimension.
+#[derive(Default)]
+struct SampleState {
+    i: u32,
+    j: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn start_pixel_sample(&mut self, i: u32, j: u32, index: u32) {
+        *self = Self {
+            i,
+            j,
+            index,
+            dimension: 0,
+        };
+    }
+    fn start_bounce(&mut self, bounce: u32) {
+        self.dimension = CAMERA_DIMENSIONS + bounce * BOUNCE_DIMENSIONS;
+    }
+    // Claim the next `n` dimensions, returning the first.
+    fn take(&mut self, n: u32) -> u32 {
+        let dimension = self.dimension;
+        self.dimension += n;
+        dimension
+    }
+    // A hash of the pixel and dimension, used to decorrelate the pixels.
+    fn pixel_hash(&self, seed: u64, dimension: u32) -> u64 {
+        hash(&[seed, self.i as u64, self.j as u64, dimension as u64])
+    }
+}
+
+// Uniform random numbers from a generator seeded per pixel sample.
+pub struct IndependentSampler {
+    seed: u64,
+    rng: StdRng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, i: u32, j: u32, index: u32) {
+        let seed = hash(&[self.seed, i as u64, j as u64, index as u64]);
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+    fn start_bounce(&mut self, _bounce: u32) {}
+    fn get_1d(&mut self) -> f64 {
+        self.rng.gen_range(0.0..1.0)
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// Jittered strata over the pixel's samples, shuffled independently for every dimension so
+// the dimensions don't line up. Samples beyond `samples_per_pixel` are uniform.
+pub struct StratifiedSampler {
+    seed: u64,
+    samples_per_pixel: u32,
+    state: SampleState,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: u32) -> Self {
+        Self {
+            seed,
+            samples_per_pixel: samples_per_pixel.max(1),
+            state: SampleState::default(),
+        }
+    }
+    fn jitter(&self, dimension: u32) -> f64 {
+        let h = hash(&[
+            self.state.pixel_hash(self.seed, dimension),
+            self.state.index as u64,
+        ]);
+        to_unit(h)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, i: u32, j: u32, index: u32) {
+        self.state.start_pixel_sample(i, j, index);
+    }
+    fn start_bounce(&mut self, bounce: u32) {
+        self.state.start_bounce(bounce);
+    }
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.take(1);
+        let n = self.samples_per_pixel;
+        let jitter = self.jitter(dimension);
+        if self.state.index >= n {
+            return jitter;
+        }
+        let stratum = permute(
+            self.state.index,
+            n,
+            self.state.pixel_hash(self.seed, dimension),
+        );
+        (stratum as f64 + jitter) / n as f64
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = self.state.take(2);
+        let cells = (self.samples_per_pixel as f64).sqrt() as u32;
+        let (ju, jv) = (self.jitter(dimension), self.jitter(dimension + 1));
+        if self.state.index >= cells * cells {
+            return (ju, jv);
+        }
+        let stratum = permute(
+            self.state.index,
+            cells * cells,
+            self.state.pixel_hash(self.seed, dimension),
+        );
+        let u = ((stratum % cells) as f64 + ju) / cells as f64;
+        let v = ((stratum / cells) as f64 + jv) / cells as f64;
+        (u, v)
+    }
+}
+
+// The Halton sequence, one prime base per dimension, with the digits of every pixel and
+// dimension Owen scrambled.
+pub struct HaltonSampler {
+    seed: u64,
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            state: SampleState::default(),
+        }
+    }
+    fn sample(&self, dimension: u32) -> f64 {
+        // Large bases are barely better than random, so deep bounces cycle through the
+        // small ones again; their scrambles still differ.
+        let base = PRIMES[dimension as usize % PRIMES.len()];
+        let seed = self.state.pixel_hash(self.seed, dimension);
+        owen_scrambled_radical_inverse(base, self.state.index as u64, seed)
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, i: u32, j: u32, index: u32) {
+        self.state.start_pixel_sample(i, j, index);
+    }
+    fn start_bounce(&mut self, bounce: u32) {
+        self.state.start_bounce(bounce);
+    }
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.take(1);
+        self.sample(dimension)
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = self.state.take(2);
+        (self.sample(dimension), self.sample(dimension + 1))
+    }
+}
+
+// Owen scrambled Sobol points. Every pair of dimensions uses the first two Sobol
+// dimensions with its own shuffle of the sample order, following Burley's "Practical
+// Hash-based Owen Scrambling", so no direction number tables are needed.
+pub struct SobolSampler {
+    seed: u64,
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            state: SampleState::default(),
+        }
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, i: u32, j: u32, index: u32) {
+        self.state.start_pixel_sample(i, j, index);
+    }
+    fn start_bounce(&mut self, bounce: u32) {
+        self.state.start_bounce(bounce);
+    }
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.take(1);
+        let seed = self.state.pixel_hash(self.seed, dimension);
+        scrambled_sobol_1d(self.state.index, seed)
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = self.state.take(2);
+        let seed = self.state.pixel_hash(self.seed, dimension);
+        scrambled_sobol_2d(self.state.index, seed)
+    }
+}
+
+// The same scrambled Sobol points in every pixel, each pixel offset by a blue noise
+// value instead. The error then ends up spread as high frequency noise across the image,
+// which is much less visible than white noise at low sample counts.
+pub struct BlueNoiseSampler {
+    seed: u64,
+    state: SampleState,
+    tile: &'static [f64],
+}
+
+impl BlueNoiseSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            state: SampleState::default(),
+            tile: blue_noise_tile(),
+        }
+    }
+    // Each dimension looks the pixel up in its own toroidal shift of the tile.
+    fn offset(&self, dimension: u32) -> f64 {
+        let shift = hash(&[self.seed, dimension as u64]);
+        let size = BLUE_NOISE_SIZE as u64;
+        let x = (self.state.i as u64 + shift % size) % size;
+        let y = (self.state.j as u64 + (shift >> 32) % size) % size;
+        self.tile[(y * size + x) as usize]
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_pixel_sample(&mut self, i: u32, j: u32, index: u32) {
+        self.state.start_pixel_sample(i, j, index);
+    }
+    fn start_bounce(&mut self, bounce: u32) {
+        self.state.start_bounce(bounce);
+    }
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.take(1);
+        let seed = hash(&[self.seed, dimension as u64]);
+        let x = scrambled_sobol_1d(self.state.index, seed) + self.offset(dimension);
+        x - x.floor()
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = self.state.take(2);
+        let seed = hash(&[self.seed, dimension as u64]);
+        let (u, v) = scrambled_sobol_2d(self.state.index, seed);
+        let u = u + self.offset(dimension);
+        let v = v + self.offset(dimension + 1);
+        (u - u.floor(), v - v.floor())
+    }
+}
+
+// Mix a handful of integers into one well distributed 64-bit value (SplitMix64 finalizer).
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |h, &v| {
+        let mut z = (h ^ v).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    })
+}
+
+// The top 53 bits of a hash as a real in [0, 1).
+pub fn to_unit(h: u64) -> f64 {
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// The position of `i` in a pseudo-random permutation of [0, n) picked by `seed`, without
+// building the permutation (Kensler's "Correlated Multi-Jittered Sampling").
+fn permute(mut i: u32, n: u32, seed: u64) -> u32 {
+    let p = seed as u32;
+    let mut w = n.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            return i.wrapping_add(p) % n;
+        }
+    }
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+// Mirror the base-`base` digits of `n` around the radix point, permuting each digit
+// depending on the digits before it. The leading zeros are permuted too, so this keeps
+// going until the result runs out of precision.
+fn owen_scrambled_radical_inverse(base: u64, mut n: u64, seed: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut reversed: u64 = 0;
+    while 1.0 - (base - 1) as f64 * inv_base_m < 1.0 {
+        let next = n / base;
+        let digit = n - next * base;
+        let digit_seed = hash(&[seed, reversed]);
+        let digit = permute(digit as u32, base as u32, digit_seed) as u64;
+        reversed = reversed * base + digit;
+        inv_base_m *= inv_base;
+        n = next;
+    }
+    (reversed as f64 * inv_base_m).min(1.0 - f64::EPSILON / 2.0)
+}
+
+// The first two Sobol dimensions: the van der Corput sequence and its companion.
+fn sobol(index: u32, dimension: u32) -> u32 {
+    if dimension == 0 {
+        return index.reverse_bits();
+    }
+    let mut result = 0;
+    let mut v: u32 = 1 << 31;
+    let mut index = index;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+// Laine and Karras' hash, which only lets bits affect the bits above them, so that applied
+// to bit-reversed values it acts as an Owen scramble.
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn bits_to_unit(x: u32) -> f64 {
+    x as f64 / (1u64 << 32) as f64
+}
+
+fn scrambled_sobol_1d(index: u32, seed: u64) -> f64 {
+    let shuffled = nested_uniform_scramble(index, seed as u32);
+    bits_to_unit(nested_uniform_scramble(
+        sobol(shuffled, 0),
+        (seed >> 32) as u32,
+    ))
+}
+
+fn scrambled_sobol_2d(index: u32, seed: u64) -> (f64, f64) {
+    let shuffled = nested_uniform_scramble(index, seed as u32);
+    let scramble = hash(&[seed]);
+    let u = nested_uniform_scramble(sobol(shuffled, 0), scramble as u32);
+    let v = nested_uniform_scramble(sobol(shuffled, 1), (scramble >> 32) as u32);
+    (bits_to_unit(u), bits_to_unit(v))
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+fn blue_noise_tile() -> &'static [f64] {
+    static TILE: OnceLock<Vec<f64>> = OnceLock::new();
+    TILE.get_or_init(void_and_cluster)
+}
+
+// Ulichney's void-and-cluster method: rank every texel of a tileable square so that each
+// prefix of the ranking is as evenly spread out as possible, then use the rank as the value.
+fn void_and_cluster() -> Vec<f64> {
+    const SIGMA: f64 = 1.9;
+    let size = BLUE_NOISE_SIZE;
+    let n = size * size;
+
+    // Gaussian energy of a point as seen from every offset, wrapping around the edges.
+    let mut kernel = vec![0.0; n];
+    for dy in 0..size {
+        for dx in 0..size {
+            let x = dx.min(size - dx) as f64;
+            let y = dy.min(size - dy) as f64;
+            kernel[dy * size + dx] = f64::exp(-(x * x + y * y) / (2.0 * SIGMA * SIGMA));
+        }
+    }
+    let toggle = |on: &mut Vec<bool>, energy: &mut Vec<f64>, p: usize, value: bool| {
+        on[p] = value;
+        let sign = if value { 1.0 } else { -1.0 };
+        let (px, py) = (p % size, p / size);
+        for (q, e) in energy.iter_mut().enumerate() {
+            let dx = (q % size + size - px) % size;
+            let dy = (q / size + size - py) % size;
+            *e += sign * kernel[dy * size + dx];
+        }
+    };
+    // The tightest cluster is the set point with the most energy, the largest void the
+    // unset point with the least.
+    let tightest_cluster = |on: &[bool], energy: &[f64]| {
+        (0..n)
+            .filter(|&p| on[p])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+    let largest_void = |on: &[bool], energy: &[f64]| {
+        (0..n)
+            .filter(|&p| !on[p])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    // Start from a random tenth of the points and move them until they are evenly spread.
+    let mut on = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let mut rng = StdRng::seed_from_u64(0xb1_0e_5e);
+    let initial = n / 10;
+    while on.iter().filter(|&&p| p).count() < initial {
+        let p = rng.gen_range(0..n);
+        if !on[p] {
+            toggle(&mut on, &mut energy, p, true);
+        }
+    }
+    loop {
+        let cluster = tightest_cluster(&on, &energy);
+        toggle(&mut on, &mut energy, cluster, false);
+        let void = largest_void(&on, &energy);
+        toggle(&mut on, &mut energy, void, true);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; n];
+    // Rank the initial points by removing clusters first...
+    let (mut first_on, mut first_energy) = (on.clone(), energy.clone());
+    for r in (0..initial).rev() {
+        let cluster = tightest_cluster(&first_on, &first_energy);
+        toggle(&mut first_on, &mut first_energy, cluster, false);
+        rank[cluster] = r;
+    }
+    // ...and the rest by filling voids.
+    for r in initial..n {
+        let void = largest_void(&on, &energy);
+        toggle(&mut on, &mut energy, void, true);
+        rank[void] = r;
+    }
+    rank.iter().map(|&r| (r as f64 + 0.5) / n as f64).collect()
+}
@@ -0,0 +1,1418 @@
+use crate::animation::{AnimatedTransform, Animation, Instance, Keyframe};
+use crate::bvh::BvhNode;
+use crate::camera::{Camera, CameraSettings, ImageSettings, Shutter};
+use crate::gltf::read_gltf;
+use crate::hittable::Hittable;
+use crate::hittable_list::HittableList;
+use crate::material::{
+    Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal, Microfacet, NormalMapKind,
+    NormalMapped, Opacity, Principled, PrincipledParams, SpotLight, ThinDielectric,
+};
+use crate::matrix::{Matrix4, Quaternion};
+use crate::medium::ConstantMedium;
+use crate::mesh::{Face, TriangleMesh};
+use crate::obj::read_obj;
+use crate::ply::read_ply;
+use crate::quad::{cuboid, Quad};
+use crate::scenes::Scene;
+use crate::spectrum::Ior;
+use crate::sphere::Sphere;
+use crate::stl::read_stl;
+use crate::texture::{
+    ChannelTexture, CheckerTexture, ImageTexture, NoiseTexture, ScaledTexture, SolidColor, Texture,
+    VertexColorTexture,
+};
+use crate::transform::Transform;
+use crate::translate::{RotateY, Translate};
+use crate::triangle::{MeshTriangle, Triangle};
+use crate::vec3::Vec3;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDesc {
+    #[serde(default)]
+    pub image: ImageDesc,
+    #[serde(default)]
+    pub camera: CameraDesc,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub textures: BTreeMap<String, TextureDesc>,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDesc>,
+    // Objects built once and placed any number of times by `instance` objects
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub geometry: BTreeMap<String, ObjectDesc>,
+    pub objects: Vec<ObjectDesc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lights: Vec<ObjectDesc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<SequenceDesc>,
+}
+
+// Frames rendered one after another to numbered images. Frame n shows the scene at time
+// `start + n * step`, with the shutter open over the part of the step from the camera's
+// `shutter_open` to its `shutter_close`, so `animated` objects move and blur from frame to
+// frame. Camera settings and material parameters change through keys.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SequenceDesc {
+    pub frames: u32,
+    #[serde(default)]
+    pub start: f64,
+    #[serde(default = "default_step")]
+    pub step: f64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub camera: Vec<ParamKeyDesc>,
+    // Keys for the entries of `materials`, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub materials: BTreeMap<String, Vec<ParamKeyDesc>>,
+}
+
+// The values some parameters reach at `time`, such as `{ time: 12, vfov: 30 }`. Numbers and
+// lists of numbers are interpolated linearly between the keys that set them, and other
+// values change at the key.
+#[derive(Serialize, Deserialize)]
+pub struct ParamKeyDesc {
+    pub time: f64,
+    #[serde(flatten)]
+    pub values: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImageDesc {
+    pub aspect_ratio: f64,
+    pub image_width: u32,
+    pub quality: u8,
+    pub samples_per_pixel: u32,
+    pub max_depth: i32,
+    pub background: [f64; 3],
+}
+
+impl Default for ImageDesc {
+    fn default() -> Self {
+        Self {
+            aspect_ratio: 16.0 / 9.0,
+            image_width: 400,
+            quality: 100,
+            samples_per_pixel: 100,
+            max_depth: 50,
+            background: [0.0, 0.0, 0.0],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraDesc {
+    pub vfov: f64,
+    pub look_from: [f64; 3],
+    pub look_at: [f64; 3],
+    pub vup: [f64; 3],
+    pub defocus_angle: f64,
+    pub focus_dist: f64,
+    // See `Shutter`. In a sequence these are fractions of the time between frames.
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub shutter_curve: Vec<f64>,
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        Self {
+            vfov: 90.0,
+            look_from: [0.0, 0.0, 0.0],
+            look_at: [0.0, 0.0, -1.0],
+            vup: [0.0, 1.0, 0.0],
+            defocus_angle: 0.0,
+            focus_dist: 10.0,
+            shutter_open: 0.0,
+            shutter_close: 1.0,
+            shutter_curve: vec![],
+        }
+    }
+}
+
+// A color given inline as `[r, g, b]`, a gray level given as a single number, or the name
+// of an entry in `textures`.
+#[derive(Clone)]
+pub enum TextureRef {
+    Color([f64; 3]),
+    Value(f64),
+    Named(String),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TextureDesc {
+    Solid {
+        color: [f64; 3],
+    },
+    Checker {
+        scale: f64,
+        even: TextureRef,
+        odd: TextureRef,
+    },
+    Image {
+        file: String,
+        // Read the pixels as data instead of gamma encoded colors, for normal and bump maps
+        #[serde(default, skip_serializing_if = "is_false")]
+        linear: bool,
+        // Tile the image instead of clamping coordinates outside [0, 1]
+        #[serde(default, skip_serializing_if = "is_false")]
+        repeat: bool,
+    },
+    Noise {
+        scale: f64,
+        // Picks the noise pattern, a random one if not given
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seed: Option<u64>,
+    },
+    // A texture multiplied by a color
+    Scale {
+        texture: TextureRef,
+        scale: [f64; 3],
+    },
+    // Channel 0, 1 or 2 of a texture as a gray level
+    Channel {
+        texture: TextureRef,
+        channel: usize,
+    },
+    // Colors blended between the vertices of each face, for a mesh whose texture
+    // coordinates come from `VertexColorTexture::face_uvs`
+    VertexColors {
+        colors: Vec<[f64; 3]>,
+        faces: Vec<[u32; 3]>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MaterialDesc {
+    Lambertian {
+        albedo: TextureRef,
+    },
+    Metal {
+        albedo: [f64; 3],
+        fuzz: f64,
+    },
+    Dielectric {
+        refraction_index: IorDesc,
+        #[serde(default)]
+        roughness: f64,
+        // Fraction of each channel absorbed per unit distance travelled inside
+        #[serde(default)]
+        absorption: [f64; 3],
+    },
+    ThinDielectric {
+        refraction_index: f64,
+    },
+    Microfacet {
+        base_color: TextureRef,
+        roughness: f64,
+        #[serde(default)]
+        metalness: f64,
+    },
+    Conductor {
+        eta: [f64; 3],
+        k: [f64; 3],
+        roughness: f64,
+    },
+    Principled(Box<PrincipledDesc>),
+    DiffuseLight {
+        emit: TextureRef,
+    },
+    // A light shining into a cone, with angles in degrees from its direction
+    SpotLight {
+        emit: [f64; 3],
+        direction: [f64; 3],
+        inner_angle: f64,
+        outer_angle: f64,
+    },
+    Isotropic {
+        albedo: TextureRef,
+    },
+    // Another material shaded with the normals of a tangent space normal map
+    NormalMap {
+        material: String,
+        map: TextureRef,
+        #[serde(default = "default_strength")]
+        strength: f64,
+    },
+    // Another material shaded as if displaced along its normal by a height map
+    BumpMap {
+        material: String,
+        height: TextureRef,
+        scale: f64,
+    },
+    // Another material that lets light through where `opacity` is below one
+    Opacity {
+        material: String,
+        opacity: TextureRef,
+    },
+}
+
+// A constant index of refraction, or a dispersion formula such as
+// `{ type: sellmeier, b: [...], c: [...] }`.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum IorDesc {
+    Constant(f64),
+    Dispersive(DispersionDesc),
+}
+
+// Wavelengths in micrometers, see `Ior`.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum DispersionDesc {
+    Cauchy { a: f64, b: f64 },
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl From<Ior> for IorDesc {
+    fn from(ior: Ior) -> Self {
+        match ior {
+            Ior::Constant(n) => IorDesc::Constant(n),
+            Ior::Cauchy { a, b } => IorDesc::Dispersive(DispersionDesc::Cauchy { a, b }),
+            Ior::Sellmeier { b, c } => IorDesc::Dispersive(DispersionDesc::Sellmeier { b, c }),
+        }
+    }
+}
+
+impl From<&IorDesc> for Ior {
+    fn from(desc: &IorDesc) -> Self {
+        match *desc {
+            IorDesc::Constant(n) => Ior::Constant(n),
+            IorDesc::Dispersive(DispersionDesc::Cauchy { a, b }) => Ior::Cauchy { a, b },
+            IorDesc::Dispersive(DispersionDesc::Sellmeier { b, c }) => Ior::Sellmeier { b, c },
+        }
+    }
+}
+
+// Parameters left out take the defaults of `PrincipledParams`.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrincipledDesc {
+    pub base_color: TextureRef,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metallic: Option<TextureRef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roughness: Option<TextureRef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub specular: Option<TextureRef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sheen: Option<TextureRef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sheen_tint: Option<TextureRef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clearcoat: Option<TextureRef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clearcoat_roughness: Option<TextureRef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transmission: Option<TextureRef>,
+    #[serde(default = "default_ior")]
+    pub ior: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ObjectDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: String,
+        // End position of a moving sphere at time 1.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        center2: Option<[f64; 3]>,
+    },
+    Quad {
+        q: [f64; 3],
+        u: [f64; 3],
+        v: [f64; 3],
+        material: String,
+    },
+    Triangle {
+        a: [f64; 3],
+        b: [f64; 3],
+        c: [f64; 3],
+        material: String,
+        // Vertex normals and texture coordinates of a smooth shaded mesh triangle
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        normals: Option<[[f64; 3]; 3]>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        uvs: Option<[[f64; 2]; 3]>,
+    },
+    Cuboid {
+        a: [f64; 3],
+        b: [f64; 3],
+        material: String,
+    },
+    ConstantMedium {
+        boundary: Box<ObjectDesc>,
+        density: f64,
+        albedo: TextureRef,
+    },
+    Translate {
+        offset: [f64; 3],
+        object: Box<ObjectDesc>,
+    },
+    RotateY {
+        angle: f64,
+        object: Box<ObjectDesc>,
+    },
+    // Any affine transform, built from steps applied to the object in order
+    Transform {
+        transform: Vec<TransformOp>,
+        object: Box<ObjectDesc>,
+    },
+    // An object moving through keyframes over the time the shutter is open, see `Animation`
+    Animated {
+        keys: Vec<KeyframeDesc>,
+        object: Box<ObjectDesc>,
+    },
+    // A copy of an entry in `geometry`, sharing its memory, optionally in another material.
+    // Geometry made of many objects should be a `bvh` so that copies are fast to hit. Keys
+    // animate the copy after its transform.
+    Instance {
+        geometry: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        transform: Vec<TransformOp>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        keys: Vec<KeyframeDesc>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+    List {
+        objects: Vec<ObjectDesc>,
+    },
+    Bvh {
+        objects: Vec<ObjectDesc>,
+    },
+    Obj {
+        file: String,
+        scale: f64,
+    },
+    // Scanned meshes, smooth shaded with normals averaged from the faces if `smooth`. A
+    // PLY file's vertex colors are used when no material is given.
+    Ply {
+        file: String,
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default, skip_serializing_if = "is_false")]
+        smooth: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+    Stl {
+        file: String,
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default, skip_serializing_if = "is_false")]
+        smooth: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+    // The meshes and punctual lights of a glTF scene, the lights also being sampled
+    Gltf {
+        file: String,
+    },
+    // Triangles indexing into shared vertex buffers, see `TriangleMesh`
+    Mesh {
+        positions: Vec<[f64; 3]>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        normals: Vec<[f64; 3]>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        uvs: Vec<[f64; 2]>,
+        faces: Vec<FaceDesc>,
+        materials: Vec<String>,
+    },
+}
+
+// One step of a transform. Rotations are counterclockwise when looking down the axis, and
+// a matrix is given row by row with a last row of 0 0 0 1.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TransformOp {
+    Translate { offset: [f64; 3] },
+    Rotate { axis: [f64; 3], angle: f64 },
+    Scale { factors: [f64; 3] },
+    Matrix { rows: [[f64; 4]; 4] },
+}
+
+impl TransformOp {
+    pub fn matrix(&self) -> Matrix4 {
+        match self {
+            TransformOp::Translate { offset } => Matrix4::translation(&vec3(*offset)),
+            TransformOp::Rotate { axis, angle } => Matrix4::rotation(&vec3(*axis), *angle),
+            TransformOp::Scale { factors } => Matrix4::scaling(&vec3(*factors)),
+            TransformOp::Matrix { rows } => Matrix4::new(*rows),
+        }
+    }
+}
+
+// Where an animated object is at `time`: scaled, then rotated, then translated
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyframeDesc {
+    pub time: f64,
+    #[serde(default)]
+    pub translate: [f64; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotate: Option<RotationDesc>,
+    #[serde(default = "unit_scale")]
+    pub scale: [f64; 3],
+}
+
+// A counterclockwise rotation by `angle` degrees when looking down `axis`
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RotationDesc {
+    pub axis: [f64; 3],
+    pub angle: f64,
+}
+
+// The steps of a transform as one matrix, later steps applying on top of earlier ones
+fn transform_matrix(transform: &[TransformOp], context: &str) -> BuildResult<Matrix4> {
+    let mut matrix = Matrix4::IDENTITY;
+    for (i, op) in transform.iter().enumerate() {
+        if let TransformOp::Rotate { axis, .. } = op {
+            check_axis(axis, || format!("{}.transform[{}].axis", context, i))?;
+        }
+        matrix = op.matrix() * matrix;
+    }
+    Ok(matrix)
+}
+
+fn animation(keys: &[KeyframeDesc], context: &str) -> BuildResult<Animation> {
+    let mut keyframes = vec![];
+    for (i, key) in keys.iter().enumerate() {
+        let rotation = match &key.rotate {
+            Some(rotate) => {
+                check_axis(&rotate.axis, || {
+                    format!("{}.keys[{}].rotate.axis", context, i)
+                })?;
+                Quaternion::from_axis_angle(&vec3(rotate.axis), rotate.angle)
+            }
+            None => Quaternion::IDENTITY,
+        };
+        keyframes.push(Keyframe {
+            time: key.time,
+            translation: vec3(key.translate),
+            rotation,
+            scale: vec3(key.scale),
+        });
+    }
+    Animation::new(keyframes).map_err(|err| (String::from(context), err))
+}
+
+// A rotation axis has no direction to normalize when it is zero or not finite.
+fn check_axis(axis: &[f64; 3], context: impl Fn() -> String) -> BuildResult<()> {
+    let length = vec3(*axis).length();
+    if length == 0.0 {
+        Err((context(), String::from("must not be zero")))
+    } else if !length.is_finite() {
+        Err((context(), String::from("must be finite")))
+    } else {
+        Ok(())
+    }
+}
+
+// Indices of a mesh triangle's vertices, and optionally of its vertex normals and texture
+// coordinates, plus the index of its material in the mesh's list.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FaceDesc {
+    pub vertices: [u32; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normals: Option<[u32; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uvs: Option<[u32; 3]>,
+    #[serde(default)]
+    pub material: u32,
+}
+
+pub struct SceneError {
+    message: String,
+}
+
+impl SceneError {
+    fn new(path: &Path, message: impl fmt::Display) -> Self {
+        Self {
+            message: format!("{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+pub fn is_scene_file(name: &str) -> bool {
+    matches!(
+        Path::new(name).extension().and_then(|ext| ext.to_str()),
+        Some("json" | "yaml" | "yml")
+    )
+}
+
+pub fn read(path: &Path) -> Result<SceneDesc, SceneError> {
+    let text = std::fs::read_to_string(path).map_err(|err| SceneError::new(path, err))?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml" | "yml") => {
+            serde_yaml::from_str(&text).map_err(|err| SceneError::new(path, err))
+        }
+        _ => serde_json::from_str(&text).map_err(|err| SceneError::new(path, err)),
+    }
+}
+
+// Build a description read from `path`, which relative file names are resolved against.
+pub fn build(path: &Path, desc: &SceneDesc) -> Result<Scene, SceneError> {
+    let mut builder = Builder::new(path, desc);
+    builder
+        .build()
+        .map_err(|(context, message)| SceneError::new(path, format!("{}: {}", context, message)))
+}
+
+pub fn write(path: &Path, desc: &SceneDesc) -> Result<(), SceneError> {
+    let text = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml" | "yml") => {
+            serde_yaml::to_string(desc).map_err(|err| SceneError::new(path, err))?
+        }
+        _ => serde_json::to_string_pretty(desc).map_err(|err| SceneError::new(path, err))?,
+    };
+    std::fs::write(path, text).map_err(|err| SceneError::new(path, err))
+}
+
+// Describe an in-memory scene so that `build` gives back the same world, lights and camera
+// once it is written to `path`.
+pub fn export(
+    world: &HittableList,
+    lights: &HittableList,
+    camera: &Camera,
+    path: &Path,
+) -> Result<SceneDesc, String> {
+    let mut writer = SceneWriter::new(path);
+
+    // The loader wraps the top level in a BVH again, so unwrap the one the scene built.
+    let mut objects = vec![];
+    for object in world.objects.iter() {
+        match writer.object(object.as_ref())? {
+            ObjectDesc::List { objects: inner } | ObjectDesc::Bvh { objects: inner }
+                if world.objects.len() == 1 =>
+            {
+                objects.extend(inner)
+            }
+            desc => objects.push(desc),
+        }
+    }
+    let lights = lights
+        .objects
+        .iter()
+        .map(|object| writer.object(object.as_ref()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(SceneDesc {
+        image: ImageDesc {
+            aspect_ratio: camera.aspect_ratio,
+            image_width: camera.image_width,
+            quality: camera.quality,
+            samples_per_pixel: camera.samples_per_pixel,
+            max_depth: camera.max_depth,
+            background: array(&camera.background),
+        },
+        camera: CameraDesc {
+            vfov: camera.vfov,
+            look_from: array(&camera.look_from),
+            look_at: array(&camera.look_at),
+            vup: array(&camera.vup),
+            defocus_angle: camera.defocus_angle,
+            focus_dist: camera.focus_dist,
+            shutter_open: camera.shutter.open,
+            shutter_close: camera.shutter.close,
+            shutter_curve: camera.shutter.curve.clone(),
+        },
+        textures: writer.textures,
+        materials: writer.materials,
+        geometry: writer.geometry,
+        objects,
+        lights,
+        sequence: None,
+    })
+}
+
+// `path` relative to `dir`, both absolute, or None if they are on different roots such as
+// two Windows drives.
+fn relative_path(dir: &Path, path: &Path) -> Option<PathBuf> {
+    let dir: Vec<_> = dir.components().collect();
+    let path: Vec<_> = path.components().collect();
+    if dir.first() != path.first() {
+        return None;
+    }
+    let common = dir.iter().zip(&path).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in common..dir.len() {
+        relative.push("..");
+    }
+    relative.extend(&path[common..]);
+    Some(relative)
+}
+
+pub type DescribeResult<T> = Result<T, String>;
+
+// Collects the named textures, materials and geometry while the object tree is being
+// described. Shared trait objects are written once and referred to by name afterwards.
+pub struct SceneWriter {
+    textures: BTreeMap<String, TextureDesc>,
+    materials: BTreeMap<String, MaterialDesc>,
+    geometry: BTreeMap<String, ObjectDesc>,
+    texture_names: HashMap<*const (), String>,
+    material_names: HashMap<*const (), String>,
+    geometry_names: HashMap<*const (), String>,
+    // Where the scene is written, which the files it names are relative to
+    dir: Option<PathBuf>,
+}
+
+impl SceneWriter {
+    fn new(path: &Path) -> Self {
+        let dir = match path.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+        Self {
+            textures: BTreeMap::new(),
+            materials: BTreeMap::new(),
+            geometry: BTreeMap::new(),
+            texture_names: HashMap::new(),
+            material_names: HashMap::new(),
+            geometry_names: HashMap::new(),
+            dir: std::fs::canonicalize(dir).ok(),
+        }
+    }
+
+    // How the scene names a file given by its absolute path: relative to the scene's
+    // directory where there is such a path, so the two can be moved together.
+    pub fn file_name(&self, path: &Path) -> String {
+        let relative = self.dir.as_deref().and_then(|dir| relative_path(dir, path));
+        relative
+            .as_deref()
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    pub fn texture(&mut self, tex: &Arc<dyn Texture>) -> DescribeResult<TextureRef> {
+        let key = Arc::as_ptr(tex) as *const ();
+        if let Some(name) = self.texture_names.get(&key) {
+            return Ok(TextureRef::Named(name.clone()));
+        }
+        match tex.describe(self)? {
+            TextureDesc::Solid { color } => Ok(TextureRef::Color(color)),
+            desc => {
+                let name = format!("texture{}", self.textures.len());
+                self.textures.insert(name.clone(), desc);
+                self.texture_names.insert(key, name.clone());
+                Ok(TextureRef::Named(name))
+            }
+        }
+    }
+
+    // Like `texture`, but writes a uniform gray as a plain number.
+    pub fn scalar(&mut self, tex: &Arc<dyn Texture>) -> DescribeResult<TextureRef> {
+        Ok(match self.texture(tex)? {
+            TextureRef::Color([r, g, b]) if r == g && g == b => TextureRef::Value(r),
+            other => other,
+        })
+    }
+
+    pub fn material(&mut self, mat: &Arc<dyn Material>) -> DescribeResult<String> {
+        let key = Arc::as_ptr(mat) as *const ();
+        if let Some(name) = self.material_names.get(&key) {
+            return Ok(name.clone());
+        }
+        let desc = mat.describe(self)?;
+        let name = format!("material{}", self.materials.len());
+        self.materials.insert(name.clone(), desc);
+        self.material_names.insert(key, name.clone());
+        Ok(name)
+    }
+
+    pub fn object(&mut self, object: &dyn Hittable) -> DescribeResult<ObjectDesc> {
+        object.describe(self)
+    }
+
+    // Name the geometry shared by instances.
+    pub fn geometry(&mut self, object: &Arc<dyn Hittable>) -> DescribeResult<String> {
+        let key = Arc::as_ptr(object) as *const ();
+        if let Some(name) = self.geometry_names.get(&key) {
+            return Ok(name.clone());
+        }
+        let desc = object.describe(self)?;
+        let name = format!("geometry{}", self.geometry.len());
+        self.geometry.insert(name.clone(), desc);
+        self.geometry_names.insert(key, name.clone());
+        Ok(name)
+    }
+}
+
+pub fn cannot_describe<T>(type_name: &str) -> DescribeResult<T> {
+    Err(format!("`{}` cannot be written to a scene file", type_name))
+}
+
+type BuildResult<T> = Result<T, (String, String)>;
+
+struct Builder<'a> {
+    dir: PathBuf,
+    desc: &'a SceneDesc,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    geometry: HashMap<String, Arc<dyn Hittable>>,
+    resolving: Vec<String>,
+    // Lights that came with imported objects
+    imported_lights: HittableList,
+}
+
+impl<'a> Builder<'a> {
+    fn new(path: &Path, desc: &'a SceneDesc) -> Self {
+        Self {
+            dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            desc,
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            geometry: HashMap::new(),
+            resolving: vec![],
+            imported_lights: HittableList::new(),
+        }
+    }
+
+    fn build(&mut self) -> BuildResult<Scene> {
+        let desc = self.desc;
+        let mut world = HittableList::new();
+        for (i, object) in desc.objects.iter().enumerate() {
+            world.add(self.object(object, &format!("objects[{}]", i))?);
+        }
+        let mut lights = HittableList::new();
+        for (i, object) in desc.lights.iter().enumerate() {
+            lights.add(self.object(object, &format!("lights[{}]", i))?);
+        }
+        lights.objects.append(&mut self.imported_lights.objects);
+        if !world.objects.is_empty() {
+            world = HittableList::new_from(Arc::new(BvhNode::from_list(&mut world)));
+        }
+
+        let image = &desc.image;
+        if image.image_width == 0
+            || image.samples_per_pixel == 0
+            || !(image.aspect_ratio > 0.0 && image.aspect_ratio.is_finite())
+        {
+            return Err((
+                String::from("image"),
+                String::from("image_width, samples_per_pixel and aspect_ratio must be positive"),
+            ));
+        }
+        if image.max_depth < 1 {
+            return Err((
+                String::from("image.max_depth"),
+                String::from("there must be at least one bounce"),
+            ));
+        }
+        let image_settings = ImageSettings {
+            aspect_ratio: image.aspect_ratio,
+            image_width: image.image_width,
+            quality: image.quality,
+            samples_per_pixel: image.samples_per_pixel,
+            max_depth: image.max_depth,
+            background: vec3(image.background),
+        };
+        let camera = &desc.camera;
+        let camera_settings = CameraSettings {
+            vfov: camera.vfov,
+            look_from: vec3(camera.look_from),
+            look_at: vec3(camera.look_at),
+            vup: vec3(camera.vup),
+            defocus_angle: camera.defocus_angle,
+            focus_dist: camera.focus_dist,
+            shutter: Shutter::new(
+                camera.shutter_open,
+                camera.shutter_close,
+                camera.shutter_curve.clone(),
+            )
+            .map_err(|err| (String::from("camera"), err))?,
+        };
+
+        Ok(Scene {
+            world,
+            lights,
+            image_settings,
+            camera_settings,
+        })
+    }
+
+    fn resolve_path(&self, file: &str) -> String {
+        let relative = self.dir.join(file);
+        if relative.exists() {
+            relative.to_string_lossy().into_owned()
+        } else {
+            String::from(file)
+        }
+    }
+
+    fn texture_ref(&mut self, tex: &TextureRef, context: &str) -> BuildResult<Arc<dyn Texture>> {
+        match tex {
+            TextureRef::Color(color) => Ok(Arc::new(SolidColor::new(&vec3(*color)))),
+            TextureRef::Value(v) => Ok(Arc::new(SolidColor::new(&Vec3::new(*v, *v, *v)))),
+            TextureRef::Named(name) => self.texture(name, context),
+        }
+    }
+
+    fn optional_texture_ref(
+        &mut self,
+        tex: &Option<TextureRef>,
+        default: Arc<dyn Texture>,
+        context: &str,
+    ) -> BuildResult<Arc<dyn Texture>> {
+        match tex {
+            Some(tex) => self.texture_ref(tex, context),
+            None => Ok(default),
+        }
+    }
+
+    fn texture(&mut self, name: &str, context: &str) -> BuildResult<Arc<dyn Texture>> {
+        if let Some(tex) = self.textures.get(name) {
+            return Ok(tex.clone());
+        }
+        let desc = self
+            .desc
+            .textures
+            .get(name)
+            .ok_or_else(|| (String::from(context), format!("unknown texture `{}`", name)))?;
+        let context = format!("textures.{}", name);
+        if self.resolving.contains(&context) {
+            return Err((context, String::from("texture refers to itself")));
+        }
+        self.resolving.push(context.clone());
+        let tex: Arc<dyn Texture> = match desc {
+            TextureDesc::Solid { color } => Arc::new(SolidColor::new(&vec3(*color))),
+            TextureDesc::Checker { scale, even, odd } => Arc::new(CheckerTexture::new(
+                *scale,
+                self.texture_ref(even, &context)?,
+                self.texture_ref(odd, &context)?,
+            )),
+            TextureDesc::Image {
+                file,
+                linear,
+                repeat,
+            } => {
+                let file = self.resolve_path(file);
+                let tex = ImageTexture::open(&file, *linear)
+                    .ok_or_else(|| (context.clone(), format!("cannot read '{}'", file)))?;
+                if *repeat {
+                    Arc::new(tex.repeating())
+                } else {
+                    Arc::new(tex)
+                }
+            }
+            TextureDesc::Noise { scale, seed } => Arc::new(match seed {
+                Some(seed) => NoiseTexture::with_seed(*scale, *seed),
+                None => NoiseTexture::new(*scale),
+            }),
+            TextureDesc::Scale { texture, scale } => Arc::new(ScaledTexture::new(
+                self.texture_ref(texture, &context)?,
+                &vec3(*scale),
+            )),
+            TextureDesc::Channel { texture, channel } => {
+                if *channel > 2 {
+                    return Err((context, String::from("channel must be 0, 1 or 2")));
+                }
+                Arc::new(ChannelTexture::new(
+                    self.texture_ref(texture, &context)?,
+                    *channel,
+                ))
+            }
+            TextureDesc::VertexColors { colors, faces } => {
+                if faces.is_empty() || faces.iter().flatten().any(|&i| i as usize >= colors.len()) {
+                    return Err((context, String::from("faces must index into colors")));
+                }
+                Arc::new(VertexColorTexture::new(
+                    colors.iter().map(|c| vec3(*c)).collect(),
+                    faces.clone(),
+                ))
+            }
+        };
+        self.resolving.pop();
+        self.textures.insert(String::from(name), tex.clone());
+        Ok(tex)
+    }
+
+    fn material(&mut self, name: &str, context: &str) -> BuildResult<Arc<dyn Material>> {
+        if let Some(mat) = self.materials.get(name) {
+            return Ok(mat.clone());
+        }
+        let desc = self.desc.materials.get(name).ok_or_else(|| {
+            (
+                String::from(context),
+                format!("unknown material `{}`", name),
+            )
+        })?;
+        let context = format!("materials.{}", name);
+        let mat: Arc<dyn Material> = match desc {
+            MaterialDesc::Lambertian { albedo } => {
+                Arc::new(Lambertian::new_tex(self.texture_ref(albedo, &context)?))
+            }
+            MaterialDesc::Metal { albedo, fuzz } => Arc::new(Metal::new(vec3(*albedo), *fuzz)),
+            MaterialDesc::Dielectric {
+                refraction_index,
+                roughness,
+                absorption,
+            } => Arc::new(Dielectric::glass(
+                Ior::from(refraction_index),
+                *roughness,
+                vec3(*absorption),
+            )),
+            MaterialDesc::ThinDielectric { refraction_index } => {
+                Arc::new(ThinDielectric::new(*refraction_index))
+            }
+            MaterialDesc::Microfacet {
+                base_color,
+                roughness,
+                metalness,
+            } => Arc::new(Microfacet::new(
+                self.texture_ref(base_color, &context)?,
+                *roughness,
+                *metalness,
+            )),
+            MaterialDesc::Conductor { eta, k, roughness } => {
+                Arc::new(Microfacet::conductor(vec3(*eta), vec3(*k), *roughness))
+            }
+            MaterialDesc::Principled(principled) => {
+                let PrincipledDesc {
+                    base_color,
+                    metallic,
+                    roughness,
+                    specular,
+                    sheen,
+                    sheen_tint,
+                    clearcoat,
+                    clearcoat_roughness,
+                    transmission,
+                    ior,
+                } = principled.as_ref();
+                let defaults = PrincipledParams::default();
+                Arc::new(Principled::new(PrincipledParams {
+                    base_color: self.texture_ref(base_color, &context)?,
+                    metallic: self.optional_texture_ref(metallic, defaults.metallic, &context)?,
+                    roughness: self.optional_texture_ref(
+                        roughness,
+                        defaults.roughness,
+                        &context,
+                    )?,
+                    specular: self.optional_texture_ref(specular, defaults.specular, &context)?,
+                    sheen: self.optional_texture_ref(sheen, defaults.sheen, &context)?,
+                    sheen_tint: self.optional_texture_ref(
+                        sheen_tint,
+                        defaults.sheen_tint,
+                        &context,
+                    )?,
+                    clearcoat: self.optional_texture_ref(
+                        clearcoat,
+                        defaults.clearcoat,
+                        &context,
+                    )?,
+                    clearcoat_roughness: self.optional_texture_ref(
+                        clearcoat_roughness,
+                        defaults.clearcoat_roughness,
+                        &context,
+                    )?,
+                    transmission: self.optional_texture_ref(
+                        transmission,
+                        defaults.transmission,
+                        &context,
+                    )?,
+                    ior: *ior,
+                }))
+            }
+            MaterialDesc::DiffuseLight { emit } => {
+                Arc::new(DiffuseLight::new_tex(self.texture_ref(emit, &context)?))
+            }
+            MaterialDesc::SpotLight {
+                emit,
+                direction,
+                inner_angle,
+                outer_angle,
+            } => Arc::new(SpotLight::new(
+                &vec3(*emit),
+                &vec3(*direction),
+                *inner_angle,
+                *outer_angle,
+            )),
+            MaterialDesc::Isotropic { albedo } => {
+                Arc::new(Isotropic::new_tex(self.texture_ref(albedo, &context)?))
+            }
+            MaterialDesc::NormalMap {
+                material,
+                map,
+                strength,
+            } => Arc::new(NormalMapped::new(
+                self.wrapped_material(name, material, &context)?,
+                self.texture_ref(map, &context)?,
+                NormalMapKind::Normal {
+                    strength: *strength,
+                },
+            )),
+            MaterialDesc::BumpMap {
+                material,
+                height,
+                scale,
+            } => Arc::new(NormalMapped::new(
+                self.wrapped_material(name, material, &context)?,
+                self.texture_ref(height, &context)?,
+                NormalMapKind::Bump { scale: *scale },
+            )),
+            MaterialDesc::Opacity { material, opacity } => Arc::new(Opacity::new(
+                self.wrapped_material(name, material, &context)?,
+                self.texture_ref(opacity, &context)?,
+            )),
+        };
+        self.materials.insert(String::from(name), mat.clone());
+        Ok(mat)
+    }
+
+    // The material wrapped by `name`, which must not lead back to `name` itself.
+    fn wrapped_material(
+        &mut self,
+        name: &str,
+        inner: &str,
+        context: &str,
+    ) -> BuildResult<Arc<dyn Material>> {
+        let key = format!("materials.{}", name);
+        if self.resolving.contains(&key) {
+            return Err((key, String::from("material refers to itself")));
+        }
+        self.resolving.push(key);
+        let mat = self.material(inner, context)?;
+        self.resolving.pop();
+        Ok(mat)
+    }
+
+    fn object(&mut self, desc: &ObjectDesc, context: &str) -> BuildResult<Arc<dyn Hittable>> {
+        let object: Arc<dyn Hittable> = match desc {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+                center2,
+            } => {
+                let mat = self.material(material, context)?;
+                match center2 {
+                    Some(center2) => Arc::new(Sphere::moving(
+                        &vec3(*center),
+                        *radius,
+                        mat,
+                        &vec3(*center2),
+                    )),
+                    None => Arc::new(Sphere::new(&vec3(*center), *radius, mat)),
+                }
+            }
+            ObjectDesc::Quad { q, u, v, material } => Arc::new(Quad::new(
+                &vec3(*q),
+                &vec3(*u),
+                &vec3(*v),
+                self.material(material, context)?,
+            )),
+            ObjectDesc::Triangle {
+                a,
+                b,
+                c,
+                material,
+                normals: None,
+                uvs: None,
+            } => Arc::new(Triangle::new(
+                &vec3(*a),
+                &vec3(*b),
+                &vec3(*c),
+                self.material(material, context)?,
+            )),
+            ObjectDesc::Triangle {
+                a,
+                b,
+                c,
+                material,
+                normals,
+                uvs,
+            } => Arc::new(MeshTriangle::new(
+                &[vec3(*a), vec3(*b), vec3(*c)],
+                normals.map(|n| n.map(vec3)),
+                uvs.map(|uv| uv.map(|[u, v]| (u, v))),
+                self.material(material, context)?,
+            )),
+            ObjectDesc::Cuboid { a, b, material } => {
+                cuboid(&vec3(*a), &vec3(*b), self.material(material, context)?)
+            }
+            ObjectDesc::ConstantMedium {
+                boundary,
+                density,
+                albedo,
+            } => Arc::new(ConstantMedium::new_tex(
+                self.object(boundary, &format!("{}.boundary", context))?,
+                *density,
+                self.texture_ref(albedo, context)?,
+            )),
+            ObjectDesc::Translate { offset, object } => Arc::new(Translate::new(
+                self.object(object, &format!("{}.object", context))?,
+                &vec3(*offset),
+            )),
+            ObjectDesc::RotateY { angle, object } => Arc::new(RotateY::new(
+                self.object(object, &format!("{}.object", context))?,
+                *angle,
+            )),
+            ObjectDesc::Transform { transform, object } => {
+                let object = self.object(object, &format!("{}.object", context))?;
+                Arc::new(
+                    Transform::new(object, &transform_matrix(transform, context)?)
+                        .map_err(|err| (String::from(context), err))?,
+                )
+            }
+            ObjectDesc::Animated { keys, object } => Arc::new(AnimatedTransform::new(
+                self.object(object, &format!("{}.object", context))?,
+                animation(keys, context)?,
+            )),
+            ObjectDesc::Instance {
+                geometry,
+                transform,
+                keys,
+                material,
+            } => {
+                let geometry = self.geometry(geometry, context)?;
+                let material = match material {
+                    Some(name) => Some(self.material(name, context)?),
+                    None => None,
+                };
+                let animation = if keys.is_empty() {
+                    None
+                } else {
+                    Some(animation(keys, context)?)
+                };
+                Arc::new(
+                    Instance::new(
+                        geometry,
+                        &transform_matrix(transform, context)?,
+                        animation,
+                        material,
+                    )
+                    .map_err(|err| (String::from(context), err))?,
+                )
+            }
+            ObjectDesc::List { objects } => Arc::new(self.list(objects, context)?),
+            ObjectDesc::Bvh { objects } => {
+                let mut list = self.list(objects, context)?;
+                if list.objects.is_empty() {
+                    return Err((String::from(context), String::from("empty bvh")));
+                }
+                Arc::new(BvhNode::from_list(&mut list))
+            }
+            ObjectDesc::Obj { file, scale } => Arc::new(
+                read_obj(Path::new(&self.resolve_path(file)), *scale)
+                    .map_err(|err| (String::from(context), err))?,
+            ),
+            ObjectDesc::Ply {
+                file,
+                scale,
+                smooth,
+                material,
+            } => {
+                let material = match material {
+                    Some(name) => Some(self.material(name, context)?),
+                    None => None,
+                };
+                Arc::new(
+                    read_ply(
+                        Path::new(&self.resolve_path(file)),
+                        *scale,
+                        *smooth,
+                        material,
+                    )
+                    .map_err(|err| (String::from(context), err))?,
+                )
+            }
+            ObjectDesc::Stl {
+                file,
+                scale,
+                smooth,
+                material,
+            } => {
+                let material = match material {
+                    Some(name) => Some(self.material(name, context)?),
+                    None => None,
+                };
+                Arc::new(
+                    read_stl(
+                        Path::new(&self.resolve_path(file)),
+                        *scale,
+                        *smooth,
+                        material,
+                    )
+                    .map_err(|err| (String::from(context), err))?,
+                )
+            }
+            ObjectDesc::Gltf { file } => {
+                let scene = read_gltf(Path::new(&self.resolve_path(file)))
+                    .map_err(|err| (String::from(context), err))?;
+                self.imported_lights.objects.extend(scene.lights.objects);
+                Arc::new(scene.world)
+            }
+            ObjectDesc::Mesh {
+                positions,
+                normals,
+                uvs,
+                faces,
+                materials,
+            } => {
+                let materials = materials
+                    .iter()
+                    .map(|name| self.material(name, context))
+                    .collect::<BuildResult<Vec<_>>>()?;
+                let faces = faces
+                    .iter()
+                    .map(|face| Face {
+                        vertices: face.vertices,
+                        normals: face.normals,
+                        uvs: face.uvs,
+                        material: face.material,
+                    })
+                    .collect();
+                let mesh = TriangleMesh::new(
+                    positions.iter().map(|p| vec3(*p)).collect(),
+                    normals.iter().map(|n| vec3(*n)).collect(),
+                    uvs.iter().map(|&[u, v]| (u, v)).collect(),
+                    faces,
+                    materials,
+                )
+                .map_err(|e| (String::from(context), e))?;
+                Arc::new(mesh)
+            }
+        };
+        Ok(object)
+    }
+
+    fn geometry(&mut self, name: &str, context: &str) -> BuildResult<Arc<dyn Hittable>> {
+        if let Some(object) = self.geometry.get(name) {
+            return Ok(object.clone());
+        }
+        let desc = self.desc.geometry.get(name).ok_or_else(|| {
+            (
+                String::from(context),
+                format!("unknown geometry `{}`", name),
+            )
+        })?;
+        let context = format!("geometry.{}", name);
+        if self.resolving.contains(&context) {
+            return Err((context, String::from("geometry contains itself")));
+        }
+        self.resolving.push(context.clone());
+        let object = self.object(desc, &context)?;
+        self.resolving.pop();
+        self.geometry.insert(String::from(name), object.clone());
+        Ok(object)
+    }
+
+    fn list(&mut self, objects: &[ObjectDesc], context: &str) -> BuildResult<HittableList> {
+        let mut list = HittableList::new();
+        for (i, object) in objects.iter().enumerate() {
+            list.add(self.object(object, &format!("{}.objects[{}]", context, i))?);
+        }
+        Ok(list)
+    }
+}
+
+fn default_step() -> f64 {
+    1.0
+}
+
+fn unit_scale() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_strength() -> f64 {
+    1.0
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+fn default_ior() -> f64 {
+    PrincipledParams::default().ior
+}
+
+fn vec3(v: [f64; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+pub fn array(v: &Vec3) -> [f64; 3] {
+    [v.x, v.y, v.z]
+}
+
+impl Serialize for TextureRef {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            TextureRef::Color(color) => color.serialize(serializer),
+            TextureRef::Value(v) => serializer.serialize_f64(*v),
+            TextureRef::Named(name) => serializer.serialize_str(name),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TextureRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TextureRefVisitor;
+
+        impl<'de> Visitor<'de> for TextureRefVisitor {
+            type Value = TextureRef;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an [r, g, b] color, a gray level or the name of a texture")
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<TextureRef, E> {
+                Ok(TextureRef::Value(v))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<TextureRef, E> {
+                Ok(TextureRef::Value(v as f64))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<TextureRef, E> {
+                Ok(TextureRef::Value(v as f64))
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<TextureRef, E> {
+                Ok(TextureRef::Named(String::from(name)))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<TextureRef, A::Error> {
+                let mut color = [0.0; 3];
+                for (i, c) in color.iter_mut().enumerate() {
+                    *c = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+                }
+                if seq.next_element::<de::IgnoredAny>()?.is_some() {
+                    return Err(de::Error::invalid_length(4, &self));
+                }
+                Ok(TextureRef::Color(color))
+            }
+        }
+
+        deserializer.deserialize_any(TextureRefVisitor)
+    }
+}
@@ -0,0 +1,600 @@
+use crate::bvh::BvhNode;
+use crate::camera::{CameraSettings, ImageSettings, Shutter};
+use crate::color::Color;
+use crate::hittable_list::HittableList;
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::medium::ConstantMedium;
+use crate::obj::read_obj;
+use crate::quad::{cuboid, Quad};
+use crate::rtweekend::{random_double, random_double_range};
+use crate::sphere::Sphere;
+use crate::texture::{CheckerTexture, ImageTexture, NoiseTexture};
+use crate::translate::{RotateY, Translate};
+use crate::vec3::{Point3, Vec3};
+use console::style;
+use std::path::Path;
+use std::process::exit;
+use std::sync::Arc;
+
+pub struct Scene {
+    pub world: HittableList,
+    pub lights: HittableList,
+    pub image_settings: ImageSettings,
+    pub camera_settings: CameraSettings,
+}
+
+// Built-in scenes as (name, default output path).
+pub const BUILTIN: [(&str, &str); 6] = [
+    ("bouncing_spheres", "output/book2/image2.jpg"),
+    ("earth", "output/book2/image5.jpg"),
+    ("perlin", "output/book2/image15.jpg"),
+    ("quads", "output/book2/image16.jpg"),
+    ("cornell_box", "output/advanced/imagetest2.jpg"),
+    ("final_scene", "output/book2/image23.jpg"),
+];
+
+pub fn default_output(name: &str) -> Option<&'static str> {
+    BUILTIN
+        .iter()
+        .find(|(scene, _)| *scene == name)
+        .map(|(_, output)| *output)
+}
+
+pub fn build(name: &str) -> Option<Scene> {
+    match name {
+        "bouncing_spheres" => Some(bouncing_spheres()),
+        "earth" => Some(earth()),
+        "perlin" => Some(perlin()),
+        "quads" => Some(quads()),
+        "cornell_box" => Some(cornell_box()),
+        "final_scene" => Some(final_scene()),
+        _ => None,
+    }
+}
+
+fn bouncing_spheres() -> Scene {
+    // Materials
+    let checker = Arc::new(CheckerTexture::new_color(
+        0.32,
+        &Color::new(0.2, 0.3, 0.1),
+        &Color::new(0.9, 0.9, 0.9),
+    ));
+    let material_ground = Arc::new(Lambertian::new_tex(checker));
+    let material1 = Arc::new(Dielectric::new(1.5));
+    let material2 = Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
+    let material3 = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
+
+    // World
+    let mut world: HittableList = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        &Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        material_ground,
+    )));
+    world.add(Arc::new(Sphere::new(
+        &Point3::new(0.0, 1.0, 0.0),
+        1.0,
+        material1,
+    )));
+    world.add(Arc::new(Sphere::new(
+        &Point3::new(-4.0, 1.0, 0.0),
+        1.0,
+        material2,
+    )));
+    world.add(Arc::new(Sphere::new(
+        &Point3::new(4.0, 1.0, 0.0),
+        1.0,
+        material3,
+    )));
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat = random_double();
+            let center = Point3::new(
+                a as f64 + 0.9 * random_double(),
+                0.2,
+                b as f64 + 0.9 * random_double(),
+            );
+
+            if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                let sphere_material: Arc<dyn Material> = if choose_mat < 0.8 {
+                    let albedo = Color::random() * Color::random();
+                    Arc::new(Lambertian::new(albedo))
+                } else if choose_mat < 0.95 {
+                    let albedo = Color::random_in(0.5, 1.0);
+                    let fuzz = random_double_range(0.0, 0.5);
+                    Arc::new(Metal::new(albedo, fuzz))
+                } else {
+                    Arc::new(Dielectric::new(1.5))
+                };
+                if choose_mat < 0.8 {
+                    let center2 = center + Vec3::new(0.0, random_double_range(0.0, 0.5), 0.0);
+                    world.add(Arc::new(Sphere::moving(
+                        &center,
+                        0.2,
+                        sphere_material,
+                        &center2,
+                    )));
+                } else {
+                    world.add(Arc::new(Sphere::new(&center, 0.2, sphere_material)));
+                }
+            }
+        }
+    }
+
+    let world = HittableList::new_from(Arc::new(BvhNode::from_list(&mut world)));
+    let lights = HittableList::new();
+
+    let image_settings = ImageSettings {
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 3840,
+        quality: 100,
+        samples_per_pixel: 100,
+        max_depth: 50,
+        background: Color::new(0.7, 0.8, 1.0),
+    };
+
+    let camera_settings = CameraSettings {
+        vfov: 20.0,
+        look_from: Point3::new(13.0, 2.0, 3.0),
+        look_at: Point3::new(0.0, 0.0, 0.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        defocus_angle: 0.6,
+        focus_dist: 10.0,
+        shutter: Shutter::default(),
+    };
+
+    Scene {
+        world,
+        lights,
+        image_settings,
+        camera_settings,
+    }
+}
+
+fn earth() -> Scene {
+    let earth_texture = Arc::new(ImageTexture::new("zbh.jpg"));
+    let earth_surface = Arc::new(Lambertian::new_tex(earth_texture));
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        &Point3::new(0.0, 0.0, 0.0),
+        2.0,
+        earth_surface,
+    )));
+    let world = HittableList::new_from(Arc::new(BvhNode::from_list(&mut world)));
+    let lights = HittableList::new();
+
+    let image_settings = ImageSettings {
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 1920,
+        quality: 100,
+        samples_per_pixel: 100,
+        max_depth: 50,
+        background: Color::new(0.7, 0.8, 1.0),
+    };
+
+    let camera_settings = CameraSettings {
+        vfov: 20.0,
+        look_from: Point3::new(0.0, 0.0, 12.0),
+        look_at: Point3::new(0.0, 0.0, 0.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        defocus_angle: 0.0,
+        focus_dist: 10.0,
+        shutter: Shutter::default(),
+    };
+
+    Scene {
+        world,
+        lights,
+        image_settings,
+        camera_settings,
+    }
+}
+
+fn perlin() -> Scene {
+    let pertext = Arc::new(NoiseTexture::new(4.0));
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        &Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new_tex(pertext.clone())),
+    )));
+    world.add(Arc::new(Sphere::new(
+        &Point3::new(0.0, 2.0, 0.0),
+        2.0,
+        Arc::new(Lambertian::new_tex(pertext)),
+    )));
+    let world = HittableList::new_from(Arc::new(BvhNode::from_list(&mut world)));
+    let lights = HittableList::new();
+
+    let image_settings = ImageSettings {
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        quality: 100,
+        samples_per_pixel: 100,
+        max_depth: 50,
+        background: Color::new(0.7, 0.8, 1.0),
+    };
+
+    let camera_settings = CameraSettings {
+        vfov: 20.0,
+        look_from: Point3::new(13.0, 2.0, 3.0),
+        look_at: Point3::new(0.0, 0.0, 0.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        defocus_angle: 0.0,
+        focus_dist: 10.0,
+        shutter: Shutter::default(),
+    };
+
+    Scene {
+        world,
+        lights,
+        image_settings,
+        camera_settings,
+    }
+}
+
+fn quads() -> Scene {
+    let pertext = Arc::new(NoiseTexture::new(4.0));
+    let left_red = Arc::new(Lambertian::new(Color::new(1.0, 0.2, 0.2)));
+    let back_green = Arc::new(Lambertian::new_tex(pertext));
+    let right_blue = Arc::new(Lambertian::new(Color::new(0.2, 0.2, 1.0)));
+    let upper_orange = Arc::new(Lambertian::new(Color::new(1.0, 0.5, 0.0)));
+    let lower_teal = Arc::new(Lambertian::new(Color::new(0.2, 0.8, 0.8)));
+
+    let mut world = HittableList::new();
+    world.add(Arc::new(Quad::new(
+        &Point3::new(-3.0, -2.0, 5.0),
+        &Vec3::new(0.0, 0.0, -4.0),
+        &Vec3::new(0.0, 4.0, 0.0),
+        left_red,
+    )));
+    world.add(Arc::new(Quad::new(
+        &Point3::new(-2.0, -2.0, 0.0),
+        &Vec3::new(4.0, 0.0, 0.0),
+        &Vec3::new(0.0, 4.0, 0.0),
+        back_green,
+    )));
+    world.add(Arc::new(Quad::new(
+        &Point3::new(3.0, -2.0, 1.0),
+        &Vec3::new(0.0, 0.0, 4.0),
+        &Vec3::new(0.0, 4.0, 0.0),
+        right_blue,
+    )));
+    world.add(Arc::new(Quad::new(
+        &Point3::new(-2.0, 3.0, 1.0),
+        &Vec3::new(4.0, 0.0, 0.0),
+        &Vec3::new(0.0, 0.0, 4.0),
+        upper_orange,
+    )));
+    world.add(Arc::new(Quad::new(
+        &Point3::new(-2.0, -3.0, 5.0),
+        &Vec3::new(4.0, 0.0, 0.0),
+        &Vec3::new(0.0, 0.0, -4.0),
+        lower_teal,
+    )));
+    let world = HittableList::new_from(Arc::new(BvhNode::from_list(&mut world)));
+    let lights = HittableList::new();
+
+    let image_settings = ImageSettings {
+        aspect_ratio: 1.0,
+        image_width: 400,
+        quality: 100,
+        samples_per_pixel: 100,
+        max_depth: 50,
+        background: Color::new(0.7, 0.8, 1.0),
+    };
+
+    let camera_settings = CameraSettings {
+        vfov: 80.0,
+        look_from: Point3::new(0.0, 0.0, 9.0),
+        look_at: Point3::new(0.0, 0.0, 0.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        defocus_angle: 0.0,
+        focus_dist: 10.0,
+        shutter: Shutter::default(),
+    };
+
+    Scene {
+        world,
+        lights,
+        image_settings,
+        camera_settings,
+    }
+}
+
+// A model the built-in scenes cannot do without.
+fn model(file: &str, scale: f64) -> HittableList {
+    read_obj(Path::new(file), scale).unwrap_or_else(|err| {
+        eprintln!("{} {}", style("error:").red(), err);
+        exit(1);
+    })
+}
+
+fn cornell_box() -> Scene {
+    let diffuse = Arc::new(DiffuseLight::new(&Color::new(5.0, 5.0, 5.0)));
+    //let red = Arc::new(Lambertian::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    //let green = Arc::new(Lambertian::new(Color::new(0.12, 0.45, 0.15)));
+    let yellow = Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
+    let level = Arc::new(Lambertian::new_tex(Arc::new(ImageTexture::new("1.png"))));
+    let mut world = HittableList::new();
+    let mut lights = HittableList::new();
+    // world.add(Arc::new(Quad::new(
+    //     &Point3::new(555.0, 0.0, 0.0),
+    //     &Vec3::new(0.0, 555.0, 0.0),
+    //     &Vec3::new(0.0, 0.0, 555.0),
+    //     green,
+    // )));
+    world.add(Arc::new(Quad::new(
+        &Point3::new(-500.0, 0.0, -200.0),
+        &Vec3::new(0.0, 555.0, 0.0),
+        &Vec3::new(0.0, 0.0, 1555.0),
+        yellow.clone(),
+    )));
+    world.add(Arc::new(Quad::new(
+        &Point3::new(0.0, 554.0, 0.0),
+        &Vec3::new(555.0, 0.0, 0.0),
+        &Vec3::new(0.0, 0.0, 555.0),
+        diffuse.clone(),
+    )));
+    lights.add(Arc::new(Quad::new(
+        &Point3::new(0.0, 554.0, 0.0),
+        &Vec3::new(555.0, 0.0, 0.0),
+        &Vec3::new(0.0, 0.0, 555.0),
+        diffuse,
+    )));
+    world.add(Arc::new(Quad::new(
+        &Point3::new(-1000.0, 0.0, -200.0),
+        &Vec3::new(4000.0, 0.0, 0.0),
+        &Vec3::new(0.0, 0.0, 1555.0),
+        yellow,
+    )));
+    world.add(Arc::new(Quad::new(
+        &Point3::new(-1000.0, 555.0, -200.0),
+        &Vec3::new(4000.0, 0.0, 0.0),
+        &Vec3::new(0.0, 0.0, 1555.0),
+        white,
+    )));
+    world.add(Arc::new(Quad::new(
+        &Point3::new(1500.0, 0.0, 1000.0),
+        &Vec3::new(-1500.0, 0.0, 0.0),
+        &Vec3::new(0.0, 555.0, 0.0),
+        level,
+    )));
+
+    let obj = model("objects/monster.obj", 300.0);
+    let obj = RotateY::new(Arc::new(obj), -30.0);
+    let obj = Translate::new(Arc::new(obj), &Vec3::new(-100.0, 100.0, 300.0));
+    world.add(Arc::new(obj));
+    let obj = model("objects/coffin.obj", 300.0);
+    let obj = RotateY::new(Arc::new(obj), 15.0);
+    let obj = Translate::new(Arc::new(obj), &Vec3::new(-400.0, 0.0, 995.0));
+    world.add(Arc::new(obj));
+
+    let obj = model("objects/ClownMask.obj", 300.0);
+    let obj = RotateY::new(Arc::new(obj), 15.0);
+    let obj = Translate::new(Arc::new(obj), &Vec3::new(0.0, 100.0, 995.0));
+    world.add(Arc::new(obj));
+
+    // let obj = model("objects/Stopsign.obj", 30.0);
+    // let obj = RotateY::new(Arc::new(obj), 30.0);
+    // let obj = Translate::new(Arc::new(obj), &Vec3::new(-200.0, 100.0, 500.0));
+    // world.add(Arc::new(obj));
+
+    // let obj = model("objects/RubberDuck.obj", 20.0);
+    // let obj = RotateY::new(Arc::new(obj), 90.0);
+    // let obj = Translate::new(Arc::new(obj), &Vec3::new(-200.0, 100.0, 0.0));
+    // world.add(Arc::new(obj));
+
+    let obj = model("objects/RubberDuck.obj", 20.0);
+    let obj = RotateY::new(Arc::new(obj), 150.0);
+    let obj = Translate::new(Arc::new(obj), &Vec3::new(300.0, 0.0, 555.0));
+    world.add(Arc::new(obj));
+
+    let obj = model("objects/RubberDuck.obj", 20.0);
+    let obj = RotateY::new(Arc::new(obj), 120.0);
+    let obj = Translate::new(Arc::new(obj), &Vec3::new(200.0, 0.0, 455.0));
+    world.add(Arc::new(obj));
+
+    let obj = model("objects/RubberDuck.obj", 20.0);
+    let obj = RotateY::new(Arc::new(obj), 90.0);
+    let obj = Translate::new(Arc::new(obj), &Vec3::new(100.0, 0.0, 355.0));
+    world.add(Arc::new(obj));
+
+    let obj = model("objects/RubberDuck.obj", 20.0);
+    let obj = RotateY::new(Arc::new(obj), 60.0);
+    let obj = Translate::new(Arc::new(obj), &Vec3::new(200.0, 0.0, 255.0));
+    world.add(Arc::new(obj));
+
+    let obj = model("objects/RubberDuck.obj", 20.0);
+    let obj = RotateY::new(Arc::new(obj), 30.0);
+    let obj = Translate::new(Arc::new(obj), &Vec3::new(300.0, 0.0, 155.0));
+    world.add(Arc::new(obj));
+    let obj = model("objects/astronaut.obj", 400.0);
+    let obj = RotateY::new(Arc::new(obj), 150.0);
+    let obj = Translate::new(Arc::new(obj), &Vec3::new(400.0, 100.0, 150.0));
+    world.add(Arc::new(obj));
+    // world.add(Arc::new(Triangle::new(
+    //     &Point3::new(0.0, 0.0, 400.0),
+    //     &Point3::new(256.0, 0.0, 555.0),
+    //     &Point3::new(0.0, 256.0, 555.0),
+    //     white,
+    // )));
+
+    let world = HittableList::new_from(Arc::new(BvhNode::from_list(&mut world)));
+    // let lights = HittableList::new_from(Arc::new(BvhNode::from_list(&mut lights)));
+
+    let image_settings = ImageSettings {
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 1920,
+        quality: 100,
+        samples_per_pixel: 1000,
+        max_depth: 50,
+        background: Color::black(),
+    };
+
+    let camera_settings = CameraSettings {
+        vfov: 40.0,
+        look_from: Point3::new(278.0, 400.0, -800.0),
+        look_at: Point3::new(278.0, 278.0, 0.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        defocus_angle: 0.0,
+        focus_dist: 10.0,
+        shutter: Shutter::default(),
+    };
+
+    Scene {
+        world,
+        lights,
+        image_settings,
+        camera_settings,
+    }
+}
+
+fn final_scene() -> Scene {
+    let mut boxes1 = HittableList::new();
+    let ground = Arc::new(Lambertian::new(Color::new(0.48, 0.83, 0.53)));
+
+    let boxes_per_sides = 20;
+    for i in 0..boxes_per_sides {
+        for j in 0..boxes_per_sides {
+            let w = 100.0;
+            let x0 = -1000.0 + i as f64 * w;
+            let y0 = 0.0;
+            let z0 = -1000.0 + j as f64 * w;
+            let x1 = x0 + w;
+            let y1 = random_double_range(1.0, 101.0);
+            let z1 = z0 + w;
+
+            boxes1.add(cuboid(
+                &Point3::new(x0, y0, z0),
+                &Point3::new(x1, y1, z1),
+                ground.clone(),
+            ));
+        }
+    }
+
+    let mut world = HittableList::new();
+    let mut lights = HittableList::new();
+    world.add(Arc::new(BvhNode::from_list(&mut boxes1)));
+
+    let light = Arc::new(DiffuseLight::new(&Color::new(15.0, 15.0, 15.0)));
+    world.add(Arc::new(Quad::new(
+        &Point3::new(123.0, 554.0, 147.0),
+        &Vec3::new(300.0, 0.0, 0.0),
+        &Vec3::new(0.0, 0.0, 265.0),
+        light.clone(),
+    )));
+    lights.add(Arc::new(Quad::new(
+        &Point3::new(123.0, 554.0, 147.0),
+        &Vec3::new(300.0, 0.0, 0.0),
+        &Vec3::new(0.0, 0.0, 265.0),
+        light,
+    )));
+
+    let center1 = Point3::new(400.0, 400.0, 200.0);
+    let center2 = center1 + Vec3::new(30.0, 0.0, 0.0);
+    let sphere_material = Arc::new(Lambertian::new(Color::new(0.7, 0.3, 0.1)));
+    world.add(Arc::new(Sphere::moving(
+        &center1,
+        50.0,
+        sphere_material,
+        &center2,
+    )));
+
+    world.add(Arc::new(Sphere::new(
+        &Point3::new(260.0, 150.0, 45.0),
+        50.0,
+        Arc::new(Dielectric::new(1.5)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        &Point3::new(0.0, 150.0, 145.0),
+        50.0,
+        Arc::new(Metal::new(Color::new(0.8, 0.8, 0.9), 1.0)),
+    )));
+
+    let mut boundary = Arc::new(Sphere::new(
+        &Point3::new(360.0, 150.0, 145.0),
+        70.0,
+        Arc::new(Dielectric::new(1.5)),
+    ));
+    world.add(boundary.clone());
+    world.add(Arc::new(ConstantMedium::new(
+        boundary,
+        0.2,
+        &Color::new(0.2, 0.4, 0.9),
+    )));
+    boundary = Arc::new(Sphere::new(
+        &Point3::new(0.0, 0.0, 0.0),
+        5000.0,
+        Arc::new(Dielectric::new(1.5)),
+    ));
+    world.add(Arc::new(ConstantMedium::new(
+        boundary,
+        0.0001,
+        &Color::white(),
+    )));
+
+    let e_mat = Arc::new(Lambertian::new_tex(Arc::new(ImageTexture::new(
+        "earthmap.jpg",
+    ))));
+    world.add(Arc::new(Sphere::new(
+        &Point3::new(400.0, 200.0, 400.0),
+        100.0,
+        e_mat,
+    )));
+    let per_text = Arc::new(NoiseTexture::new(0.2));
+    world.add(Arc::new(Sphere::new(
+        &Point3::new(220.0, 280.0, 300.0),
+        80.0,
+        Arc::new(Lambertian::new_tex(per_text)),
+    )));
+
+    let mut boxes2 = HittableList::new();
+    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let ns = 1000;
+    for _j in 0..ns {
+        boxes2.add(Arc::new(Sphere::new(
+            &Point3::random_in(0.0, 165.0),
+            10.0,
+            white.clone(),
+        )));
+    }
+
+    world.add(Arc::new(Translate::new(
+        Arc::new(RotateY::new(
+            Arc::new(BvhNode::from_list(&mut boxes2)),
+            15.0,
+        )),
+        &Vec3::new(-100.0, 270.0, 395.0),
+    )));
+
+    let world = HittableList::new_from(Arc::new(BvhNode::from_list(&mut world)));
+
+    let image_settings = ImageSettings {
+        aspect_ratio: 1.0,
+        image_width: 600,
+        quality: 100,
+        samples_per_pixel: 2500,
+        max_depth: 40,
+        background: Color::black(),
+    };
+
+    let camera_settings = CameraSettings {
+        vfov: 40.0,
+        look_from: Point3::new(478.0, 278.0, -600.0),
+        look_at: Point3::new(278.0, 278.0, 0.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        defocus_angle: 0.0,
+        focus_dist: 10.0,
+        shutter: Shutter::default(),
+    };
+
+    Scene {
+        world,
+        lights,
+        image_settings,
+        camera_settings,
+    }
+}
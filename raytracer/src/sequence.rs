@@ -0,0 +1,129 @@
+use crate::scene_file::{ParamKeyDesc, SceneDesc, SequenceDesc};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+pub fn check(sequence: &SequenceDesc) -> Result<(), String> {
+    if sequence.frames == 0 {
+        return Err(String::from("sequence: there must be at least one frame"));
+    }
+    if !(sequence.start.is_finite() && sequence.step.is_finite() && sequence.step > 0.0) {
+        return Err(String::from(
+            "sequence: start must be finite and step positive",
+        ));
+    }
+    Ok(())
+}
+
+// The description of one frame: the camera and materials as the keys have them at the
+// frame's time, and the shutter open during the frame.
+pub fn frame(desc: &SceneDesc, sequence: &SequenceDesc, frame: u32) -> Result<SceneDesc, String> {
+    let time = sequence.start + sequence.step * frame as f64;
+    let error = |message: &dyn std::fmt::Display| format!("frame {}: {}", frame, message);
+    let mut value = serde_json::to_value(desc).map_err(|err| error(&err))?;
+
+    let camera = &mut value["camera"];
+    check_keys(&sequence.camera).map_err(|err| error(&format!("sequence.camera: {}", err)))?;
+    interpolate_keys(camera, &sequence.camera, time);
+    for end in ["shutter_open", "shutter_close"] {
+        let fraction = camera[end].as_f64().unwrap_or_default();
+        camera[end] = Value::from(time + sequence.step * fraction);
+    }
+
+    for (name, keys) in &sequence.materials {
+        let context = format!("sequence.materials.{}", name);
+        let material = value["materials"]
+            .get_mut(name)
+            .ok_or_else(|| error(&format!("{}: unknown material `{}`", context, name)))?;
+        check_keys(keys).map_err(|err| error(&format!("{}: {}", context, err)))?;
+        interpolate_keys(material, keys, time);
+    }
+
+    if let Some(object) = value.as_object_mut() {
+        object.remove("sequence");
+    }
+    serde_json::from_value(value).map_err(|err| error(&err))
+}
+
+fn check_keys(keys: &[ParamKeyDesc]) -> Result<(), String> {
+    if keys.iter().any(|key| !key.time.is_finite()) {
+        return Err(String::from("key times must be finite"));
+    }
+    if keys.iter().any(|key| key.values.contains_key("type")) {
+        return Err(String::from("keys cannot change the type"));
+    }
+    Ok(())
+}
+
+// Set every parameter that the keys mention to its value at `time`.
+fn interpolate_keys(target: &mut Value, keys: &[ParamKeyDesc], time: f64) {
+    let names: BTreeSet<&String> = keys.iter().flat_map(|key| key.values.keys()).collect();
+    for name in names {
+        let mut keyed: Vec<(f64, &Value)> = keys
+            .iter()
+            .filter_map(|key| key.values.get(name).map(|value| (key.time, value)))
+            .collect();
+        keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let next = keyed.partition_point(|(key_time, _)| *key_time <= time);
+        let value = if next == 0 || next == keyed.len() {
+            keyed[next.saturating_sub(1)].1.clone()
+        } else {
+            let ((t0, a), (t1, b)) = (keyed[next - 1], keyed[next]);
+            lerp(a, b, (time - t0) / (t1 - t0))
+        };
+        target[name.as_str()] = value;
+    }
+}
+
+fn lerp(a: &Value, b: &Value, f: f64) -> Value {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => match (x.as_f64(), y.as_f64()) {
+            (Some(x), Some(y)) => Value::from(x + (y - x) * f),
+            _ => a.clone(),
+        },
+        (Value::Array(xs), Value::Array(ys)) if xs.len() == ys.len() => {
+            Value::Array(xs.iter().zip(ys).map(|(x, y)| lerp(x, y, f)).collect())
+        }
+        _ => a.clone(),
+    }
+}
+
+// The file a frame is written to: the last run of `#` in the file name replaced by the
+// frame number padded to its length, or the number added before the extension.
+pub fn frame_path(template: &Path, frame: u32) -> String {
+    let name = template
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let numbered = match name.rfind('#') {
+        Some(end) => {
+            let start = name[..end].trim_end_matches('#').len();
+            let width = end + 1 - start;
+            format!("{}{:0width$}{}", &name[..start], frame, &name[end + 1..])
+        }
+        None => match name.rsplit_once('.') {
+            Some((stem, extension)) => format!("{}_{:04}.{}", stem, frame, extension),
+            None => format!("{}_{:04}", name, frame),
+        },
+    };
+    template
+        .with_file_name(numbered)
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_numbers_replace_the_last_hashes() {
+        let path = |template: &str, frame| frame_path(Path::new(template), frame);
+        assert_eq!(path("out/shot_####.png", 7), "out/shot_0007.png");
+        assert_eq!(path("out/a#b_##.png", 3), "out/a#b_03.png");
+        assert_eq!(path("out/#.exr", 12345), "out/12345.exr");
+        // The directories keep their hashes.
+        assert_eq!(path("take#2/frame.jpg", 42), "take#2/frame_0042.jpg");
+        assert_eq!(path("frame", 1), "frame_0001");
+    }
+}
@@ -0,0 +1,207 @@
+use crate::color::Color;
+use std::sync::OnceLock;
+
+// Range of wavelengths traced in spectral mode, in nanometers
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+// Wavelength of the sodium D line, where refractive indices are usually quoted
+const LAMBDA_D: f64 = 589.3;
+
+// Wavelengths traced together along one path. The first is the hero wavelength, the
+// others are spread evenly from it, so every sample covers the whole spectrum. A `Color`
+// on such a path holds the spectral values at these three wavelengths instead of RGB.
+#[derive(Copy, Clone)]
+pub struct Wavelengths {
+    lambda: [f64; 3],
+    pdf: [f64; 3],
+}
+
+impl Wavelengths {
+    // Sample wavelengths where the eye is most sensitive more often.
+    pub fn sample(u: f64) -> Self {
+        let mut lambda = [0.0; 3];
+        let mut pdf = [0.0; 3];
+        for i in 0..3 {
+            let u = (u + i as f64 / 3.0).fract();
+            lambda[i] = sample_visible(u);
+            pdf[i] = visible_pdf(lambda[i]);
+        }
+        Self { lambda, pdf }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    // Whether only the hero wavelength is left, see `terminate_secondary`.
+    pub fn is_single(&self) -> bool {
+        self.lambda[1] == self.lambda[0] && self.lambda[2] == self.lambda[0]
+    }
+
+    // Keep only the hero wavelength, for when the path is about to take a direction that
+    // depends on wavelength. Returns the weights that drop the other wavelengths and make
+    // the hero count for all three.
+    pub fn terminate_secondary(&mut self) -> Color {
+        self.lambda = [self.lambda[0]; 3];
+        Color::new(3.0, 0.0, 0.0)
+    }
+
+    // Spectral values at these wavelengths of a smooth spectrum matching `rgb`.
+    pub fn uplift(&self, rgb: &Color) -> Color {
+        let value = |lambda: f64| {
+            let (r, g, b) = bands(lambda);
+            rgb.x * r + rgb.y * g + rgb.z * b
+        };
+        Color::new(
+            value(self.lambda[0]),
+            value(self.lambda[1]),
+            value(self.lambda[2]),
+        )
+    }
+
+    // Convert the radiance carried at these wavelengths back to linear sRGB. The estimate is
+    // unbiased over many samples, and an uplifted color converts back to itself exactly.
+    pub fn to_rgb(self, radiance: &Color) -> Color {
+        let radiance = [radiance.x, radiance.y, radiance.z];
+        let mut xyz = [0.0; 3];
+        for ((lambda, pdf), radiance) in self.lambda.iter().zip(self.pdf).zip(radiance) {
+            if pdf <= 0.0 {
+                continue;
+            }
+            let weight = radiance / (3.0 * pdf);
+            let (x, y, z) = cie_xyz(*lambda);
+            xyz[0] += x * weight;
+            xyz[1] += y * weight;
+            xyz[2] += z * weight;
+        }
+        let rgb = mul(&film_matrix().xyz_to_rgb, &xyz);
+        Color::new(rgb[0], rgb[1], rgb[2])
+    }
+}
+
+// Index of refraction, either constant or varying with wavelength so that the material
+// disperses light.
+#[derive(Copy, Clone)]
+pub enum Ior {
+    Constant(f64),
+    // n = a + b / λ², with λ in micrometers
+    Cauchy { a: f64, b: f64 },
+    // n² = 1 + Σ b λ² / (λ² - c), with λ in micrometers and c in square micrometers
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Ior {
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+
+    // The index at a wavelength in nanometers, or at the sodium D line when rendering RGB.
+    pub fn at(&self, wavelength: Option<f64>) -> f64 {
+        let micrometers = wavelength.unwrap_or(LAMBDA_D) / 1000.0;
+        let l2 = micrometers * micrometers;
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => {
+                let sum: f64 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1.0 + sum).max(1.0).sqrt()
+            }
+        }
+    }
+}
+
+// Density proportional to the eye's sensitivity, from pbrt's `SampleVisibleWavelengths`.
+fn sample_visible(u: f64) -> f64 {
+    538.0 - 138.888889 * (0.85691062 - 1.82750197 * u).atanh()
+}
+
+fn visible_pdf(lambda: f64) -> f64 {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+        return 0.0;
+    }
+    0.0039398042 / (0.0072 * (lambda - 538.0)).cosh().powi(2)
+}
+
+// The reflectance spectra of pure red, green and blue. They sum to one everywhere, so gray
+// uplifts to a flat spectrum and no reflectance ever exceeds its largest channel.
+fn bands(lambda: f64) -> (f64, f64, f64) {
+    let red = 1.0 / (1.0 + (-(lambda - 595.0) / 12.0).exp());
+    let blue = 1.0 / (1.0 + (-(490.0 - lambda) / 12.0).exp());
+    (red, 1.0 - red - blue, blue)
+}
+
+// The CIE 1931 standard observer, using the multi-lobe Gaussian fit of Wyman, Sloan and
+// Shirley, "Simple Analytic Approximations to the CIE XYZ Color Matching Functions".
+fn cie_xyz(lambda: f64) -> (f64, f64, f64) {
+    let g = |mu: f64, sigma1: f64, sigma2: f64| {
+        let t = (lambda - mu) / if lambda < mu { sigma1 } else { sigma2 };
+        (-0.5 * t * t).exp()
+    };
+    let x =
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2);
+    let y = 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1);
+    let z = 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8);
+    (x, y, z)
+}
+
+struct FilmMatrix {
+    xyz_to_rgb: [[f64; 3]; 3],
+}
+
+// Maps integrated XYZ to linear sRGB. Converting with the standard matrix alone would leave
+// uplifted colors slightly desaturated and flat spectra tinted, so the result is corrected
+// by the inverse of what the round trip does to the three bands.
+fn film_matrix() -> &'static FilmMatrix {
+    static MATRIX: OnceLock<FilmMatrix> = OnceLock::new();
+    MATRIX.get_or_init(|| {
+        const XYZ_TO_SRGB: [[f64; 3]; 3] = [
+            [3.2404542, -1.5371385, -0.4985314],
+            [-0.9692660, 1.8760108, 0.0415560],
+            [0.0556434, -0.2040259, 1.0572252],
+        ];
+        let mut y_integral = 0.0;
+        let mut band_xyz = [[0.0; 3]; 3];
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            let (x, y, z) = cie_xyz(lambda);
+            let (r, g, b) = bands(lambda);
+            y_integral += y;
+            for (row, cmf) in band_xyz.iter_mut().zip([x, y, z]) {
+                row[0] += cmf * r;
+                row[1] += cmf * g;
+                row[2] += cmf * b;
+            }
+            lambda += 1.0;
+        }
+        let xyz_to_rgb = scale(&XYZ_TO_SRGB, 1.0 / y_integral);
+        let round_trip = mat_mul(&xyz_to_rgb, &band_xyz);
+        FilmMatrix {
+            xyz_to_rgb: mat_mul(&inverse(&round_trip), &xyz_to_rgb),
+        }
+    })
+}
+
+type Matrix3 = [[f64; 3]; 3];
+
+fn mul(m: &Matrix3, v: &[f64; 3]) -> [f64; 3] {
+    [0, 1, 2].map(|i| m[i][0] * v[0] + m[i][1] * v[1] + m[i][2] * v[2])
+}
+
+fn mat_mul(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    [0, 1, 2].map(|i| [0, 1, 2].map(|j| (0..3).map(|k| a[i][k] * b[k][j]).sum()))
+}
+
+fn scale(m: &Matrix3, s: f64) -> Matrix3 {
+    m.map(|row| row.map(|v| v * s))
+}
+
+fn inverse(m: &Matrix3) -> Matrix3 {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let det: f64 = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum();
+    // The inverse is the transposed cofactor matrix over the determinant.
+    [0, 1, 2].map(|i| [0, 1, 2].map(|j| cofactor(j, i) / det))
+}
@@ -4,8 +4,9 @@ use crate::interval::Interval;
 use crate::material::Material;
 use crate::onb::Onb;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene_file::{array, DescribeResult, ObjectDesc, SceneWriter};
 use crate::vec3::{dot, Point3, Vec3};
-use rand::{thread_rng, Rng};
 use std::sync::Arc;
 
 pub struct Sphere {
@@ -49,8 +50,10 @@ impl Sphere {
             bbox,
         }
     }
+    // The center moves from `center` at time 0 to `center2` at time 1 and stays at those
+    // ends outside that range, so it never leaves the bounding box.
     pub(crate) fn sphere_center(&self, time: f64) -> Point3 {
-        self.center + self.center_vec * time
+        self.center + self.center_vec * time.clamp(0.0, 1.0)
     }
 }
 
@@ -84,13 +87,25 @@ impl Hittable for Sphere {
 
         let t: f64 = root;
         let p: Point3 = r.at(t);
-        let outward_normal: Vec3 = (p - self.center) / self.radius;
+        let outward_normal: Vec3 = (p - center) / self.radius;
 
         let theta = f64::acos(-outward_normal.y);
         let phi = f64::atan2(-outward_normal.z, outward_normal.x) + std::f64::consts::PI;
         let u = phi / (2.0 * std::f64::consts::PI);
         let v = theta / std::f64::consts::PI;
-        let rec: HitRecord = HitRecord::new(&p, t, &outward_normal, r, self.mat.clone(), u, v);
+        let mut rec: HitRecord = HitRecord::new(&p, t, &outward_normal, r, self.mat.clone(), u, v);
+
+        // Derivatives of the (u, v) parameterization above; dpdv vanishes at the poles.
+        let (x, y, z) = (outward_normal.x, outward_normal.y, outward_normal.z);
+        let sin_theta = f64::sin(theta);
+        let dpdu = Vec3::new(z, 0.0, -x) * (2.0 * std::f64::consts::PI * self.radius);
+        let dpdv = if sin_theta > 1e-8 {
+            Vec3::new(-y * x / sin_theta, sin_theta, -y * z / sin_theta)
+                * (std::f64::consts::PI * self.radius)
+        } else {
+            Vec3::black()
+        };
+        rec.set_derivatives(&dpdu, &dpdv);
         Some(rec)
     }
 
@@ -98,13 +113,14 @@ impl Hittable for Sphere {
         self.bbox.clone()
     }
 
-    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
         if let Some(_rec) = self.hit(
-            &Ray::new(origin, direction, 0.0),
+            &Ray::new(origin, direction, time),
             Interval::new(0.001, f64::INFINITY),
         ) {
+            let center = self.sphere_center(time);
             let cos_theta_max =
-                (1.0 - self.radius * self.radius / (self.center - *origin).length_squared()).sqrt();
+                (1.0 - self.radius * self.radius / (center - *origin).length_squared()).sqrt();
             let solid_angle = 2.0 * std::f64::consts::PI * (1.0 - cos_theta_max);
             1.0 / solid_angle
         } else {
@@ -112,17 +128,29 @@ impl Hittable for Sphere {
         }
     }
 
-    fn random(&self, origin: &Point3) -> Vec3 {
-        let dir = self.center - *origin;
+    fn random(&self, origin: &Point3, time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        let dir = self.sphere_center(time) - *origin;
         let dis_squared = dir.length_squared();
         let uvw = Onb::new(&dir);
-        uvw.local(&random_to_sphere(self.radius, dis_squared))
+        uvw.local(&random_to_sphere(self.radius, dis_squared, sampler))
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<ObjectDesc> {
+        Ok(ObjectDesc::Sphere {
+            center: array(&self.center),
+            radius: self.radius,
+            material: writer.material(&self.mat)?,
+            center2: if self.is_moving {
+                Some(array(&self.sphere_center(1.0)))
+            } else {
+                None
+            },
+        })
     }
 }
 
-fn random_to_sphere(radius: f64, dis_squared: f64) -> Vec3 {
-    let r1 = thread_rng().gen_range(0.0..1.0);
-    let r2 = thread_rng().gen_range(0.0..1.0);
+fn random_to_sphere(radius: f64, dis_squared: f64, sampler: &mut dyn Sampler) -> Vec3 {
+    let (r1, r2) = sampler.get_2d();
     let z = 1.0 + r2 * ((1.0 - radius * radius / dis_squared).sqrt() - 1.0);
 
     let phi = 2.0 * r1 * std::f64::consts::PI;
@@ -0,0 +1,142 @@
+use crate::hittable_list::HittableList;
+use crate::material::Material;
+use crate::mesh::VertexMesh;
+use crate::vec3::Point3;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+// Load an STL model, ASCII or binary, as one mesh. STL repeats every vertex for each
+// facet, so equal positions are merged so that `smooth` normals can be shared between
+// neighbouring facets. The facet normals in the file are not used.
+pub fn read_stl(
+    path: &Path,
+    scale: f64,
+    smooth: bool,
+    material: Option<Arc<dyn Material>>,
+) -> Result<HittableList, String> {
+    let error = |message: &dyn std::fmt::Display| format!("{}: {}", path.display(), message);
+    let data = std::fs::read(path).map_err(|err| error(&err))?;
+    let corners = parse(&data).map_err(|err| error(&err))?;
+    if corners.is_empty() {
+        return Err(error(&"no facets"));
+    }
+
+    let mut mesh = VertexMesh::default();
+    let mut indices = HashMap::new();
+    for facet in corners.chunks_exact(3) {
+        let triangle = [0, 1, 2].map(|k| {
+            let p = facet[k];
+            // Adding zero turns -0 into 0, which has other bits.
+            *indices
+                .entry(p.map(|c| (c + 0.0).to_bits()))
+                .or_insert_with(|| {
+                    let [x, y, z] = p.map(|c| c as f64 * scale);
+                    mesh.positions.push(Point3::new(x, y, z));
+                    mesh.positions.len() as u32 - 1
+                })
+        });
+        mesh.triangles.push(triangle);
+    }
+    if smooth {
+        mesh.smooth_normals();
+    }
+    let mesh = mesh.into_mesh(material).map_err(|err| error(&err))?;
+    Ok(HittableList::new_from(Arc::new(mesh)))
+}
+
+// The corners of all the facets, three by three.
+fn parse(data: &[u8]) -> Result<Vec<[f32; 3]>, String> {
+    // A binary file is an 80 byte header, a facet count and 50 bytes per facet. ASCII
+    // files start with `solid`, but so do the headers of some binary ones, so the size
+    // decides.
+    if data.len() >= 84 {
+        let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+        if data.len() == 84 + 50 * count {
+            let mut corners = Vec::with_capacity(3 * count);
+            for facet in data[84..].chunks_exact(50) {
+                // Skip the normal, then read three corners of three floats each.
+                for corner in facet[12..48].chunks_exact(12) {
+                    let float = |i: usize| {
+                        f32::from_le_bytes([corner[i], corner[i + 1], corner[i + 2], corner[i + 3]])
+                    };
+                    corners.push([float(0), float(4), float(8)]);
+                }
+            }
+            return Ok(corners);
+        }
+    }
+
+    let text = String::from_utf8_lossy(data);
+    let mut words = text.split_whitespace();
+    if words.next() != Some("solid") {
+        return Err(String::from("not an STL file"));
+    }
+    let mut corners = vec![];
+    while let Some(word) = words.next() {
+        if word != "vertex" {
+            continue;
+        }
+        let mut corner = [0.0; 3];
+        for c in corner.iter_mut() {
+            let word = words.next().ok_or("unexpected end of file")?;
+            *c = word
+                .parse()
+                .map_err(|_| format!("`{}` is not a number", word))?;
+        }
+        corners.push(corner);
+    }
+    if corners.len() % 3 != 0 {
+        return Err(String::from("a facet does not have three vertices"));
+    }
+    Ok(corners)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CORNERS: [[f32; 3]; 6] = [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 1.0, -2.5],
+    ];
+
+    #[test]
+    fn ascii() {
+        let mut text = String::from("solid square\n");
+        for facet in CORNERS.chunks(3) {
+            text += "facet normal 0 0 1\nouter loop\n";
+            for [x, y, z] in facet {
+                text += &format!("vertex {} {} {}\n", x, y, z);
+            }
+            text += "endloop\nendfacet\n";
+        }
+        text += "endsolid square\n";
+        assert_eq!(parse(text.as_bytes()).unwrap(), CORNERS);
+    }
+
+    #[test]
+    fn binary() {
+        // A header starting with `solid`, as some exporters write
+        let mut data = b"solid".to_vec();
+        data.resize(80, b' ');
+        data.extend(2u32.to_le_bytes());
+        for facet in CORNERS.chunks(3) {
+            data.extend([0.0f32, 0.0, 1.0].iter().flat_map(|c| c.to_le_bytes()));
+            data.extend(facet.iter().flatten().flat_map(|c| c.to_le_bytes()));
+            data.extend([0, 0]);
+        }
+        assert_eq!(parse(&data).unwrap(), CORNERS);
+    }
+
+    #[test]
+    fn incomplete_facet() {
+        let text = "solid\nvertex 0 0 0\nvertex 1 0 0\nendsolid\n";
+        let err = parse(text.as_bytes()).err().unwrap();
+        assert_eq!(err, "a facet does not have three vertices");
+    }
+}
@@ -2,11 +2,16 @@ use crate::color::Color;
 use crate::interval::Interval;
 use crate::perlin::Perlin;
 use crate::rtw_stb_image::RTWImage;
+use crate::scene_file::{array, cannot_describe, DescribeResult, SceneWriter, TextureDesc};
 use crate::vec3::Point3;
+use image::DynamicImage;
 use std::sync::Arc;
 
 pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+    fn describe(&self, _writer: &mut SceneWriter) -> DescribeResult<TextureDesc> {
+        cannot_describe(std::any::type_name::<Self>())
+    }
 }
 
 #[derive(Clone)]
@@ -27,6 +32,11 @@ impl Texture for SolidColor {
     fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
         self.albedo
     }
+    fn describe(&self, _writer: &mut SceneWriter) -> DescribeResult<TextureDesc> {
+        Ok(TextureDesc::Solid {
+            color: array(&self.albedo),
+        })
+    }
 }
 
 #[derive(Clone)]
@@ -37,7 +47,7 @@ pub struct CheckerTexture {
 }
 
 impl CheckerTexture {
-    pub fn _new(scale: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
+    pub fn new(scale: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
         Self {
             inv_scale: 1.0 / scale,
             even,
@@ -67,16 +77,62 @@ impl Texture for CheckerTexture {
             self.odd.value(u, v, p)
         }
     }
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<TextureDesc> {
+        Ok(TextureDesc::Checker {
+            scale: 1.0 / self.inv_scale,
+            even: writer.texture(&self.even)?,
+            odd: writer.texture(&self.odd)?,
+        })
+    }
 }
 
 pub struct ImageTexture {
+    // None for images that were never files of their own, which cannot be written out
+    file_name: Option<String>,
     image: RTWImage,
+    // Whether the pixels are plain data, such as normals or heights, rather than colors
+    linear: bool,
+    // Tile the image outside [0, 1] instead of stretching its edges
+    repeat: bool,
 }
 
 impl ImageTexture {
     pub fn new(file_name: &str) -> Self {
         Self {
+            file_name: Some(String::from(file_name)),
             image: RTWImage::new(file_name),
+            linear: false,
+            repeat: false,
+        }
+    }
+    // An image whose values are used as they are, without undoing the color gamma.
+    pub fn linear(file_name: &str) -> Self {
+        Self {
+            linear: true,
+            ..Self::new(file_name)
+        }
+    }
+    // Like `new` or `linear`, but None if the file cannot be read.
+    pub fn open(file_name: &str, linear: bool) -> Option<Self> {
+        Some(Self {
+            file_name: Some(String::from(file_name)),
+            image: RTWImage::open(file_name)?,
+            linear,
+            repeat: false,
+        })
+    }
+    pub fn from_image(image: DynamicImage, linear: bool) -> Self {
+        Self {
+            file_name: None,
+            image: RTWImage::from_image(image),
+            linear,
+            repeat: false,
+        }
+    }
+    pub fn repeating(self) -> Self {
+        Self {
+            repeat: true,
+            ..self
         }
     }
 }
@@ -87,6 +143,11 @@ impl Texture for ImageTexture {
             return Color::new(0.0, 1.0, 1.0);
         };
 
+        let (u, v) = if self.repeat {
+            (u.rem_euclid(1.0), v.rem_euclid(1.0))
+        } else {
+            (u, v)
+        };
         let u = Interval::new(0.0, 1.0).clamp(u);
         let v = 1.0 - Interval::new(0.0, 1.0).clamp(v);
 
@@ -95,15 +156,128 @@ impl Texture for ImageTexture {
         let pixel = self.image.pixel_data(i, j);
         let color_scale = 1.0 / 255.0;
 
+        if self.linear {
+            return Color::new(
+                color_scale * pixel[0] as f64,
+                color_scale * pixel[1] as f64,
+                color_scale * pixel[2] as f64,
+            );
+        }
         Color::new(
             gamma_to_linear(color_scale * pixel[0] as f64),
             gamma_to_linear(color_scale * pixel[1] as f64),
             gamma_to_linear(color_scale * pixel[2] as f64),
         )
     }
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<TextureDesc> {
+        match &self.file_name {
+            // The file that was read, wherever the scene is written
+            Some(file) => Ok(TextureDesc::Image {
+                file: self
+                    .image
+                    .path()
+                    .map(|path| writer.file_name(path))
+                    .unwrap_or_else(|| file.clone()),
+                linear: self.linear,
+                repeat: self.repeat,
+            }),
+            None => Err(String::from(
+                "an embedded image cannot be written to a scene file",
+            )),
+        }
+    }
+}
+
+// Another texture multiplied by a color.
+pub struct ScaledTexture {
+    texture: Arc<dyn Texture>,
+    scale: Color,
+}
+
+impl ScaledTexture {
+    pub fn new(texture: Arc<dyn Texture>, scale: &Color) -> Self {
+        Self {
+            texture,
+            scale: *scale,
+        }
+    }
 }
 
-fn gamma_to_linear(linear: f64) -> f64 {
+impl Texture for ScaledTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.texture.value(u, v, p) * self.scale
+    }
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<TextureDesc> {
+        Ok(TextureDesc::Scale {
+            texture: writer.texture(&self.texture)?,
+            scale: array(&self.scale),
+        })
+    }
+}
+
+// One channel of another texture as a gray level, for maps that pack several parameters
+// into one image.
+pub struct ChannelTexture {
+    texture: Arc<dyn Texture>,
+    channel: usize,
+}
+
+impl ChannelTexture {
+    pub fn new(texture: Arc<dyn Texture>, channel: usize) -> Self {
+        Self { texture, channel }
+    }
+}
+
+impl Texture for ChannelTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let value = self.texture.value(u, v, p)[self.channel];
+        Color::new(value, value, value)
+    }
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<TextureDesc> {
+        Ok(TextureDesc::Channel {
+            texture: writer.texture(&self.texture)?,
+            channel: self.channel,
+        })
+    }
+}
+
+// Colors given at the vertices of a mesh, blended across its faces. The mesh tells the
+// texture where it was hit through texture coordinates from `face_uvs`, whose integer part
+// picks the face and whose fractions are the weights of the face's vertices.
+pub struct VertexColorTexture {
+    colors: Vec<Color>,
+    faces: Vec<[u32; 3]>,
+}
+
+impl VertexColorTexture {
+    pub fn new(colors: Vec<Color>, faces: Vec<[u32; 3]>) -> Self {
+        Self { colors, faces }
+    }
+    // Texture coordinates of the corners of face `index`. The second weight ends up in
+    // u - 2 * index and the third in v.
+    pub fn face_uvs(index: usize) -> [(f64, f64); 3] {
+        let u = 2.0 * index as f64;
+        [(u, 0.0), (u + 1.0, 0.0), (u, 1.0)]
+    }
+}
+
+impl Texture for VertexColorTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        let index = ((u / 2.0).floor().max(0.0) as usize).min(self.faces.len() - 1);
+        let beta = (u - 2.0 * index as f64).clamp(0.0, 1.0);
+        let gamma = v.clamp(0.0, 1.0 - beta);
+        let [a, b, c] = self.faces[index].map(|i| self.colors[i as usize]);
+        a * (1.0 - beta - gamma) + b * beta + c * gamma
+    }
+    fn describe(&self, _writer: &mut SceneWriter) -> DescribeResult<TextureDesc> {
+        Ok(TextureDesc::VertexColors {
+            colors: self.colors.iter().map(array).collect(),
+            faces: self.faces.clone(),
+        })
+    }
+}
+
+pub fn gamma_to_linear(linear: f64) -> f64 {
     if linear > 0.0 {
         linear * linear
     } else {
@@ -123,10 +297,22 @@ impl NoiseTexture {
             scale: _scale,
         }
     }
+    pub fn with_seed(scale: f64, seed: u64) -> Self {
+        Self {
+            noise: Perlin::with_seed(seed),
+            scale,
+        }
+    }
 }
 
 impl Texture for NoiseTexture {
     fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
         Color::new(0.5, 0.5, 0.5) * (1.0 + (self.scale * p.z + 10.0 * self.noise.turb(p, 7)).sin())
     }
+    fn describe(&self, _writer: &mut SceneWriter) -> DescribeResult<TextureDesc> {
+        Ok(TextureDesc::Noise {
+            scale: self.scale,
+            seed: Some(self.noise.seed()),
+        })
+    }
 }
@@ -0,0 +1,113 @@
+use crate::color::Color;
+use std::str::FromStr;
+
+// Maps scene radiance into [0, 1] before the transfer curve is applied.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Operator {
+    // Hard clip at 1, as the renderer always used to do
+    Clamp,
+    Reinhard,
+    // Narkowicz's fit of the ACES filmic curve
+    Aces,
+    // John Hable's Uncharted 2 filmic curve
+    Hable,
+}
+
+// Encodes display-linear values for an 8 or 16-bit image.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Transfer {
+    Srgb,
+    // Plain square root, the gamma 2 approximation from the books
+    Gamma2,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ToneMapping {
+    pub operator: Operator,
+    pub transfer: Transfer,
+    // Exposure compensation in stops; every stop doubles the radiance.
+    pub exposure: f64,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: Operator::Clamp,
+            transfer: Transfer::Srgb,
+            exposure: 0.0,
+        }
+    }
+}
+
+impl ToneMapping {
+    // Turn a linear radiance value into an encoded display value in [0, 1].
+    pub fn apply(&self, radiance: Color) -> Color {
+        let scale = f64::powf(2.0, self.exposure);
+        let map = |c: f64| {
+            let c = if c.is_nan() { 0.0 } else { c.max(0.0) * scale };
+            let c = match self.operator {
+                Operator::Clamp => c,
+                Operator::Reinhard => c / (1.0 + c),
+                Operator::Aces => aces(c),
+                Operator::Hable => hable(2.0 * c) / hable(HABLE_WHITE),
+            };
+            let c = c.clamp(0.0, 1.0);
+            match self.transfer {
+                Transfer::Srgb => linear_to_srgb(c),
+                Transfer::Gamma2 => f64::sqrt(c),
+            }
+        };
+        Color::new(map(radiance.x), map(radiance.y), map(radiance.z))
+    }
+}
+
+fn aces(x: f64) -> f64 {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    (x * (a * x + b)) / (x * (c * x + d) + e)
+}
+
+// Linear radiance that Hable's curve maps to pure white.
+const HABLE_WHITE: f64 = 11.2;
+
+fn hable(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+fn linear_to_srgb(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+impl FromStr for Operator {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" | "none" => Ok(Operator::Clamp),
+            "reinhard" => Ok(Operator::Reinhard),
+            "aces" => Ok(Operator::Aces),
+            "hable" | "filmic" => Ok(Operator::Hable),
+            _ => Err(format!(
+                "unknown tone mapping `{}`, expected clamp, reinhard, aces or hable",
+                s
+            )),
+        }
+    }
+}
+
+impl FromStr for Transfer {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "srgb" => Ok(Transfer::Srgb),
+            "gamma2" => Ok(Transfer::Gamma2),
+            _ => Err(format!(
+                "unknown transfer curve `{}`, expected srgb or gamma2",
+                s
+            )),
+        }
+    }
+}
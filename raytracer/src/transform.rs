@@ -0,0 +1,153 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::matrix::Matrix4;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene_file::{DescribeResult, ObjectDesc, SceneWriter, TransformOp};
+use crate::vec3::{unit_vector, Point3, Vec3};
+use std::sync::Arc;
+
+// A transform with the inverse and normal matrices that carry rays into an object's space
+// and hits back out.
+#[derive(Copy, Clone)]
+pub(crate) struct Frame {
+    pub(crate) matrix: Matrix4,
+    inverse: Matrix4,
+    normal_matrix: Matrix4,
+}
+
+impl Frame {
+    pub(crate) fn new(matrix: &Matrix4) -> Result<Self, String> {
+        if !matrix.is_affine() {
+            return Err(String::from("the last row of the matrix must be 0 0 0 1"));
+        }
+        let inverse = matrix
+            .inverse()
+            .ok_or("the transform flattens the object")?;
+        Ok(Self {
+            matrix: *matrix,
+            inverse,
+            normal_matrix: matrix.normal_matrix(),
+        })
+    }
+
+    pub(crate) fn hit(&self, object: &dyn Hittable, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        // The direction is not normalized, so distances along the ray stay the same in
+        // both spaces.
+        let local_r = r.transformed(
+            &self.inverse.point(&r.origin()),
+            &self.inverse.vector(&r.direction()),
+        );
+
+        let mut rec = object.hit(&local_r, ray_t)?;
+        rec.p = self.matrix.point(&rec.p);
+        rec.geometric_normal = unit_vector(&self.normal_matrix.vector(&rec.geometric_normal));
+        rec.dpdu = self.matrix.vector(&rec.dpdu);
+        rec.dpdv = self.matrix.vector(&rec.dpdv);
+        // Scaling can shear the shading frame, so it is rebuilt around the new normal.
+        let normal = unit_vector(&self.normal_matrix.vector(&rec.normal));
+        rec.set_shading_normal(&normal);
+        Some(rec)
+    }
+
+    pub(crate) fn pdf_value(
+        &self,
+        object: &dyn Hittable,
+        origin: &Point3,
+        direction: &Vec3,
+        time: f64,
+    ) -> f64 {
+        // The object's density is over directions in its own space. A unit direction w
+        // maps to B w, where B is the linear part of the inverse, and a small cone of
+        // directions around it changes in solid angle by |det B| / |B w|³.
+        let local_direction = self.inverse.vector(&unit_vector(direction));
+        let pdf = object.pdf_value(&self.inverse.point(origin), &local_direction, time);
+        pdf * self.inverse.determinant().abs() / local_direction.length().powi(3)
+    }
+
+    pub(crate) fn random(
+        &self,
+        object: &dyn Hittable,
+        origin: &Point3,
+        time: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3 {
+        self.matrix
+            .vector(&object.random(&self.inverse.point(origin), time, sampler))
+    }
+}
+
+// The box around the transformed corners of `bbox`
+pub(crate) fn transformed_bbox(bbox: &Aabb, matrix: &Matrix4) -> Aabb {
+    let inf = f64::INFINITY;
+    let mut min = Point3::new(inf, inf, inf);
+    let mut max = Point3::new(-inf, -inf, -inf);
+    for corner in corners(bbox) {
+        let p = matrix.point(&corner);
+        for c in 0..3 {
+            min[c] = f64::min(min[c], p[c]);
+            max[c] = f64::max(max[c], p[c]);
+        }
+    }
+    Aabb::two_point(&min, &max)
+}
+
+pub(crate) fn corners(bbox: &Aabb) -> [Point3; 8] {
+    [0, 1, 2, 3, 4, 5, 6, 7].map(|i| {
+        Point3::new(
+            if i & 1 == 0 { bbox.x.min } else { bbox.x.max },
+            if i & 2 == 0 { bbox.y.min } else { bbox.y.max },
+            if i & 4 == 0 { bbox.z.min } else { bbox.z.max },
+        )
+    })
+}
+
+// An object moved by any affine transform: translation, rotation about any axis, scaling,
+// shearing and mirroring.
+pub struct Transform {
+    object: Arc<dyn Hittable>,
+    frame: Frame,
+    bbox: Aabb,
+}
+
+impl Transform {
+    pub fn new(object: Arc<dyn Hittable>, matrix: &Matrix4) -> Result<Self, String> {
+        let frame = Frame::new(matrix)?;
+        let bbox = transformed_bbox(&object.bounding_box(), matrix);
+        Ok(Self {
+            object,
+            frame,
+            bbox,
+        })
+    }
+}
+
+impl Hittable for Transform {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        self.frame.hit(self.object.as_ref(), r, ray_t)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox.clone()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        self.frame
+            .pdf_value(self.object.as_ref(), origin, direction, time)
+    }
+
+    fn random(&self, origin: &Point3, time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        self.frame
+            .random(self.object.as_ref(), origin, time, sampler)
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<ObjectDesc> {
+        Ok(ObjectDesc::Transform {
+            transform: vec![TransformOp::Matrix {
+                rows: self.frame.matrix.m,
+            }],
+            object: Box::new(writer.object(self.object.as_ref())?),
+        })
+    }
+}
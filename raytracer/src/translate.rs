@@ -2,6 +2,7 @@ use crate::aabb::{add, Aabb};
 use crate::hittable::{HitRecord, Hittable};
 use crate::interval::Interval;
 use crate::ray::Ray;
+use crate::scene_file::{array, DescribeResult, ObjectDesc, SceneWriter};
 use crate::vec3::{Point3, Vec3};
 use std::sync::Arc;
 
@@ -24,7 +25,7 @@ impl Translate {
 
 impl Hittable for Translate {
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        let offset_r = Ray::new(&(r.origin() - self.offset), &r.direction(), r.time());
+        let offset_r = r.transformed(&(r.origin() - self.offset), &r.direction());
 
         if let Some(mut rec) = self.object.hit(&offset_r, ray_t) {
             rec.p += self.offset;
@@ -36,6 +37,13 @@ impl Hittable for Translate {
     fn bounding_box(&self) -> Aabb {
         self.bbox.clone()
     }
+
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<ObjectDesc> {
+        Ok(ObjectDesc::Translate {
+            offset: array(&self.offset),
+            object: Box::new(writer.object(self.object.as_ref())?),
+        })
+    }
 }
 
 pub struct RotateY {
@@ -86,6 +94,17 @@ impl RotateY {
     }
 }
 
+impl RotateY {
+    // Rotate a vector from object space back into world space.
+    fn rotate_back(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            v.x * self.cos_theta + v.z * self.sin_theta,
+            v.y,
+            v.x * -self.sin_theta + v.z * self.cos_theta,
+        )
+    }
+}
+
 impl Hittable for RotateY {
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
         let mut ori = r.origin();
@@ -95,19 +114,17 @@ impl Hittable for RotateY {
         ori[2] = r.origin()[0] * self.sin_theta + r.origin()[2] * self.cos_theta;
         dir[0] = r.direction()[0] * self.cos_theta - r.direction()[2] * self.sin_theta;
         dir[2] = r.direction()[0] * self.sin_theta + r.direction()[2] * self.cos_theta;
-        let rotated_r = Ray::new(&ori, &dir, r.time());
+        let rotated_r = r.transformed(&ori, &dir);
 
         if let Some(mut rec) = self.object.hit(&rotated_r, ray_t) {
-            let mut p = rec.p;
-            p[0] = rec.p[0] * self.cos_theta + rec.p[2] * self.sin_theta;
-            p[2] = rec.p[0] * -self.sin_theta + rec.p[2] * self.cos_theta;
-
-            let mut normal = rec.normal;
-            normal[0] = rec.normal[0] * self.cos_theta + rec.normal[2] * self.sin_theta;
-            normal[2] = rec.normal[0] * -self.sin_theta + rec.normal[2] * self.cos_theta;
-
-            rec.p = p;
-            rec.normal = normal;
+            rec.p = self.rotate_back(&rec.p);
+            // The whole shading frame turns with the object.
+            rec.normal = self.rotate_back(&rec.normal);
+            rec.geometric_normal = self.rotate_back(&rec.geometric_normal);
+            rec.tangent = self.rotate_back(&rec.tangent);
+            rec.bitangent = self.rotate_back(&rec.bitangent);
+            rec.dpdu = self.rotate_back(&rec.dpdu);
+            rec.dpdv = self.rotate_back(&rec.dpdv);
             return Some(rec);
         }
         None
@@ -116,4 +133,11 @@ impl Hittable for RotateY {
     fn bounding_box(&self) -> Aabb {
         self.bbox.clone()
     }
+
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<ObjectDesc> {
+        Ok(ObjectDesc::RotateY {
+            angle: self.sin_theta.atan2(self.cos_theta).to_degrees(),
+            object: Box::new(writer.object(self.object.as_ref())?),
+        })
+    }
 }
@@ -3,8 +3,9 @@ use crate::hittable::{HitRecord, Hittable};
 use crate::interval::Interval;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene_file::{array, DescribeResult, ObjectDesc, SceneWriter};
 use crate::vec3::{cross, dot, unit_vector, Point3, Vec3};
-use rand::{thread_rng, Rng};
 use std::sync::Arc;
 
 pub struct Triangle {
@@ -66,7 +67,7 @@ impl Hittable for Triangle {
         if !range.contains(alpha) || !range.contains(beta) || !range.contains(alpha + beta) {
             return None;
         }
-        let rec = HitRecord::new(
+        let mut rec = HitRecord::new(
             &intersection,
             t,
             &self.normal,
@@ -75,6 +76,7 @@ impl Hittable for Triangle {
             alpha,
             beta,
         );
+        rec.set_derivatives(&self.u, &self.v);
         Some(rec)
     }
 
@@ -82,9 +84,9 @@ impl Hittable for Triangle {
         self.bbox.clone()
     }
 
-    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
         if let Some(rec) = self.hit(
-            &Ray::new(origin, direction, 0.0),
+            &Ray::new(origin, direction, time),
             Interval::new(0.001, f64::INFINITY),
         ) {
             let distance_squared = rec.t * rec.t * direction.length_squared();
@@ -96,10 +98,136 @@ impl Hittable for Triangle {
         }
     }
 
-    fn random(&self, origin: &Point3) -> Vec3 {
-        let a = thread_rng().gen_range(0.0..1.0);
-        let b = thread_rng().gen_range(0.0..1.0 - a);
+    fn random(&self, origin: &Point3, _time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        // Uniform over the triangle's area, to match `pdf_value`.
+        let (r1, r2) = sampler.get_2d();
+        let a = 1.0 - r1.sqrt();
+        let b = r2 * r1.sqrt();
         let p = self.q + self.u * a + self.v * b;
         p - *origin
     }
+
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<ObjectDesc> {
+        Ok(ObjectDesc::Triangle {
+            a: array(&self.q),
+            b: array(&(self.q + self.u)),
+            c: array(&(self.q + self.v)),
+            material: writer.material(&self.mat)?,
+            normals: None,
+            uvs: None,
+        })
+    }
+}
+
+// A triangle from a mesh, shaded smoothly with normals and texture coordinates given at
+// its vertices. Either may be missing, in which case the triangle's own are used.
+pub struct MeshTriangle {
+    triangle: Triangle,
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[(f64, f64); 3]>,
+    // Derivatives of the position with respect to the texture coordinates
+    dpdu: Vec3,
+    dpdv: Vec3,
+}
+
+impl MeshTriangle {
+    pub fn new(
+        vertices: &[Point3; 3],
+        normals: Option<[Vec3; 3]>,
+        uvs: Option<[(f64, f64); 3]>,
+        mat: Arc<dyn Material>,
+    ) -> Self {
+        let triangle = Triangle::new(&vertices[0], &vertices[1], &vertices[2], mat);
+        let (dpdu, dpdv) = uv_derivatives(&triangle.u, &triangle.v, uvs);
+        Self {
+            triangle,
+            normals,
+            uvs,
+            dpdu,
+            dpdv,
+        }
+    }
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let mut rec = self.triangle.hit(r, ray_t)?;
+        apply_vertex_data(
+            &mut rec,
+            &self.triangle.normal,
+            (&self.dpdu, &self.dpdv),
+            self.normals,
+            self.uvs,
+        );
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.triangle.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        self.triangle.pdf_value(origin, direction, time)
+    }
+
+    fn random(&self, origin: &Point3, time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        self.triangle.random(origin, time, sampler)
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> DescribeResult<ObjectDesc> {
+        let t = &self.triangle;
+        Ok(ObjectDesc::Triangle {
+            a: array(&t.q),
+            b: array(&(t.q + t.u)),
+            c: array(&(t.q + t.v)),
+            material: writer.material(&t.mat)?,
+            normals: self.normals.map(|n| n.map(|n| array(&n))),
+            uvs: self.uvs.map(|uv| uv.map(|(u, v)| [u, v])),
+        })
+    }
+}
+
+// The directions in which the texture coordinates increase, for a triangle with edges
+// `e1` and `e2` from its first vertex. Without usable coordinates they are the edges.
+pub fn uv_derivatives(e1: &Vec3, e2: &Vec3, uvs: Option<[(f64, f64); 3]>) -> (Vec3, Vec3) {
+    if let Some(uv) = uvs {
+        let (du1, dv1) = (uv[1].0 - uv[0].0, uv[1].1 - uv[0].1);
+        let (du2, dv2) = (uv[2].0 - uv[0].0, uv[2].1 - uv[0].1);
+        let det = du1 * dv2 - dv1 * du2;
+        if det.abs() > 1e-12 {
+            return ((*e1 * dv2 - *e2 * dv1) / det, (*e2 * du1 - *e1 * du2) / det);
+        }
+    }
+    (*e1, *e2)
+}
+
+// Interpolate vertex data over a triangle hit whose `u` and `v` still hold the weights of
+// the second and third vertex. `face_normal` is the unit normal given by the winding.
+pub fn apply_vertex_data(
+    rec: &mut HitRecord,
+    face_normal: &Vec3,
+    (dpdu, dpdv): (&Vec3, &Vec3),
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[(f64, f64); 3]>,
+) {
+    let weights = [1.0 - rec.u - rec.v, rec.u, rec.v];
+
+    if let Some(uv) = uvs {
+        rec.u = weights[0] * uv[0].0 + weights[1] * uv[1].0 + weights[2] * uv[2].0;
+        rec.v = weights[0] * uv[0].1 + weights[1] * uv[1].1 + weights[2] * uv[2].1;
+    }
+    rec.set_derivatives(dpdu, dpdv);
+
+    if let Some(n) = normals {
+        let shading = n[0] * weights[0] + n[1] * weights[1] + n[2] * weights[2];
+        if shading.length_squared() > 0.0 {
+            let shading = unit_vector(&shading);
+            // The vertex normals decide which side is the outside, whatever the winding.
+            if dot(&shading, face_normal) < 0.0 {
+                rec.front_face = !rec.front_face;
+            }
+            let side = if rec.front_face { 1.0 } else { -1.0 };
+            rec.set_shading_normal(&(shading * side));
+        }
+    }
 }
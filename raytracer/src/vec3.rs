@@ -1,4 +1,5 @@
-use rand::{thread_rng, Rng};
+use crate::rtweekend::{random_double, random_double_range};
+use crate::sampler::Sampler;
 use std::f64;
 use std::ops::{
     Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign,
@@ -34,19 +35,13 @@ impl Vec3 {
         self.length_squared().sqrt()
     }
     pub fn random() -> Vec3 {
-        let mut rng = rand::thread_rng();
-        Vec3::new(
-            rng.gen_range(0.0..1.0),
-            rng.gen_range(0.0..1.0),
-            rng.gen_range(0.0..1.0),
-        )
+        Vec3::new(random_double(), random_double(), random_double())
     }
     pub fn random_in(min: f64, max: f64) -> Vec3 {
-        let mut rng = rand::thread_rng();
         Vec3::new(
-            rng.gen_range(min..max),
-            rng.gen_range(min..max),
-            rng.gen_range(min..max),
+            random_double_range(min, max),
+            random_double_range(min, max),
+            random_double_range(min, max),
         )
     }
     pub fn _near_zero(&self) -> bool {
@@ -68,7 +63,7 @@ pub fn cross(u: &Vec3, v: &Vec3) -> Vec3 {
 pub fn unit_vector(v: &Vec3) -> Vec3 {
     *v / v.length()
 }
-pub fn random_in_unit_sphere() -> Vec3 {
+pub fn _random_in_unit_sphere() -> Vec3 {
     loop {
         let p = Vec3::random_in(-1.0, 1.0);
         if p.length_squared() < 1.0 {
@@ -76,20 +71,23 @@ pub fn random_in_unit_sphere() -> Vec3 {
         }
     }
 }
-pub fn random_unit_vector() -> Vec3 {
-    unit_vector(&random_in_unit_sphere())
+pub fn random_unit_vector(sampler: &mut dyn Sampler) -> Vec3 {
+    let (r1, r2) = sampler.get_2d();
+    let z = 1.0 - 2.0 * r2;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * r1 * std::f64::consts::PI;
+    Vec3::new(phi.cos() * r, phi.sin() * r, z)
 }
-pub fn _random_on_hemisphere(normal: &Vec3) -> Vec3 {
-    let on_unit_sphere = random_unit_vector();
+pub fn _random_on_hemisphere(normal: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+    let on_unit_sphere = random_unit_vector(sampler);
     if dot(&on_unit_sphere, normal) > 0.0 {
         on_unit_sphere
     } else {
         -on_unit_sphere
     }
 }
-pub fn random_cosine_direction() -> Vec3 {
-    let r1: f64 = thread_rng().gen_range(0.0..1.0);
-    let r2: f64 = thread_rng().gen_range(0.0..1.0);
+pub fn random_cosine_direction(sampler: &mut dyn Sampler) -> Vec3 {
+    let (r1, r2) = sampler.get_2d();
 
     let phi = 2.0 * r1 * std::f64::consts::PI;
     Vec3 {
@@ -98,14 +96,11 @@ pub fn random_cosine_direction() -> Vec3 {
         z: (1.0 - r2).sqrt(),
     }
 }
-pub fn random_in_unit_disk() -> Vec3 {
-    loop {
-        let mut rng = rand::thread_rng();
-        let p = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
-        if p.length_squared() < 1.0 {
-            return p;
-        }
-    }
+pub fn random_in_unit_disk(sampler: &mut dyn Sampler) -> Vec3 {
+    let (r1, r2) = sampler.get_2d();
+    let r = r2.sqrt();
+    let phi = 2.0 * r1 * std::f64::consts::PI;
+    Vec3::new(phi.cos() * r, phi.sin() * r, 0.0)
 }
 pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
     *v - *n * 2.0 * dot(v, n)